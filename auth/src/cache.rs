@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::profile::{GameProfile, Property};
+
+const MOJANG_NAMES: &str = "https://api.mojang.com/users/profiles/minecraft/{}";
+const MOJANG_PROFILES: &str = "https://sessionserver.mojang.com/session/minecraft/profile/{}?unsigned=false";
+
+/// The URLs used to resolve profiles that are not cached. Each URL contains
+/// a `{}` placeholder, replaced with the player name or the undashed UUID.
+///
+/// The defaults point at Mojang, but any service answering in the same
+/// format can stand in for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileEndpoint {
+    pub names: String,
+    pub profiles: String,
+}
+
+impl Default for ProfileEndpoint {
+    fn default() -> Self {
+        Self {
+            names: MOJANG_NAMES.to_string(),
+            profiles: MOJANG_PROFILES.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NameLookup {
+    id: Uuid,
+}
+
+/// An entry of the cache file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    name: String,
+    uuid: Uuid,
+    #[serde(default)]
+    properties: Vec<Property>,
+    /// Seconds since the unix epoch after which the entry is stale
+    expires_on: u64,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_on <= now
+    }
+
+    fn to_profile(&self) -> GameProfile {
+        GameProfile {
+            name: self.name.clone(),
+            id: self.uuid,
+            properties: self.properties.clone(),
+        }
+    }
+}
+
+/// A disk-backed cache of game profiles, in the spirit of vanilla's
+/// `usercache.json`. Profiles can be looked up by name or UUID and expire
+/// after a configurable time to live.
+///
+/// Saving blocks on the file system, so async code should run it on a
+/// blocking thread, using [ProfileCache::request_save] to merge the saves
+/// requested while one is waiting.
+pub struct ProfileCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: RwLock<HashMap<Uuid, CacheEntry>>,
+    // Whether a save was requested and has not started yet
+    save_pending: AtomicBool,
+    // Held while writing, so saves never write the file at the same time
+    save_lock: Mutex<()>,
+}
+
+impl ProfileCache {
+    /// Loads the cache stored at `path`. A missing file yields an empty cache.
+    pub fn load(path: &Path, ttl: Duration) -> Result<Self> {
+        let entries = if path.exists() {
+            let json = fs::read_to_string(path)?;
+            let entries: Vec<CacheEntry> = serde_json::from_str(&json)?;
+            entries.into_iter().map(|entry| (entry.uuid, entry)).collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            ttl,
+            entries: RwLock::new(entries),
+            save_pending: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    /// Marks a save as pending. Returns `false` if one already was, in
+    /// which case it will write the current entries and no other save is
+    /// needed.
+    pub fn request_save(&self) -> bool {
+        !self.save_pending.swap(true, Ordering::SeqCst)
+    }
+
+    /// Writes every non-expired entry back to the cache file.
+    pub fn save(&self) -> Result<()> {
+        let _writing = self.save_lock.lock().unwrap();
+        self.save_pending.store(false, Ordering::SeqCst);
+        let now = now();
+        let entries: Vec<CacheEntry> = self.entries.read().unwrap()
+            .values()
+            .filter(|entry| !entry.is_expired(now))
+            .cloned()
+            .collect();
+        let json = serde_json::to_string_pretty(&entries)?;
+        fs::write(&self.path, json)
+    }
+
+    pub fn get_by_id(&self, id: &Uuid) -> Option<GameProfile> {
+        let entries = self.entries.read().unwrap();
+        entries.get(id)
+            .filter(|entry| !entry.is_expired(now()))
+            .map(CacheEntry::to_profile)
+    }
+
+    /// Looks up a profile by name, ignoring case
+    pub fn get_by_name(&self, name: &str) -> Option<GameProfile> {
+        let now = now();
+        let entries = self.entries.read().unwrap();
+        entries.values()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name) && !entry.is_expired(now))
+            .map(CacheEntry::to_profile)
+    }
+
+    /// Caches the profile, replacing any entry with the same UUID or name.
+    pub fn insert(&self, profile: &GameProfile) {
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|_, entry| !entry.name.eq_ignore_ascii_case(&profile.name));
+        entries.insert(profile.id, CacheEntry {
+            name: profile.name.clone(),
+            uuid: profile.id,
+            properties: profile.properties.clone(),
+            expires_on: now() + self.ttl.as_secs(),
+        });
+    }
+
+    /// Removes every expired entry
+    pub fn evict_expired(&self) {
+        let now = now();
+        self.entries.write().unwrap().retain(|_, entry| !entry.is_expired(now));
+    }
+
+    /// Returns the cached profile of the given player, or resolves it
+    /// through the endpoint and caches it. Returns `None` if no player
+    /// with that name exists.
+    pub async fn fetch(&self, endpoint: &ProfileEndpoint, name: &str) -> reqwest::Result<Option<GameProfile>> {
+        if let Some(profile) = self.get_by_name(name) {
+            return Ok(Some(profile));
+        }
+        let response = reqwest::get(endpoint.names.replace("{}", name)).await?;
+        match response.status() {
+            reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::NOT_FOUND => return Ok(None),
+            _ => {}
+        }
+        let lookup = response.error_for_status()?.json::<NameLookup>().await?;
+        let profile = reqwest::get(endpoint.profiles.replace("{}", &lookup.id.simple().to_string())).await?
+            .error_for_status()?
+            .json::<GameProfile>()
+            .await?;
+        self.insert(&profile);
+        Ok(Some(profile))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use crate::cache::ProfileCache;
+    use crate::profile::GameProfile;
+
+    fn cache_file(directory: &TempDir) -> PathBuf {
+        directory.path().join("usercache.json")
+    }
+
+    fn profile(name: &str, id: u128) -> GameProfile {
+        GameProfile {
+            name: name.to_string(),
+            id: Uuid::from_u128(id),
+            properties: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_lookup_by_name() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ProfileCache::load(&cache_file(&directory), Duration::from_secs(3600)).unwrap();
+        cache.insert(&profile("Notch", 1));
+        assert_eq!(cache.get_by_name("notch").map(|profile| profile.id), Some(Uuid::from_u128(1)));
+        assert_eq!(cache.get_by_id(&Uuid::from_u128(1)).map(|profile| profile.name), Some("Notch".to_string()));
+        assert!(cache.get_by_name("jeb_").is_none());
+
+        // The name now belongs to another player
+        cache.insert(&profile("notch", 2));
+        assert_eq!(cache.get_by_name("Notch").map(|profile| profile.id), Some(Uuid::from_u128(2)));
+        assert!(cache.get_by_id(&Uuid::from_u128(1)).is_none());
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = cache_file(&directory);
+        let cache = ProfileCache::load(&path, Duration::ZERO).unwrap();
+        cache.insert(&profile("Notch", 1));
+        assert!(cache.get_by_name("Notch").is_none());
+        assert!(cache.get_by_id(&Uuid::from_u128(1)).is_none());
+
+        fs::write(&path, r#"[
+            {"name": "Notch", "uuid": "00000000-0000-0000-0000-000000000001", "expiresOn": 0},
+            {"name": "jeb_", "uuid": "00000000-0000-0000-0000-000000000002", "expiresOn": 99999999999}
+        ]"#).unwrap();
+        let cache = ProfileCache::load(&path, Duration::from_secs(3600)).unwrap();
+        assert!(cache.get_by_name("Notch").is_none());
+        assert!(cache.get_by_name("jeb_").is_some());
+
+        // Expired entries are not saved
+        cache.save().unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("Notch"));
+        assert!(saved.contains("jeb_"));
+    }
+
+    #[test]
+    fn test_save_requests_are_merged() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = cache_file(&directory);
+        let cache = ProfileCache::load(&path, Duration::from_secs(3600)).unwrap();
+        assert!(cache.request_save());
+        assert!(!cache.request_save());
+        cache.insert(&profile("Notch", 1));
+        cache.save().unwrap();
+        assert!(cache.request_save());
+
+        let reloaded = ProfileCache::load(&path, Duration::from_secs(3600)).unwrap();
+        assert!(reloaded.get_by_name("Notch").is_some());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod profile;
+pub mod cache;
 
 use num_bigint::BigInt;
 use sha1::{Digest, Sha1};
@@ -16,16 +17,17 @@ pub fn generate_server_hash(der_key: &Vec<u8>, key: &[u8]) -> String {
     format!("{:x}", bigint)
 }
 
-pub async fn authenticate(player: &str, hex: &str, ip: &str) -> GameProfile {
-    let response = reqwest::get(format!(
+/// Asks the session server whether the player has joined using the given
+/// server hash. Fails if the request fails or the player is not authenticated.
+pub async fn authenticate(player: &str, hex: &str, ip: &str) -> reqwest::Result<GameProfile> {
+    reqwest::get(format!(
         "{}?username={}&serverId={}&ip={}",
         HAS_JOINED,
         player,
         hex,
         ip,
-    )).await;
-    if response.is_err() {
-        panic!("couldn't authenticate player {} using Mojang.", player);
-    }
-    response.unwrap().json::<GameProfile>().await.unwrap()
+    )).await?
+        .error_for_status()?
+        .json::<GameProfile>()
+        .await
 }
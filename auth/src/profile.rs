@@ -7,13 +7,24 @@ use protocol::fields::io_ext::{PacketWriterExt, PacketReaderExt};
 use uuid::Uuid;
 use protocol::fields::generic::KnownOption;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameProfile {
     pub name: String,
     pub id: Uuid,
     pub properties: Vec<Property>,
 }
 
+/// The minimum and maximum length of a player name
+pub const MIN_NAME_LENGTH: usize = 3;
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// Tests whether the name is a valid player name: 3 to 16 characters,
+/// consisting only of ASCII letters, digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    (MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
 impl GameProfile {
     pub fn new(name: String, id: Uuid) -> Self {
         Self { name, id, properties: vec![] }
@@ -41,7 +52,7 @@ impl PacketField for GameProfile {
     fn read_field<R: Read>(input: &mut R) -> std::io::Result<Self> where Self: Sized {
         let id: Uuid = input.read_field()?;
         let name = input.read_utf8()?;
-        if name.len() > 16 {
+        if name.len() > MAX_NAME_LENGTH {
             return Err(Error::new(ErrorKind::InvalidData, "username cannot be longer than 16 characters."));
         }
         let properties = input.read_field()?;
@@ -60,7 +71,7 @@ impl PacketField for GameProfile {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Property {
     pub name: String,
     pub value: String,
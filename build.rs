@@ -0,0 +1,239 @@
+//! Generates the block state registry from the `blocks.json` report of
+//! vanilla's data generator, checked in under `codecs/<version>`.
+//!
+//! The report is produced by running the vanilla server with
+//! `java -DbundlerMainClass=net.minecraft.data.Main -jar server.jar --reports`.
+//!
+//! The light of each state comes from `block_light.json` next to it, which
+//! maps block keys to `{"emission": e, "opacity": o}`. Each value is either
+//! a number for every state of the block, or an array with one number per
+//! state, ordered by id. It is not part of the reports, and is extracted
+//! from the game's block definitions.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+/// The version whose blocks are generated
+const VERSION: &str = "1.19.4";
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let blocks = read_json(&format!("codecs/{}/blocks.json", VERSION));
+    let light = read_json(&format!("codecs/{}/block_light.json", VERSION));
+    let code = generate_blocks(&blocks, &light);
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("blocks.rs");
+    fs::write(out, code).expect("failed to write the generated blocks");
+}
+
+fn read_json(path: &str) -> Map<String, Value> {
+    println!("cargo:rerun-if-changed={}", path);
+    let json = fs::read_to_string(path).unwrap_or_else(|why| panic!("failed to read {}: {}", path, why));
+    serde_json::from_str(&json).unwrap_or_else(|why| panic!("failed to parse {}: {}", path, why))
+}
+
+struct BlockData {
+    key: String,
+    variant: String,
+    min_state: u64,
+    max_state: u64,
+    default_state: u64,
+    // In the order of the report, which is the order state ids vary in
+    properties: Vec<(String, Vec<String>)>,
+}
+
+fn generate_blocks(blocks: &Map<String, Value>, light: &Map<String, Value>) -> String {
+    let mut data: Vec<BlockData> = blocks.iter().map(|(key, block)| parse_block(key, block)).collect();
+    data.sort_by_key(|block| block.min_state);
+
+    let mut names = BTreeSet::new();
+    let mut values = BTreeSet::new();
+    for block in &data {
+        for (name, block_values) in &block.properties {
+            names.insert(name.clone());
+            values.extend(block_values.iter().cloned());
+        }
+    }
+
+    let mut code = String::new();
+    writeln!(code, "// Generated by build.rs from codecs/{}/blocks.json. Do not edit.", VERSION).unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "/// The number of block states").unwrap();
+    writeln!(code, "pub const STATE_COUNT: u16 = {};", data.last().map_or(0, |block| block.max_state + 1)).unwrap();
+    writeln!(code).unwrap();
+
+    write_string_enum(&mut code, "PropName", "The name of a block state property", &names);
+    write_string_enum(&mut code, "PropValue", "A value of a block state property", &values);
+
+    writeln!(code, "#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]").unwrap();
+    // Vanilla names blocks like grass_block and bamboo_block
+    writeln!(code, "#[allow(clippy::enum_variant_names)]").unwrap();
+    writeln!(code, "pub enum Block {{").unwrap();
+    for block in &data {
+        writeln!(code, "    {},", block.variant).unwrap();
+    }
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+
+    writeln!(code, "impl Block {{").unwrap();
+    writeln!(code, "    /// Every block, ordered by state id").unwrap();
+    writeln!(code, "    pub const ALL: [Block; {}] = [", data.len()).unwrap();
+    for block in &data {
+        writeln!(code, "        Block::{},", block.variant).unwrap();
+    }
+    writeln!(code, "    ];").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub fn from_key(key: &str) -> Option<Block> {{").unwrap();
+    writeln!(code, "        match key {{").unwrap();
+    for block in &data {
+        writeln!(code, "            {:?} => Some(Block::{}),", block.key, block.variant).unwrap();
+    }
+    writeln!(code, "            _ => None").unwrap();
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub(crate) fn info(&self) -> &'static BlockInfo {{").unwrap();
+    writeln!(code, "        &BLOCK_INFO[*self as usize]").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+
+    writeln!(code, "static BLOCK_INFO: [BlockInfo; {}] = [", data.len()).unwrap();
+    for block in &data {
+        let properties: Vec<String> = block.properties.iter()
+            .map(|(name, values)| {
+                let values: Vec<String> = values.iter().map(|value| format!("PropValue::{}", value_variant(value))).collect();
+                format!("(PropName::{}, &[{}])", to_pascal_case(name), values.join(", "))
+            })
+            .collect();
+        writeln!(
+            code,
+            "    BlockInfo {{ key: {:?}, min_state: {}, max_state: {}, default_state: {}, properties: &[{}] }},",
+            block.key, block.min_state, block.max_state, block.default_state, properties.join(", ")
+        ).unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    writeln!(code).unwrap();
+
+    writeln!(code, "/// The block light each state emits, by state id").unwrap();
+    write_state_values(&mut code, "LIGHT_EMISSION", &light_values(&data, light, "emission"));
+    writeln!(code, "/// The light levels lost entering each state, by state id").unwrap();
+    write_state_values(&mut code, "LIGHT_OPACITY", &light_values(&data, light, "opacity"));
+    code
+}
+
+/// One of the light values of every state, ordered by id
+fn light_values(data: &[BlockData], light: &Map<String, Value>, name: &str) -> Vec<u8> {
+    let mut values = Vec::new();
+    for block in data {
+        let value = light.get(&block.key)
+            .and_then(|light| light.get(name))
+            .unwrap_or_else(|| panic!("missing the light {} of {}", name, block.key));
+        let count = (block.max_state - block.min_state + 1) as usize;
+        let parse = |value: &Value| value.as_u64()
+            .filter(|value| *value <= 15)
+            .unwrap_or_else(|| panic!("the light {} of {} is not a light level", name, block.key)) as u8;
+        match value.as_array() {
+            Some(states) => {
+                assert_eq!(states.len(), count, "the light {} of {} does not match its states", name, block.key);
+                values.extend(states.iter().map(parse));
+            }
+            None => values.resize(values.len() + count, parse(value)),
+        }
+    }
+    values
+}
+
+fn write_state_values(code: &mut String, name: &str, values: &[u8]) {
+    writeln!(code, "static {}: [u8; {}] = [", name, values.len()).unwrap();
+    for line in values.chunks(32) {
+        let line: Vec<String> = line.iter().map(u8::to_string).collect();
+        writeln!(code, "    {},", line.join(", ")).unwrap();
+    }
+    writeln!(code, "];").unwrap();
+    writeln!(code).unwrap();
+}
+
+fn parse_block(key: &str, block: &Value) -> BlockData {
+    let states = block["states"].as_array().expect("a block has states");
+    let ids: Vec<u64> = states.iter().map(|state| state["id"].as_u64().expect("a state has an id")).collect();
+    let default_state = states.iter()
+        .find(|state| state["default"].as_bool().unwrap_or(false))
+        .map_or(ids[0], |state| state["id"].as_u64().unwrap());
+    let properties = block.get("properties")
+        .and_then(Value::as_object)
+        .map(|properties| properties.iter()
+            .map(|(name, values)| {
+                let values = values.as_array().expect("property values are an array")
+                    .iter()
+                    .map(|value| value.as_str().expect("property values are strings").to_string())
+                    .collect();
+                (name.clone(), values)
+            })
+            .collect())
+        .unwrap_or_default();
+    let name = key.strip_prefix("minecraft:").unwrap_or(key);
+    BlockData {
+        key: key.to_string(),
+        variant: to_pascal_case(name),
+        min_state: *ids.iter().min().unwrap(),
+        max_state: *ids.iter().max().unwrap(),
+        default_state,
+        properties,
+    }
+}
+
+fn write_string_enum(code: &mut String, name: &str, doc: &str, values: &BTreeSet<String>) {
+    writeln!(code, "/// {}", doc).unwrap();
+    writeln!(code, "#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(code, "pub enum {} {{", name).unwrap();
+    for value in values {
+        writeln!(code, "    {},", value_variant(value)).unwrap();
+    }
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "impl {} {{", name).unwrap();
+    writeln!(code, "    pub fn name(&self) -> &'static str {{").unwrap();
+    writeln!(code, "        match self {{").unwrap();
+    for value in values {
+        writeln!(code, "            {}::{} => {:?},", name, value_variant(value), value).unwrap();
+    }
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub fn from_name(name: &str) -> Option<{}> {{", name).unwrap();
+    writeln!(code, "        match name {{").unwrap();
+    for value in values {
+        writeln!(code, "            {:?} => Some({}::{}),", value, name, value_variant(value)).unwrap();
+    }
+    writeln!(code, "            _ => None").unwrap();
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+}
+
+/// Numeric values such as `age=3` become `N3`
+fn value_variant(value: &str) -> String {
+    if value.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("N{}", value)
+    } else {
+        to_pascal_case(value)
+    }
+}
+
+fn to_pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            first.to_string() + chars.as_str()
+        })
+        .collect()
+}
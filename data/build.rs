@@ -0,0 +1,169 @@
+//! Generates the item, entity type and sound event registries of each
+//! supported version from the files checked in under `codecs/<version>`:
+//!
+//! - `registries.json` is the registries report of vanilla's data generator
+//!   (`java -DbundlerMainClass=net.minecraft.data.Main -jar server.jar --reports`),
+//!   and gives the numeric id of every entry.
+//! - `items.json` maps item keys to `{"max_stack_size": n}`.
+//! - `entities.json` maps entity type keys to `{"width": w, "height": h}`.
+//!
+//! The last two are not part of the reports, and are extracted from the
+//! game's item and entity type definitions.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+/// The versions whose registries are generated
+const VERSIONS: [&str; 1] = ["1.19.4"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).to_path_buf();
+    for version in VERSIONS {
+        let directory = Path::new("../codecs").join(version);
+        let registries = read_json(&directory.join("registries.json"));
+        let items = read_json(&directory.join("items.json"));
+        let entities = read_json(&directory.join("entities.json"));
+
+        let module = version.replace('.', "_");
+        fs::write(out.join(format!("items_{}.rs", module)), generate_items(&registries, &items))
+            .expect("failed to write the generated items");
+        fs::write(out.join(format!("entities_{}.rs", module)), generate_entities(&registries, &entities))
+            .expect("failed to write the generated entity types");
+        fs::write(out.join(format!("sounds_{}.rs", module)), generate_sounds(&registries))
+            .expect("failed to write the generated sound events");
+    }
+}
+
+fn read_json(path: &Path) -> Map<String, Value> {
+    println!("cargo:rerun-if-changed={}", path.display());
+    let json = fs::read_to_string(path).unwrap_or_else(|why| panic!("failed to read {}: {}", path.display(), why));
+    serde_json::from_str(&json).unwrap_or_else(|why| panic!("failed to parse {}: {}", path.display(), why))
+}
+
+/// The keys of the registry's entries, ordered by id
+fn entries(registries: &Map<String, Value>, registry: &str) -> Vec<String> {
+    let entries = registries[registry]["entries"].as_object()
+        .unwrap_or_else(|| panic!("the report has no {} registry", registry));
+    let mut entries: Vec<(u64, String)> = entries.iter()
+        .map(|(key, entry)| (entry["protocol_id"].as_u64().expect("an entry has a protocol id"), key.clone()))
+        .collect();
+    entries.sort();
+    for (index, (id, key)) in entries.iter().enumerate() {
+        assert_eq!(*id, index as u64, "the ids of {} are not contiguous at {}", registry, key);
+    }
+    entries.into_iter().map(|(_, key)| key).collect()
+}
+
+fn generate_items(registries: &Map<String, Value>, items: &Map<String, Value>) -> String {
+    let entries = entries(registries, "minecraft:item");
+    let mut code = registry_enum("Item", "An item", &entries);
+    writeln!(code, "impl Item {{").unwrap();
+    writeln!(code, "    pub fn max_stack_size(&self) -> u8 {{").unwrap();
+    writeln!(code, "        match self {{").unwrap();
+    for key in &entries {
+        let size = items.get(key)
+            .and_then(|item| item["max_stack_size"].as_u64())
+            .unwrap_or_else(|| panic!("missing the max stack size of {}", key));
+        writeln!(code, "            Item::{} => {},", variant(key), size).unwrap();
+    }
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+fn generate_entities(registries: &Map<String, Value>, entities: &Map<String, Value>) -> String {
+    let entries = entries(registries, "minecraft:entity_type");
+    let mut code = registry_enum("EntityType", "A type of entity", &entries);
+    writeln!(code, "impl EntityType {{").unwrap();
+    writeln!(code, "    /// The width and height of the hitbox, in blocks").unwrap();
+    writeln!(code, "    pub fn dimensions(&self) -> (f32, f32) {{").unwrap();
+    writeln!(code, "        match self {{").unwrap();
+    for key in &entries {
+        let entity = entities.get(key).unwrap_or_else(|| panic!("missing the dimensions of {}", key));
+        let width = entity["width"].as_f64().expect("the width is a number");
+        let height = entity["height"].as_f64().expect("the height is a number");
+        writeln!(code, "            EntityType::{} => ({:?}_f32, {:?}_f32),", variant(key), width as f32, height as f32).unwrap();
+    }
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    code
+}
+
+fn generate_sounds(registries: &Map<String, Value>) -> String {
+    registry_enum("SoundEvent", "A sound event", &entries(registries, "minecraft:sound_event"))
+}
+
+/// Generates the enum of a registry, with conversions to and from ids and keys
+fn registry_enum(name: &str, doc: &str, entries: &[String]) -> String {
+    let mut code = String::new();
+    writeln!(code, "// Generated by data/build.rs. Do not edit.").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "/// {}", doc).unwrap();
+    writeln!(code, "#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]").unwrap();
+    writeln!(code, "pub enum {} {{", name).unwrap();
+    for key in entries {
+        writeln!(code, "    {},", variant(key)).unwrap();
+    }
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "impl {} {{", name).unwrap();
+    writeln!(code, "    /// Every entry, ordered by id").unwrap();
+    writeln!(code, "    pub const ALL: [{}; {}] = [", name, entries.len()).unwrap();
+    for key in entries {
+        writeln!(code, "        {}::{},", name, variant(key)).unwrap();
+    }
+    writeln!(code, "    ];").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub fn id(&self) -> i32 {{").unwrap();
+    writeln!(code, "        *self as i32").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub fn from_id(id: i32) -> Option<{}> {{", name).unwrap();
+    writeln!(code, "        usize::try_from(id).ok().and_then(|id| Self::ALL.get(id)).copied()").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    /// The key without its namespace").unwrap();
+    writeln!(code, "    pub fn name(&self) -> &'static str {{").unwrap();
+    writeln!(code, "        match self {{").unwrap();
+    for key in entries {
+        writeln!(code, "            {}::{} => {:?},", name, variant(key), path(key)).unwrap();
+    }
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code).unwrap();
+    writeln!(code, "    pub fn from_name(name: &str) -> Option<{}> {{", name).unwrap();
+    writeln!(code, "        match name {{").unwrap();
+    for key in entries {
+        writeln!(code, "            {:?} => Some({}::{}),", path(key), name, variant(key)).unwrap();
+    }
+    writeln!(code, "            _ => None").unwrap();
+    writeln!(code, "        }}").unwrap();
+    writeln!(code, "    }}").unwrap();
+    writeln!(code, "}}").unwrap();
+    writeln!(code).unwrap();
+    code
+}
+
+fn path(key: &str) -> &str {
+    key.strip_prefix("minecraft:").expect("vanilla keys are in the minecraft namespace")
+}
+
+/// `minecraft:entity.allay.hurt` becomes `EntityAllayHurt`
+fn variant(key: &str) -> String {
+    path(key)
+        .split(['_', '.', '/'])
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            first.to_string() + chars.as_str()
+        })
+        .collect()
+}
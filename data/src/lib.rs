@@ -0,0 +1,104 @@
+//! Registries generated from the vanilla reports of each supported version.
+//!
+//! Every registry is an enum with conversions to and from numeric ids and
+//! [Key](protocol::fields::key::Key)s. The registries of the latest version
+//! are re-exported at the root.
+
+/// Implements the conversions between a generated registry and keys
+macro_rules! registry_keys {
+    ($($registry:ident),*) => {
+        $(impl $registry {
+            pub fn key(&self) -> Key {
+                Key::minecraft(self.name())
+            }
+
+            /// Returns the entry with the given key. Entries only exist in
+            /// the `minecraft` namespace.
+            pub fn from_key(key: &Key) -> Option<$registry> {
+                if key.namespace() != "minecraft" {
+                    return None;
+                }
+                Self::from_name(key.value())
+            }
+        }
+
+        impl From<$registry> for Key {
+            fn from(entry: $registry) -> Key {
+                entry.key()
+            }
+        }
+
+        impl std::fmt::Display for $registry {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "minecraft:{}", self.name())
+            }
+        })*
+    }
+}
+
+pub mod v1_19_4 {
+    pub mod item {
+        use protocol::fields::key::Key;
+
+        include!(concat!(env!("OUT_DIR"), "/items_1_19_4.rs"));
+
+        registry_keys!(Item);
+    }
+
+    pub mod entity {
+        use protocol::fields::key::Key;
+
+        include!(concat!(env!("OUT_DIR"), "/entities_1_19_4.rs"));
+
+        registry_keys!(EntityType);
+    }
+
+    pub mod sound {
+        use protocol::fields::key::Key;
+
+        include!(concat!(env!("OUT_DIR"), "/sounds_1_19_4.rs"));
+
+        registry_keys!(SoundEvent);
+    }
+}
+
+pub use v1_19_4::*;
+
+#[cfg(test)]
+mod tests {
+    use protocol::fields::key::Key;
+
+    use crate::{entity, item, sound};
+
+    #[test]
+    fn test_conversions() {
+        let stone = item::Item::from_id(1).unwrap();
+        assert_eq!(stone.key(), Key::minecraft("stone"));
+        assert_eq!(item::Item::from_key(&Key::minecraft("stone")), Some(stone));
+        assert_eq!(item::Item::from_key(&Key::new("custom", "stone")), None);
+        assert_eq!(stone.max_stack_size(), 64);
+        assert_eq!(item::Item::from_id(-1), None);
+
+        let arrow = entity::EntityType::from_key(&Key::minecraft("arrow")).unwrap();
+        assert_eq!(arrow.dimensions(), (0.5, 0.5));
+
+        let hurt = sound::SoundEvent::from_key(&Key::minecraft("entity.allay.hurt")).unwrap();
+        assert_eq!(sound::SoundEvent::from_id(hurt.id()), Some(hurt));
+        assert_eq!(hurt.to_string(), "minecraft:entity.allay.hurt");
+    }
+
+    #[test]
+    fn test_reports_are_complete() {
+        assert_eq!(item::Item::ALL.len(), 1228);
+        assert_eq!(entity::EntityType::ALL.len(), 124);
+        assert_eq!(sound::SoundEvent::ALL.len(), 1454);
+
+        assert_eq!(item::Item::from_key(&Key::minecraft("ender_pearl")).map(|item| item.max_stack_size()), Some(16));
+        assert_eq!(item::Item::from_key(&Key::minecraft("diamond_sword")).map(|item| item.max_stack_size()), Some(1));
+        assert_eq!(item::Item::ALL.last().map(|item| item.name()), Some("pottery_shard_skull"));
+
+        let zombie = entity::EntityType::from_key(&Key::minecraft("zombie")).unwrap();
+        assert_eq!(zombie.dimensions(), (0.6, 1.95));
+        assert_eq!(entity::EntityType::ALL.last().map(|entity| entity.name()), Some("fishing_bobber"));
+    }
+}
@@ -108,4 +108,36 @@ pub fn write_compressed_packet<T: Clientbound>(packet: &T, output: &mut impl Wri
     output.write_varint(&data_length)?;
     output.write_all(comrpessed_data.as_slice())?;
     Ok(())
-}
\ No newline at end of file
+}
+/// Serializes the packet ID and data, without the length prefix, so the
+/// packet can be framed later with [write_encoded_packet].
+pub fn encode_packet<T: Clientbound>(packet: &T) -> Result<Vec<u8>> {
+    let mut buf = ByteBuffer::new();
+    buf.write_varint(&VarInt(T::id()))?;
+    packet.write_packet(&mut buf)?;
+    Ok(buf.as_ref().to_vec())
+}
+
+/// Frames a packet serialized by [encode_packet], compressing it if the
+/// threshold is not negative.
+pub fn write_encoded_packet(data: &[u8], output: &mut impl Write, threshold: i32) -> Result<()> {
+    if threshold < 0 {
+        output.write_varint(&VarInt(data.len() as i32))?;
+        output.write_all(data)?;
+        return Ok(());
+    }
+    let data_length = VarInt(data.len() as i32);
+    if data.len() < threshold as usize {
+        output.write_varint(&VarInt(data_length.0 + 1))?;
+        output.write_varint(&VarInt(0))?;
+        output.write_all(data)?;
+        return Ok(());
+    }
+    let mut writer = ZlibEncoder::new(Vec::new(), Compression::default());
+    writer.write_all(data)?;
+    let compressed = writer.finish()?;
+    output.write_varint(&VarInt((data_length.size() + compressed.len()) as i32))?;
+    output.write_varint(&data_length)?;
+    output.write_all(compressed.as_slice())?;
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use std::io::{Error, ErrorKind, Read, Result, Write};
 
 use lazy_static::lazy_static;
@@ -16,7 +17,7 @@ lazy_static! {
 static MINECRAFT: &str = "minecraft";
 
 // TODO: move to core crate
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Key {
     namespace: String,
     value: String,
@@ -89,6 +90,12 @@ impl PacketField for Key {
     }
 }
 
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.value)
+    }
+}
+
 impl From<String> for Key {
     fn from(v: String) -> Self {
         Key::parse(&v).unwrap()
@@ -151,10 +151,20 @@ impl BitSet {
     }
 
     pub fn get(&self, index: usize) -> bool {
-        (self.0[index / 64] & (1 << (index % 64))) != 0
+        match self.0.get(index / 64) {
+            Some(long) => (long & (1 << (index % 64))) != 0,
+            None => false
+        }
     }
 
+    /// Sets the bit at `index`, growing the set if needed
     pub fn set(&mut self, index: usize, value: bool) {
+        if index / 64 >= self.0.len() {
+            if !value {
+                return;
+            }
+            self.0.resize(index / 64 + 1, 0);
+        }
         if value {
             self.0[index / 64] |= 1 << (index % 64)
         } else {
@@ -162,6 +172,10 @@ impl BitSet {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|long| *long == 0)
+    }
+
     pub fn data(&self) -> &Vec<i64> {
         &self.0
     }
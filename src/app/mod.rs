@@ -3,16 +3,20 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use bevy::{app::App, MinimalPlugins, log::LogPlugin, prelude::Events, DefaultPlugins};
-use bevy::app::{PluginGroup, ScheduleRunnerPlugin};
-use bevy::prelude::{apply_system_buffers, Commands, Deref, Res, ResMut, Resource};
+use bevy::app::{AppExit, PluginGroup, ScheduleRunnerPlugin};
+use bevy::log::info;
+use bevy::prelude::{apply_system_buffers, Commands, Deref, DerefMut, EventWriter, Res, ResMut, Resource};
+use bevy::time::{Time, Timer, TimerMode};
 use bytebuffer::ByteBuffer;
+use futures::future::join_all;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::runtime::Runtime;
 use tokio::select;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::sync::oneshot::Sender;
-use crate::client::client::{Client, ProtocolStage};
+use crate::client::client::{Client, ClientMessage, ProtocolStage};
 use crate::config::ServerProperties;
+use crate::dimension::registry::RegistryCodec;
 use crate::encryption::server::ServerEncryption;
 use crate::packets::handler::handshake::receive_handshake;
 use crate::packets::handler::login::receive_login;
@@ -28,7 +32,10 @@ pub fn setup_netherite_app() {
         )
         .add_plugin(LogPlugin::default())
         .add_event::<TokioEvent>()
+        // Startup systems ordered before setup_listener can register custom entries
+        .insert_resource(RegistryCodec::vanilla())
         .add_startup_systems((setup_tokio, apply_system_buffers, setup_listener).chain())
+        .add_systems((tick_worlds, autosave, shutdown).chain())
         .run();
 }
 
@@ -51,18 +58,105 @@ struct TokioEventStream<T>(UnboundedReceiver<T>);
 #[derive(Resource, Deref)]
 struct NetheriteServer(Arc<Server>);
 
+/// Fires every autosave interval. Absent when autosaving is disabled.
+#[derive(Resource, Deref, DerefMut)]
+struct AutosaveTimer(Timer);
+
+/// Receives a message when the process is interrupted or terminated
+#[derive(Resource, Deref, DerefMut)]
+struct ShutdownSignal(UnboundedReceiver<()>);
+
 /// Create a resource that contains the Tokio [Runtime]
 fn setup_tokio(mut commands: Commands) {
     let runtime = Runtime::new().expect("failed to start tokio runtime");
     commands.insert_resource(TokioRuntime(runtime))
 }
 
-fn setup_listener(commands: Commands, runtime: Res<TokioRuntime>) {
+fn setup_listener(mut commands: Commands, runtime: Res<TokioRuntime>, codec: Res<RegistryCodec>) {
+    let directory = Path::new("run").to_path_buf();
+    let properties = ServerProperties::from_file(&directory.join("server.toml"));
+    let autosave_interval = *properties.game().autosave_interval();
+    if autosave_interval > 0 {
+        let timer = Timer::new(Duration::from_secs(autosave_interval), TimerMode::Repeating);
+        commands.insert_resource(AutosaveTimer(timer));
+    }
+    let server = Arc::new(Server::new(properties, directory, codec.clone(), runtime.handle().clone()));
+    commands.insert_resource(NetheriteServer(Arc::clone(&server)));
+    let (interrupted, signal) = unbounded_channel();
     runtime.spawn(async move {
-        let directory = Path::new("run").to_path_buf();
-        let properties = ServerProperties::from_file(&directory.join("server.toml"));
-        start(commands, directory, properties).await;
+        stop_requested().await;
+        let _ = interrupted.send(());
     });
+    commands.insert_resource(ShutdownSignal(signal));
+    runtime.spawn(start(server));
+}
+
+/// Resolves once the process is interrupted, or terminated on unix as
+/// service managers and `docker stop` do. Never resolves for the signals
+/// that cannot be listened to.
+async fn stop_requested() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Applies the changes players sent and runs the systems of every world,
+/// once per app update
+fn tick_worlds(server: Option<Res<NetheriteServer>>) {
+    if let Some(server) = server {
+        server.tick_worlds();
+    }
+}
+
+/// Saves the changes of every world each autosave interval, in the background
+fn autosave(server: Option<Res<NetheriteServer>>, timer: Option<ResMut<AutosaveTimer>>, time: Res<Time>) {
+    let (Some(server), Some(mut timer)) = (server, timer) else {
+        return;
+    };
+    if timer.tick(time.delta()).just_finished() {
+        server.worlds().lock().unwrap().save_all();
+    }
+}
+
+/// Once the process is interrupted or terminated, waits for every world to be saved and
+/// stops the app
+fn shutdown(
+    server: Option<Res<NetheriteServer>>,
+    signal: Option<ResMut<ShutdownSignal>>,
+    runtime: Res<TokioRuntime>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let (Some(server), Some(mut signal)) = (server, signal) else {
+        return;
+    };
+    if signal.try_recv().is_err() {
+        return;
+    }
+    info!("Saving worlds...");
+    let saves = server.worlds().lock().unwrap().save_all();
+    let saved: usize = runtime.block_on(join_all(saves)).into_iter()
+        .filter_map(Result::ok)
+        .sum();
+    info!("Saved {} chunks", saved);
+    exit.send(AppExit);
 }
 
 // fn event_receiver(mut stream: ResMut<TokioEventStream<TokioEvent>>) {
@@ -78,19 +172,14 @@ fn setup_listener(commands: Commands, runtime: Res<TokioRuntime>) {
 //     }
 // }
 
-async fn start(mut commands: Commands, run_directory: PathBuf, properties: ServerProperties) {
+async fn start(server: Arc<Server>) {
+    let properties = server.properties();
     let address = properties.address();
     let listener = TcpListener::bind(address).await.expect(&*format!(
         "failed to bind to port {} because it is already in use.",
         properties.server().port()
     ));
 
-    let server = Server::new(properties, run_directory);
-
-    let server = Arc::new(server);
-
-    commands.insert_resource(NetheriteServer(server.clone()));
-
     loop {
         let (socket, addr) = listener.accept().await.unwrap();
         let server = Arc::clone(&server);
@@ -102,16 +191,18 @@ async fn start(mut commands: Commands, run_directory: PathBuf, properties: Serve
 
 async fn handle_client(server: Arc<Server>, socket: TcpStream, addr: SocketAddr) {
     let (tx, mut rx) = unbounded_channel();
-    {
-        let server = Arc::clone(&server);
-        server.players().player_joined(addr, tx.clone());
-    }
-    let mut client = Client::new(socket, tx.clone());
+    let mut client = Client::new(socket, addr, tx);
     loop {
         select! {
-                Some(packet) = rx.recv() => {
-                    client.write_to_socket(packet.as_ref()).await;
-                }
+                Some(message) = rx.recv() => match message {
+                    ClientMessage::Packet(packet) => {
+                        let _ = client.send_encoded(packet.as_ref()).await;
+                    }
+                    ClientMessage::Disconnect(reason) => {
+                        client.disconnect(reason, server.as_ref()).await;
+                        break;
+                    }
+                },
                 result = client.read_next_packet() => match result {
                     Ok(Some((id,  data))) => {
                         let mut data = ByteBuffer::from(data);
@@ -129,13 +220,22 @@ async fn handle_client(server: Arc<Server>, socket: TcpStream, addr: SocketAddr)
                                 receive_play(id, &mut data, &mut client, Arc::clone(&server)).await;
                             },
                         }
+                        // The packet got the client kicked
+                        if client.is_closed() {
+                            break;
+                        }
+                    },
+                    Ok(None) => {
+                        // Client disconnected. Exit the loop
+                        client.close_connection(server.as_ref()).await;
+                        break;
                     },
-                    Ok(None) => {},
-                    Err(e) => {
-                        // eprintln!("error: {:?}", e);
+                    Err(_) => {
+                        // The connection was reset, or sent data that is not a packet
+                        client.close_connection(server.as_ref()).await;
+                        break;
                     },
                 },
             }
-        // Client disconnected. Exit the loop
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,60 @@
+//! The light emitted and blocked by each block state.
+//!
+//! The blocks report has no light data, so both come from
+//! `codecs/<version>/block_light.json`, checked in next to it and
+//! generated into tables along with the block states.
+
+use crate::block::{BlockState, LIGHT_EMISSION, LIGHT_OPACITY};
+
+/// The highest light level
+pub const MAX_LIGHT: u8 = 15;
+
+impl BlockState {
+    /// The block light level the state emits
+    pub fn light_emission(&self) -> u8 {
+        LIGHT_EMISSION[self.0 as usize]
+    }
+
+    /// How many light levels are lost when light enters the state. Light
+    /// always loses at least one level per block travelled, so opacities of
+    /// 0 and 1 dim it the same. 15 blocks light entirely.
+    pub fn light_opacity(&self) -> u8 {
+        LIGHT_OPACITY[self.0 as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::BlockState;
+
+    fn state(key: &str, properties: &[(&str, &str)]) -> BlockState {
+        let properties = properties.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        BlockState::from_properties(key, &properties).unwrap()
+    }
+
+    #[test]
+    fn test_emission() {
+        assert_eq!(state("minecraft:torch", &[]).light_emission(), 14);
+        assert_eq!(state("minecraft:glowstone", &[]).light_emission(), 15);
+        assert_eq!(state("minecraft:furnace", &[("lit", "false")]).light_emission(), 0);
+        assert_eq!(state("minecraft:furnace", &[("lit", "true")]).light_emission(), 13);
+        assert_eq!(state("minecraft:stone", &[]).light_emission(), 0);
+        assert_eq!(state("minecraft:light", &[("level", "9")]).light_emission(), 9);
+        assert_eq!(state("minecraft:candle", &[("candles", "3"), ("lit", "true")]).light_emission(), 9);
+        assert_eq!(state("minecraft:candle", &[("candles", "3"), ("lit", "false")]).light_emission(), 0);
+    }
+
+    #[test]
+    fn test_opacity() {
+        assert_eq!(BlockState::AIR.light_opacity(), 0);
+        assert_eq!(state("minecraft:stone", &[]).light_opacity(), 15);
+        assert_eq!(state("minecraft:glass", &[]).light_opacity(), 0);
+        assert_eq!(state("minecraft:oak_leaves", &[]).light_opacity(), 1);
+        assert_eq!(state("minecraft:water", &[]).light_opacity(), 1);
+        assert_eq!(state("minecraft:fire_coral_block", &[]).light_opacity(), 15);
+        assert_eq!(state("minecraft:jack_o_lantern", &[]).light_opacity(), 15);
+        assert_eq!(state("minecraft:tinted_glass", &[]).light_opacity(), 15);
+        assert_eq!(state("minecraft:oak_stairs", &[]).light_opacity(), 0);
+        assert_eq!(state("minecraft:oak_stairs", &[("waterlogged", "true")]).light_opacity(), 1);
+    }
+}
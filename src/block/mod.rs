@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+pub mod light;
+
+include!(concat!(env!("OUT_DIR"), "/blocks.rs"));
+
+/// The static data of a block, generated from the blocks report
+pub(crate) struct BlockInfo {
+    key: &'static str,
+    min_state: u16,
+    max_state: u16,
+    default_state: u16,
+    // In the order state ids vary in: the last property varies fastest
+    properties: &'static [(PropName, &'static [PropValue])],
+}
+
+impl Block {
+    /// The namespaced key, e.g. `minecraft:oak_log`
+    pub fn key(&self) -> &'static str {
+        self.info().key
+    }
+
+    pub fn default_state(&self) -> BlockState {
+        BlockState(self.info().default_state)
+    }
+
+    /// The properties of the block, and the values each can have
+    pub fn properties(&self) -> &'static [(PropName, &'static [PropValue])] {
+        self.info().properties
+    }
+
+    /// Every state of the block
+    pub fn states(&self) -> impl Iterator<Item=BlockState> {
+        (self.info().min_state..=self.info().max_state).map(BlockState)
+    }
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.key())
+    }
+}
+
+/// A block with a value for each of its properties, stored as its state id
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockState(u16);
+
+impl BlockState {
+    pub const AIR: BlockState = BlockState(0);
+
+    /// Returns the state with the given id, if it exists
+    pub fn from_id(id: i32) -> Option<BlockState> {
+        if id >= 0 && id < STATE_COUNT as i32 {
+            Some(BlockState(id as u16))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the state of the block with the given properties. Properties
+    /// that are missing take their value from the default state, like vanilla
+    /// does when reading palettes.
+    pub fn from_properties(key: &str, properties: &HashMap<String, String>) -> Option<BlockState> {
+        let mut state = Block::from_key(key)?.default_state();
+        for (name, value) in properties {
+            let name = PropName::from_name(name)?;
+            let value = PropValue::from_name(value)?;
+            state = state.with(name, value)?;
+        }
+        Some(state)
+    }
+
+    pub fn id(&self) -> i32 {
+        self.0 as i32
+    }
+
+    pub fn block(&self) -> Block {
+        let index = Block::ALL.partition_point(|block| block.info().max_state < self.0);
+        Block::ALL[index]
+    }
+
+    /// Returns the value of the property, or `None` if the block doesn't have it
+    pub fn get(&self, name: PropName) -> Option<PropValue> {
+        let info = self.block().info();
+        let mut offset = (self.0 - info.min_state) as usize;
+        for (property, values) in info.properties.iter().rev() {
+            if *property == name {
+                return Some(values[offset % values.len()]);
+            }
+            offset /= values.len();
+        }
+        None
+    }
+
+    /// Returns this state with the property set to the value, or `None` if
+    /// the block doesn't have the property or the value.
+    pub fn with(&self, name: PropName, value: PropValue) -> Option<BlockState> {
+        let info = self.block().info();
+        let mut offset = (self.0 - info.min_state) as usize;
+        let mut stride = 1;
+        for (property, values) in info.properties.iter().rev() {
+            if *property == name {
+                let new_index = values.iter().position(|candidate| *candidate == value)?;
+                let old_index = offset % values.len();
+                let id = self.0 as usize + new_index * stride - old_index * stride;
+                return Some(BlockState(id as u16));
+            }
+            offset /= values.len();
+            stride *= values.len();
+        }
+        None
+    }
+
+    /// Every property of the block and its value in this state
+    pub fn properties(&self) -> Vec<(PropName, PropValue)> {
+        self.block().properties().iter()
+            .filter_map(|(name, _)| self.get(*name).map(|value| (*name, value)))
+            .collect()
+    }
+
+    pub fn is_air(&self) -> bool {
+        matches!(self.block().key(), "minecraft:air" | "minecraft:cave_air" | "minecraft:void_air")
+    }
+}
+
+impl Display for BlockState {
+    /// Formats the state like commands do, e.g. `minecraft:oak_log[axis=y]`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.block().key())?;
+        let properties = self.properties();
+        if properties.is_empty() {
+            return Ok(());
+        }
+        let properties: Vec<String> = properties.iter()
+            .map(|(name, value)| format!("{}={}", name.name(), value.name()))
+            .collect();
+        write!(f, "[{}]", properties.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::block::{Block, BlockState, PropName, PropValue, STATE_COUNT};
+
+    #[test]
+    fn test_report_is_complete() {
+        assert_eq!(Block::ALL.len(), 998);
+        assert_eq!(STATE_COUNT, 23725);
+        for key in ["minecraft:sand", "minecraft:water", "minecraft:bedrock", "minecraft:torch"] {
+            assert!(Block::from_key(key).is_some(), "{} is missing", key);
+        }
+        assert_eq!(Block::Bedrock.default_state().id(), 79);
+        assert_eq!(Block::Water.default_state().id(), 80);
+        assert_eq!(BlockState::from_id(STATE_COUNT as i32 - 1).map(|state| state.block()), Some(Block::DecoratedPot));
+        assert_eq!(BlockState::from_id(STATE_COUNT as i32), None);
+        for block in Block::ALL {
+            assert!(block.states().all(|state| state.block() == block), "{} has foreign states", block);
+        }
+    }
+
+    #[test]
+    fn test_stairs_properties() {
+        let stairs = Block::OakStairs.default_state();
+        assert_eq!(stairs.id(), 2881);
+        assert_eq!(stairs.get(PropName::Facing), Some(PropValue::North));
+        assert_eq!(stairs.get(PropName::Half), Some(PropValue::Bottom));
+        assert_eq!(stairs.get(PropName::Shape), Some(PropValue::Straight));
+        assert_eq!(stairs.get(PropName::Waterlogged), Some(PropValue::False));
+        assert_eq!(stairs.get(PropName::Axis), None);
+
+        let changed = stairs.with(PropName::Facing, PropValue::East)
+            .and_then(|state| state.with(PropName::Half, PropValue::Top))
+            .and_then(|state| state.with(PropName::Shape, PropValue::OuterRight))
+            .and_then(|state| state.with(PropName::Waterlogged, PropValue::True))
+            .unwrap();
+        assert_eq!(changed.id(), 2938);
+        assert_eq!(changed.to_string(), "minecraft:oak_stairs[facing=east,half=top,shape=outer_right,waterlogged=true]");
+        assert_eq!(changed.with(PropName::Shape, PropValue::Straight).and_then(|state| state.get(PropName::Facing)), Some(PropValue::East));
+        assert_eq!(stairs.with(PropName::Facing, PropValue::Up), None);
+        assert_eq!(stairs.with(PropName::Axis, PropValue::X), None);
+
+        for state in Block::OakStairs.states() {
+            let properties: HashMap<String, String> = state.properties().iter()
+                .map(|(name, value)| (name.name().to_string(), value.name().to_string()))
+                .collect();
+            assert_eq!(properties.len(), 4);
+            assert_eq!(BlockState::from_properties("minecraft:oak_stairs", &properties), Some(state));
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
 use std::io::{Cursor, ErrorKind};
+use std::net::SocketAddr;
 
 use anyhow::Result;
 use auth::profile::GameProfile;
 use bytebuffer::ByteBuffer;
 use bytes::{Buf, BytesMut};
 use futures::SinkExt;
-use protocol::codec::{read_packet, write_packet};
+use protocol::codec::{read_packet, write_encoded_packet, write_packet};
 use protocol::{Clientbound, Serverbound};
 use rsa::RsaPublicKey;
 use tokio::io::AsyncWriteExt;
@@ -14,12 +17,18 @@ use tokio::net::TcpSocket;
 use tokio::{io::AsyncReadExt, net::TcpStream};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::codec::{Framed, FramedWrite, Encoder};
+use uuid::Uuid;
+use chat::text_component::TextComponent;
 
+use protocol::fields::key::Key;
 use protocol::fields::numeric::VarInt;
 
 use crate::encryption::client::ClientEncryption;
-use crate::packets::login::SetCompressionPacket;
+use crate::packets::login::{DisconnectLogin, LoginPluginRequest, SetCompressionPacket};
+use crate::packets::play::DisconnectPlay;
 use crate::server::server::Server;
+use crate::world::player::Session;
+use crate::world::worlds::PlayerEvent;
 
 #[derive(Debug)]
 pub enum ProtocolStage {
@@ -29,10 +38,47 @@ pub enum ProtocolStage {
     Play,
 }
 
+/// A message sent to a client's connection task from elsewhere in the server
+#[derive(Debug)]
+pub enum ClientMessage {
+    /// A packet serialized with [protocol::codec::encode_packet]. The
+    /// connection compresses and encrypts it before writing it.
+    Packet(Vec<u8>),
+    /// Disconnect the client with the given reason
+    Disconnect(TextComponent),
+}
+
+/// The steps of the login sequence, in order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoginState {
+    /// Waiting for the client to send `LoginStart`
+    AwaitingStart,
+    /// An `EncryptionRequest` was sent, waiting for the `EncryptionResponse`
+    AwaitingEncryption,
+    /// The player is authenticated, waiting for `LoginPluginResponse`s
+    AwaitingPluginResponses,
+    /// `LoginSuccess` has been sent
+    Done,
+}
+
+impl fmt::Display for LoginState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginState::AwaitingStart => write!(f, "awaiting login start"),
+            LoginState::AwaitingEncryption => write!(f, "awaiting encryption response"),
+            LoginState::AwaitingPluginResponses => write!(f, "awaiting plugin responses"),
+            LoginState::Done => write!(f, "logged in"),
+        }
+    }
+}
+
 pub struct Client {
     // The client's connection socket
     socket: TcpStream,
 
+    // The address of the client
+    address: SocketAddr,
+
     // The compression threshold
     compression_threshold: Option<i32>,
 
@@ -42,34 +88,59 @@ pub struct Client {
     // The current stage the protocol is in
     stage: ProtocolStage,
 
+    // The current step of the login sequence
+    login_state: LoginState,
+
+    // IDs of login plugin requests that have not been answered yet
+    pending_plugin_requests: Vec<i32>,
+
+    // The ID given to the next login plugin request
+    next_plugin_message_id: i32,
+
     // The client's public key
     public_key: Option<RsaPublicKey>,
 
+    // The verify token sent to this client in the EncryptionRequest
+    verify_token: Option<Vec<u8>>,
+
     // The player's name
     player_name: Option<String>,
 
+    // The UUID the client sent in LoginStart, if any
+    claimed_uuid: Option<Uuid>,
+
     // The GameProfile of this client
     profile: Option<GameProfile>,
 
     // Packets sent to the client
-    packets: UnboundedSender<Vec<u8>>,
+    packets: UnboundedSender<ClientMessage>,
 
     // A byte buffer used for sending and receiving data
     buf: BytesMut,
+
+    // Whether the connection was closed, after which packets are ignored
+    closed: bool,
 }
 
 impl Client {
-    pub fn new(socket: TcpStream, packets: UnboundedSender<Vec<u8>>) -> Self {
+    pub fn new(socket: TcpStream, address: SocketAddr, packets: UnboundedSender<ClientMessage>) -> Self {
         Self {
             socket,
+            address,
             compression_threshold: None,
             encryption: None,
             stage: ProtocolStage::Handshake,
+            login_state: LoginState::AwaitingStart,
+            pending_plugin_requests: Vec::new(),
+            next_plugin_message_id: 0,
             public_key: None,
+            verify_token: None,
             player_name: None,
+            claimed_uuid: None,
             profile: None,
             packets,
             buf: BytesMut::with_capacity(1024),
+            closed: false,
         }
     }
 
@@ -81,6 +152,44 @@ impl Client {
         self.stage = stage;
     }
 
+    pub fn login_state(&self) -> LoginState {
+        self.login_state
+    }
+
+    pub fn set_login_state(&mut self, login_state: LoginState) {
+        self.login_state = login_state;
+    }
+
+    /// Sends a login plugin request and records it as pending. The login
+    /// sequence will not finish before every pending request is answered.
+    pub async fn send_login_plugin_request(&mut self, channel: Key, data: Vec<u8>) -> Result<i32> {
+        let message_id = self.next_plugin_message_id;
+        self.next_plugin_message_id += 1;
+        self.send_packet(&LoginPluginRequest {
+            message_id: VarInt(message_id),
+            channel,
+            data,
+        }).await?;
+        self.pending_plugin_requests.push(message_id);
+        Ok(message_id)
+    }
+
+    /// Marks the given plugin request as answered. Returns false if no
+    /// such request is pending.
+    pub fn complete_plugin_request(&mut self, message_id: i32) -> bool {
+        match self.pending_plugin_requests.iter().position(|id| *id == message_id) {
+            Some(index) => {
+                self.pending_plugin_requests.remove(index);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn has_pending_plugin_requests(&self) -> bool {
+        !self.pending_plugin_requests.is_empty()
+    }
+
     pub fn public_key(&self) -> &Option<RsaPublicKey> {
         &self.public_key
     }
@@ -89,6 +198,14 @@ impl Client {
         self.public_key = Some(public_key);
     }
 
+    pub fn verify_token(&self) -> &Option<Vec<u8>> {
+        &self.verify_token
+    }
+
+    pub fn set_verify_token(&mut self, verify_token: Vec<u8>) {
+        self.verify_token = Some(verify_token);
+    }
+
     pub fn compression_threshold(&self) -> Option<i32> {
         self.compression_threshold
     }
@@ -116,15 +233,63 @@ impl Client {
         if let Some(encryptor) = self.encryption.as_mut() {
             encryptor.encrypt(buf.as_mut());
         }
-        self.write_to_socket(buf.as_slice()).await;
+        self.write_to_socket(buf.as_slice()).await?;
         // self.packets.send(buf).unwrap();
         Ok(())
     }
 
+    /// Sends a packet serialized with [protocol::codec::encode_packet]
+    pub async fn send_encoded(&mut self, data: &[u8]) -> Result<()> {
+        let mut buf: Vec<u8> = Vec::new();
+        write_encoded_packet(data, &mut buf, self.compression_threshold.unwrap_or(-1))?;
+
+        if let Some(encryptor) = self.encryption.as_mut() {
+            encryptor.encrypt(buf.as_mut());
+        }
+        self.write_to_socket(buf.as_slice()).await?;
+        Ok(())
+    }
+
+    /// Closes the connection and lets the server know the player left.
+    /// Does nothing if the connection is already closed.
     pub async fn close_connection(&mut self, server: &Server) {
-        self.socket.shutdown().await.unwrap();
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        // The peer may have closed the connection already
+        let _ = self.socket.shutdown().await;
+
+        if let Some(profile) = &self.profile {
+            server.players().player_left(&profile.id, &self.address);
+            let _ = server.player_events().send(PlayerEvent::Leave(Session::new(profile.id, self.address)));
+        }
+    }
+
+    /// Sends the reason to the client with the disconnect packet of its
+    /// stage, then closes the connection.
+    pub async fn disconnect(&mut self, reason: TextComponent, server: &Server) {
+        let _ = match self.stage {
+            ProtocolStage::Login => self.send_packet(&DisconnectLogin { reason }).await,
+            ProtocolStage::Play => self.send_packet(&DisconnectPlay { reason }).await,
+            // Clients can't be told why before they log in
+            ProtocolStage::Handshake | ProtocolStage::Status => Ok(()),
+        };
+        self.close_connection(server).await;
+    }
+
+    /// Whether the connection was closed. The connection task stops reading
+    /// packets once it is.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
 
-        server.players().player_left(self.socket.peer_addr().as_ref().unwrap());
+    pub fn packets(&self) -> &UnboundedSender<ClientMessage> {
+        &self.packets
     }
 
     pub fn profile(&self) -> &Option<GameProfile> {
@@ -135,6 +300,10 @@ impl Client {
         self.profile = Some(profile);
     }
 
+    pub fn take_profile(&mut self) -> Option<GameProfile> {
+        self.profile.take()
+    }
+
     pub async fn read_next_packet(&mut self) -> std::io::Result<Option<(i32, Vec<u8>)>> {
         let mut buf = &mut self.buf;
         match self.socket.read_buf(&mut buf).await {
@@ -156,8 +325,9 @@ impl Client {
         return v;
     }
 
-    pub(crate) async fn write_to_socket(&mut self, data: &[u8]) {
-        self.socket.write_all(data).await.unwrap();
+    /// Fails if the connection was closed
+    pub(crate) async fn write_to_socket(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.socket.write_all(data).await
     }
 
     pub async fn parse_next_packet<T: Serverbound>(&mut self) -> std::io::Result<Option<T>> {
@@ -185,4 +355,12 @@ impl Client {
     pub fn set_player_name(&mut self, player_name: String) {
         self.player_name = Some(player_name);
     }
+
+    pub fn claimed_uuid(&self) -> &Option<Uuid> {
+        &self.claimed_uuid
+    }
+
+    pub fn set_claimed_uuid(&mut self, claimed_uuid: Option<Uuid>) {
+        self.claimed_uuid = claimed_uuid;
+    }
 }
@@ -6,7 +6,12 @@ use base64::Engine;
 use base64::engine::general_purpose;
 use derive_getters::Getters;
 use crate::game_mode::GameMode;
+use crate::generation::GeneratorType;
+use crate::generation::flat::CLASSIC_FLAT;
+use crate::server::player_count::DuplicateLoginPolicy;
 use serde::Deserialize;
+use auth::cache::ProfileEndpoint;
+use protocol::fields::key::Key;
 use chat::style::RgbColor;
 use chat::text_component::TextComponent;
 
@@ -15,6 +20,7 @@ pub struct ServerProperties {
     server: ServerSection,
     game: GameSection,
     status: StatusSection,
+    profiles: ProfileSection,
     icon: String,
     description: TextComponent,
     address: String,
@@ -25,11 +31,12 @@ impl ServerProperties {
         server: ServerSection,
         game: GameSection,
         status: StatusSection,
+        profiles: ProfileSection,
         icon: String,
         description: TextComponent,
         address: String,
     ) -> Self {
-        Self { server, game, status, icon, description, address }
+        Self { server, game, status, profiles, icon, description, address }
     }
 
     pub fn from_file(path: &Path) -> ServerProperties {
@@ -46,6 +53,7 @@ impl ServerProperties {
             server: props.server,
             game: props.game,
             status: props.status,
+            profiles: props.profiles,
             icon,
             description,
             address,
@@ -58,6 +66,8 @@ struct RawServerProps {
     server: ServerSection,
     status: StatusSection,
     game: GameSection,
+    #[serde(default)]
+    profiles: ProfileSection,
 }
 
 #[derive(Deserialize, Getters)]
@@ -68,6 +78,50 @@ pub struct GameSection {
     simulation_disance: u8,
     reduce_debug_info: bool,
     enable_respawn_screen: bool,
+    #[serde(default = "default_chunks_per_tick")]
+    chunks_per_tick: usize,
+    /// The dimension type of the default world
+    #[serde(default = "default_dimension_type")]
+    dimension_type: Key,
+    /// The world folder in the run directory. If it holds a vanilla world,
+    /// the default world is loaded from it.
+    #[serde(default = "default_level_name")]
+    level_name: String,
+    /// Seconds between saves of the loaded worlds, or 0 to only save on shutdown
+    #[serde(default = "default_autosave_interval")]
+    autosave_interval: u64,
+    /// Never save the loaded worlds, so they reset on every restart
+    #[serde(default)]
+    read_only: bool,
+    /// Fills the chunks of the default world that were never saved
+    #[serde(default)]
+    generator: GeneratorType,
+    /// Configures the generator, e.g. the preset of the flat generator
+    #[serde(default = "default_generator_settings")]
+    generator_settings: String,
+    /// The seed of new worlds, like vanilla's `level-seed`. Random if empty.
+    #[serde(default)]
+    seed: String,
+}
+
+fn default_chunks_per_tick() -> usize {
+    16
+}
+
+fn default_generator_settings() -> String {
+    String::from(CLASSIC_FLAT)
+}
+
+fn default_autosave_interval() -> u64 {
+    300
+}
+
+fn default_level_name() -> String {
+    String::from("world")
+}
+
+fn default_dimension_type() -> Key {
+    Key::minecraft("overworld")
 }
 
 #[derive(Deserialize, Getters)]
@@ -77,6 +131,16 @@ pub struct ServerSection {
     port: u32,
     online_mode: bool,
     compression_threshold: u32,
+    #[serde(default = "default_key_size")]
+    key_size: usize,
+    #[serde(default)]
+    duplicate_login: DuplicateLoginPolicy,
+    #[serde(default)]
+    whitelist: bool,
+}
+
+fn default_key_size() -> usize {
+    1024
 }
 
 #[derive(Deserialize, Getters)]
@@ -87,6 +151,33 @@ pub struct StatusSection {
     icon: String,
 }
 
+#[derive(Deserialize, Getters)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProfileSection {
+    /// How long cached profiles stay valid, in days
+    #[serde(default = "default_cache_ttl_days")]
+    cache_ttl_days: u64,
+    /// Whether offline-mode players get the skin of the premium account with the same name
+    #[serde(default)]
+    offline_skins: bool,
+    #[serde(default)]
+    endpoint: ProfileEndpoint,
+}
+
+impl Default for ProfileSection {
+    fn default() -> Self {
+        Self {
+            cache_ttl_days: default_cache_ttl_days(),
+            offline_skins: false,
+            endpoint: ProfileEndpoint::default(),
+        }
+    }
+}
+
+fn default_cache_ttl_days() -> u64 {
+    30
+}
+
 impl RawServerProps {
     fn read_icon(&self, run_directory: &Path) -> String {
         let mut file = File::open(run_directory.join(&self.status.icon))
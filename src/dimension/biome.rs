@@ -1,39 +1,233 @@
-// use serde::{Serialize, Deserialize};
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct BiomeRegistry {
-//     pub r#type: String,
-//     pub value: Vec<BiomeEntry>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct BiomeEntry {
-//     pub name: String,
-//     pub id: i32,
-//     pub element: BiomeProperties,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct BiomeProperties {
-//     pub precipitation: String,
-//     pub temperature: f32,
-//     pub downfall: f32,
-//     pub effects: Effects,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Effects {
-//     pub water_color: i32,
-//     pub mood_sound: MoodSound,
-//     pub water_fog_color: i32,
-//     pub fog_color: i32,
-//     pub sky_color: i32,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct MoodSound {
-//     pub sound: String,
-//     pub offset: f64,
-//     pub block_search_extent: i32,
-//     pub tick_delay: i32,
-// }
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use protocol::fields::key::Key;
+
+use crate::dimension::registry::Registry;
+use crate::region::palette::PaletteKind;
+
+/// The biomes known to clients. The id of an entry is the value stored in
+/// the biome containers of chunk sections.
+pub type BiomeRegistry = Registry<BiomeProperties>;
+
+impl BiomeRegistry {
+    /// The palette biome containers must use so that clients read their
+    /// direct palettes with the same number of bits
+    pub fn palette_kind(&self) -> PaletteKind {
+        PaletteKind::biomes(self.entries().len())
+    }
+
+    /// The id of each biome by its full name, as region files store them
+    pub fn ids_by_name(&self) -> HashMap<String, i32> {
+        self.entries().iter().map(|entry| (entry.name().to_string(), entry.id())).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BiomeProperties {
+    pub has_precipitation: bool,
+    pub temperature: f32,
+    /// `frozen` makes parts of the biome cold enough to snow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_modifier: Option<String>,
+    pub downfall: f32,
+    pub effects: Effects,
+}
+
+impl BiomeProperties {
+    /// A biome with the given effects and plains-like weather
+    pub fn new(effects: Effects) -> Self {
+        Self {
+            has_precipitation: true,
+            temperature: 0.8,
+            temperature_modifier: None,
+            downfall: 0.4,
+            effects,
+        }
+    }
+}
+
+/// How a biome looks and sounds to players
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Effects {
+    pub sky_color: i32,
+    pub fog_color: i32,
+    pub water_color: i32,
+    pub water_fog_color: i32,
+    /// Computed from the temperature and downfall when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub foliage_color: Option<i32>,
+    /// Computed from the temperature and downfall when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grass_color: Option<i32>,
+    /// `swamp` or `dark_forest`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grass_color_modifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub particle: Option<Particle>,
+    /// The sound looping while in the biome
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ambient_sound: Option<Key>,
+    pub mood_sound: MoodSound,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additions_sound: Option<AdditionsSound>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub music: Option<Music>,
+}
+
+impl Effects {
+    /// Effects with the given colors, and the cave mood sound every vanilla
+    /// biome has
+    pub fn new(sky_color: i32, fog_color: i32, water_color: i32, water_fog_color: i32) -> Self {
+        Self {
+            sky_color,
+            fog_color,
+            water_color,
+            water_fog_color,
+            foliage_color: None,
+            grass_color: None,
+            grass_color_modifier: None,
+            particle: None,
+            ambient_sound: None,
+            mood_sound: MoodSound::cave(),
+            additions_sound: None,
+            music: None,
+        }
+    }
+}
+
+/// Particles spawned randomly around players in the biome
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Particle {
+    /// The chance of spawning in each position each tick
+    pub probability: f32,
+    pub options: ParticleOptions,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParticleOptions {
+    #[serde(rename = "type")]
+    pub kind: Key,
+}
+
+/// A sound played in dark places
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MoodSound {
+    pub sound: Key,
+    /// How far from the player the sound plays
+    pub offset: f64,
+    pub block_search_extent: i32,
+    /// The ticks spent in the dark before it plays
+    pub tick_delay: i32,
+}
+
+impl MoodSound {
+    /// The mood sound of every vanilla biome
+    pub fn cave() -> Self {
+        Self {
+            sound: Key::minecraft("ambient.cave"),
+            offset: 2.0,
+            block_search_extent: 8,
+            tick_delay: 6000,
+        }
+    }
+}
+
+/// A sound played randomly while in the biome
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AdditionsSound {
+    pub sound: Key,
+    /// The chance of playing each tick
+    pub tick_chance: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Music {
+    pub sound: Key,
+    /// The minimum ticks between two tracks
+    pub min_delay: i32,
+    /// The maximum ticks between two tracks
+    pub max_delay: i32,
+    pub replace_current_music: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use nbt::Value;
+
+    use protocol::fields::key::Key;
+
+    use crate::dimension::biome::{AdditionsSound, BiomeProperties, Effects, Music, Particle, ParticleOptions};
+    use crate::dimension::to_compound;
+
+    fn biome() -> BiomeProperties {
+        BiomeProperties {
+            temperature_modifier: Some(String::from("frozen")),
+            effects: Effects {
+                foliage_color: Some(0x6a7039),
+                grass_color: Some(0x4c763c),
+                grass_color_modifier: Some(String::from("swamp")),
+                particle: Some(Particle {
+                    probability: 0.025,
+                    options: ParticleOptions { kind: Key::minecraft("ash") },
+                }),
+                ambient_sound: Some(Key::minecraft("ambient.basalt_deltas.loop")),
+                additions_sound: Some(AdditionsSound {
+                    sound: Key::minecraft("ambient.basalt_deltas.additions"),
+                    tick_chance: 0.0111,
+                }),
+                music: Some(Music {
+                    sound: Key::minecraft("music.nether.basalt_deltas"),
+                    min_delay: 12000,
+                    max_delay: 24000,
+                    replace_current_music: false,
+                }),
+                ..Effects::new(7907327, 12638463, 4159204, 329011)
+            },
+            ..BiomeProperties::new(Effects::new(0, 0, 0, 0))
+        }
+    }
+
+    #[test]
+    fn test_biome_round_trip() {
+        let biome = biome();
+        let mut bytes = Vec::new();
+        nbt::to_writer(&mut bytes, &biome, None).unwrap();
+        assert_eq!(nbt::from_reader::<_, BiomeProperties>(Cursor::new(bytes)).unwrap(), biome);
+    }
+
+    #[test]
+    fn test_biome_tags() {
+        let compound = to_compound(&biome());
+        assert_eq!(compound.get("has_precipitation"), Some(&Value::Byte(1)));
+        assert_eq!(compound.get("temperature"), Some(&Value::Float(0.8)));
+        assert_eq!(compound.get("temperature_modifier"), Some(&Value::String(String::from("frozen"))));
+
+        let Some(Value::Compound(effects)) = compound.get("effects") else {
+            panic!("the effects are a compound");
+        };
+        assert_eq!(effects.get("sky_color"), Some(&Value::Int(7907327)));
+        assert_eq!(effects.get("grass_color"), Some(&Value::Int(0x4c763c)));
+        assert_eq!(effects.get("ambient_sound"), Some(&Value::String(String::from("minecraft:ambient.basalt_deltas.loop"))));
+        let Some(Value::Compound(particle)) = effects.get("particle") else {
+            panic!("the particle is a compound");
+        };
+        assert_eq!(particle.get("probability"), Some(&Value::Float(0.025)));
+        let Some(Value::Compound(options)) = particle.get("options") else {
+            panic!("the particle options are a compound");
+        };
+        assert_eq!(options.get("type"), Some(&Value::String(String::from("minecraft:ash"))));
+        let Some(Value::Compound(mood)) = effects.get("mood_sound") else {
+            panic!("the mood sound is a compound");
+        };
+        assert_eq!(mood.get("offset"), Some(&Value::Double(2.0)));
+        assert_eq!(mood.get("tick_delay"), Some(&Value::Int(6000)));
+        let Some(Value::Compound(music)) = effects.get("music") else {
+            panic!("the music is a compound");
+        };
+        assert_eq!(music.get("replace_current_music"), Some(&Value::Byte(0)));
+    }
+}
@@ -1,45 +1,25 @@
-// use serde::{Serialize, Deserialize};
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct ChatRegistry {
-//     pub value: Vec<ChatRegistryEntry>,
-//     pub r#type: String,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct ChatRegistryEntry {
-//     pub name: String,
-//     pub id: i64,
-//     pub element: ChatElement,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct ChatElement {
-//     pub chat: Option<Chat>,
-//     pub narration: Option<Narration>,
-//     pub overlay: Option<Style>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Chat {
-//     pub decoration: Option<Decoration>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Narration {
-//     pub decoration: Option<Decoration>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Decoration {
-//     pub parameters: Vec<String>,
-//     pub translation_key: String,
-//     pub style: Option<Style>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Style {
-//     pub italic: Option<i64>,
-//     pub color: Option<String>,
-// }
-//
+use serde::{Deserialize, Serialize};
+
+/// How the client displays and narrates a kind of chat message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChatType {
+    pub chat: Decoration,
+    pub narration: Decoration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Decoration {
+    pub translation_key: String,
+    /// Which of `sender`, `target` and `content` fill the translation
+    pub parameters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<Style>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Style {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
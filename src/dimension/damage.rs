@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DamageType {
+    /// Completes the `death.attack.` translation key of death messages
+    pub message_id: String,
+    pub exhaustion: f32,
+    /// One of `never`, `always` and `when_caused_by_living_non_player`
+    pub scaling: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub death_message_type: Option<String>,
+}
@@ -1,68 +1,92 @@
+use anyhow::ensure;
 use serde::{Deserialize, Serialize};
 
 use protocol::fields::key::Key;
 
+use crate::dimension::range::{IntProvider, NumberProvider, NumberProviderType};
+
 #[derive(Serialize, Deserialize)]
 pub struct Dimension {
     key: Key,
 }
 
-//
-// pub const OVERWORLD: DimensionType = DimensionType {
-//     monster_spawn_light_level: 11,
-//     monster_spawn_block_light_limit: 0,
-//     ambient_light: 0.0,
-//     infiniburn: String::from("minecraft:infiniburn_overworld"),
-//     has_raids: true,
-//     piglin_safe: false,
-//     natural: true,
-//     respawn_anchor_works: false,
-//     has_skylight: true,
-//     bed_works: true,
-//     effects: String::from("minecraft:overworld"),
-//     min_y: -64,
-//     height: 384,
-//     logical_height: 384,
-//     coordinate_scale: 1,
-//     ultrawarm: true,
-//     has_ceiling: false,
-// };
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct DimensionTypeRegistry {
-//     pub r#type: String,
-//     pub value: Vec<DimensionEntry>,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct DimensionEntry {
-//     pub name: String,
-//     pub id: i32,
-//     pub element: DimensionType,
-// }
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct DimensionType {
-//     #[serde(default = "def_monster_spawn_light_level")]
-//     pub monster_spawn_light_level: i32,
-//     #[serde(default = "def_monster_spawn_block_light_limit")]
-//     pub monster_spawn_block_light_limit: i32,
-//     pub ambient_light: f32,
-//     pub infiniburn: String,
-//     pub has_raids: bool,
-//     pub piglin_safe: bool,
-//     pub natural: bool,
-//     pub respawn_anchor_works: bool,
-//     pub has_skylight: bool,
-//     pub bed_works: bool,
-//     pub effects: String,
-//     pub min_y: i32,
-//     pub height: i32,
-//     pub logical_height: i32,
-//     pub coordinate_scale: i32,
-//     pub ultrawarm: bool,
-//     pub has_ceiling: bool,
-// }
-//
-// pub const fn def_monster_spawn_light_level() -> i32 {11}
-// pub const fn def_monster_spawn_block_light_limit() -> i32 {0}
\ No newline at end of file
+/// The properties of a dimension shared with the client, such as its
+/// height and lighting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DimensionType {
+    /// The time of day is locked to this value when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_time: Option<i64>,
+    pub has_skylight: bool,
+    pub has_ceiling: bool,
+    pub ultrawarm: bool,
+    pub natural: bool,
+    pub coordinate_scale: f64,
+    pub bed_works: bool,
+    pub respawn_anchor_works: bool,
+    pub min_y: i32,
+    pub height: i32,
+    pub logical_height: i32,
+    /// The block tag of blocks that burn forever, e.g. `#minecraft:infiniburn_overworld`
+    pub infiniburn: String,
+    pub effects: Key,
+    pub ambient_light: f32,
+    pub piglin_safe: bool,
+    pub has_raids: bool,
+    pub monster_spawn_light_level: IntProvider,
+    pub monster_spawn_block_light_limit: i32,
+}
+
+/// The lowest y vanilla clients accept for the bottom of a dimension
+pub const MIN_Y: i32 = -2032;
+/// The highest y vanilla clients accept for the top of a dimension
+pub const MAX_Y: i32 = 2031;
+
+impl DimensionType {
+    /// A dimension like the overworld spanning `height` blocks from `min_y`.
+    /// The other properties can be changed with struct update syntax, e.g.
+    /// `DimensionType { ambient_light: 1.0, ..DimensionType::new(0, 64) }`
+    /// for a fully lit arena.
+    pub fn new(min_y: i32, height: i32) -> Self {
+        Self {
+            fixed_time: None,
+            has_skylight: true,
+            has_ceiling: false,
+            ultrawarm: false,
+            natural: true,
+            coordinate_scale: 1.0,
+            bed_works: true,
+            respawn_anchor_works: false,
+            min_y,
+            height,
+            logical_height: height,
+            infiniburn: String::from("#minecraft:infiniburn_overworld"),
+            effects: Key::minecraft("overworld"),
+            ambient_light: 0.0,
+            piglin_safe: false,
+            has_raids: true,
+            monster_spawn_light_level: IntProvider::Distribution(NumberProviderType::Uniform {
+                value: NumberProvider::IntRange { min_inclusive: 0, max_inclusive: 7 },
+            }),
+            monster_spawn_block_light_limit: 0,
+        }
+    }
+
+    /// Checks the bounds the client enforces, which would otherwise
+    /// disconnect players when the codec is sent
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(self.height >= 16, "the height must be at least 16, not {}", self.height);
+        ensure!(self.height % 16 == 0, "the height must be a multiple of 16, not {}", self.height);
+        ensure!(self.min_y % 16 == 0, "min_y must be a multiple of 16, not {}", self.min_y);
+        ensure!(self.min_y >= MIN_Y && self.min_y + self.height - 1 <= MAX_Y,
+            "the dimension must fit between y={} and y={}", MIN_Y, MAX_Y);
+        ensure!(self.logical_height >= 0 && self.logical_height <= self.height,
+            "the logical height must be between 0 and the height, not {}", self.logical_height);
+        Ok(())
+    }
+
+    /// The y just above the highest block
+    pub fn max_y(&self) -> i32 {
+        self.min_y + self.height
+    }
+}
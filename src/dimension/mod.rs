@@ -1,6 +1,19 @@
-// pub mod registry;
-// pub mod dimension;
-// pub mod biome;
-// pub mod chat;
-// pub mod range;
-mod dimension;
\ No newline at end of file
+pub mod registry;
+pub mod dimension;
+pub mod biome;
+pub mod chat;
+pub mod damage;
+pub mod range;
+
+/// Serializes the value to NBT and reads it back as a compound, to check
+/// the tags it is written with
+#[cfg(test)]
+pub(crate) fn to_compound<T: serde::Serialize>(value: &T) -> nbt::Map<String, nbt::Value> {
+    let mut bytes = Vec::new();
+    nbt::to_writer(&mut bytes, value, None).unwrap();
+    // Skips the tag type and the empty name of the root compound
+    match nbt::Value::from_reader(0x0a, &mut std::io::Cursor::new(&bytes[3..])).unwrap() {
+        nbt::Value::Compound(compound) => compound,
+        value => panic!("expected a compound, not {:?}", value),
+    }
+}
@@ -1,17 +1,186 @@
-// use serde::{Deserialize, Serialize};
-//
-// use crate::dimension::biome::BiomeRegistry;
-// use crate::dimension::chat::ChatRegistry;
-// use crate::dimension::dimension::DimensionTypeRegistry;
-//
-// #[derive(Serialize, Deserialize)]
-// pub struct Registry {
-//     #[serde(rename = "minecraft:dimension_type")]
-//     pub dimension_type: DimensionTypeRegistry,
-//
-//     #[serde(rename = "minecraft:worldgen/biome")]
-//     pub biome: BiomeRegistry,
-//
-//     #[serde(rename = "minecraft:chat_type")]
-//     pub chat_type: ChatRegistry,
-// }
+use std::io::Cursor;
+
+use anyhow::Context;
+use bevy::prelude::Resource;
+use nbt::{Blob, Value};
+use serde::{Deserialize, Serialize};
+
+use protocol::fields::key::Key;
+
+use crate::dimension::biome::{BiomeProperties, BiomeRegistry};
+use crate::dimension::chat::ChatType;
+use crate::dimension::damage::DamageType;
+use crate::dimension::dimension::DimensionType;
+
+/// The registries sent to clients when they join. The client knows nothing
+/// about dimension types, biomes, chat types and damage types it is not sent.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegistryCodec {
+    #[serde(rename = "minecraft:dimension_type")]
+    pub dimension_type: Registry<DimensionType>,
+
+    #[serde(rename = "minecraft:worldgen/biome")]
+    pub biome: BiomeRegistry,
+
+    #[serde(rename = "minecraft:chat_type")]
+    pub chat_type: Registry<ChatType>,
+
+    #[serde(rename = "minecraft:damage_type")]
+    pub damage_type: Registry<DamageType>,
+
+    // Armor trims are experimental in 1.19.4, and vanilla sends them empty
+    #[serde(rename = "minecraft:trim_pattern")]
+    pub trim_pattern: Registry<Value>,
+
+    #[serde(rename = "minecraft:trim_material")]
+    pub trim_material: Registry<Value>,
+}
+
+impl RegistryCodec {
+    /// Reads the registries vanilla sends
+    pub fn vanilla() -> Self {
+        let codec: &[u8] = include_bytes!("../../codecs/1.19.4/registry-1.19.4.nbt");
+        nbt::from_reader(codec).expect("the vanilla registry codec is valid")
+    }
+
+    /// Registers a biome, returning the id chunks refer to it by
+    pub fn add_biome(&mut self, name: Key, biome: BiomeProperties) -> i32 {
+        self.biome.register(name, biome)
+    }
+
+    /// Registers a dimension type, which worlds can then be created with.
+    /// Fails if the client would reject the type.
+    pub fn add_dimension_type(&mut self, name: Key, dimension_type: DimensionType) -> anyhow::Result<i32> {
+        dimension_type.validate()
+            .with_context(|| format!("invalid dimension type {}", name))?;
+        Ok(self.dimension_type.register(name, dimension_type))
+    }
+
+    /// Serializes the registries to the NBT sent in the Login (play) packet
+    pub fn to_blob(&self) -> nbt::Result<Blob> {
+        let mut bytes = Vec::new();
+        nbt::to_writer(&mut bytes, self, None)?;
+        Blob::from_reader(&mut Cursor::new(bytes))
+    }
+}
+
+/// A registry of the codec, e.g. `minecraft:worldgen/biome`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Registry<T> {
+    #[serde(rename = "type")]
+    kind: Key,
+    value: Vec<RegistryEntry<T>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RegistryEntry<T> {
+    name: Key,
+    id: i32,
+    element: T,
+}
+
+impl<T> Registry<T> {
+    pub fn new(kind: Key) -> Self {
+        Self { kind, value: Vec::new() }
+    }
+
+    /// Adds an entry, returning its id. Registering a name again replaces
+    /// its element and keeps its id.
+    pub fn register(&mut self, name: Key, element: T) -> i32 {
+        if let Some(entry) = self.value.iter_mut().find(|entry| entry.name == name) {
+            entry.element = element;
+            return entry.id;
+        }
+        let id = self.value.len() as i32;
+        self.value.push(RegistryEntry { name, id, element });
+        id
+    }
+
+    pub fn get(&self, name: &Key) -> Option<&T> {
+        self.entry(name).map(|entry| &entry.element)
+    }
+
+    pub fn get_mut(&mut self, name: &Key) -> Option<&mut T> {
+        self.value.iter_mut()
+            .find(|entry| entry.name == *name)
+            .map(|entry| &mut entry.element)
+    }
+
+    pub fn id(&self, name: &Key) -> Option<i32> {
+        self.entry(name).map(|entry| entry.id)
+    }
+
+    pub fn by_id(&self, id: i32) -> Option<&RegistryEntry<T>> {
+        self.value.iter().find(|entry| entry.id == id)
+    }
+
+    pub fn entry(&self, name: &Key) -> Option<&RegistryEntry<T>> {
+        self.value.iter().find(|entry| entry.name == *name)
+    }
+
+    pub fn entries(&self) -> &[RegistryEntry<T>] {
+        &self.value
+    }
+
+    pub fn kind(&self) -> &Key {
+        &self.kind
+    }
+}
+
+impl<T> RegistryEntry<T> {
+    pub fn name(&self) -> &Key {
+        &self.name
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn element(&self) -> &T {
+        &self.element
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use nbt::{Blob, Value};
+
+    use crate::dimension::biome::{BiomeProperties, Effects};
+    use crate::dimension::dimension::DimensionType;
+    use crate::dimension::registry::RegistryCodec;
+    use crate::dimension::to_compound;
+
+    #[test]
+    fn test_vanilla_round_trip() {
+        let mut codec: &[u8] = include_bytes!("../../codecs/1.19.4/registry-1.19.4.nbt");
+        let original = Blob::from_reader(&mut codec).unwrap();
+        assert_eq!(RegistryCodec::vanilla().to_blob().unwrap(), original);
+    }
+
+    #[test]
+    fn test_dimension_type_omits_none() {
+        let compound = to_compound(&DimensionType::new(0, 64));
+        assert!(!compound.contains_key("fixed_time"));
+
+        let compound = to_compound(&DimensionType { fixed_time: Some(6000), ..DimensionType::new(0, 64) });
+        assert_eq!(compound.get("fixed_time"), Some(&Value::Long(6000)));
+    }
+
+    #[test]
+    fn test_biome_omits_none() {
+        let biome = BiomeProperties::new(Effects::new(1, 2, 3, 4));
+        let compound = to_compound(&biome);
+        assert!(!compound.contains_key("temperature_modifier"));
+        let Some(Value::Compound(effects)) = compound.get("effects") else {
+            panic!("the effects are a compound");
+        };
+        for name in ["foliage_color", "grass_color", "grass_color_modifier", "particle",
+            "ambient_sound", "additions_sound", "music"] {
+            assert!(!effects.contains_key(name), "{} is written", name);
+        }
+        assert_eq!(effects.get("sky_color"), Some(&Value::Int(1)));
+        assert!(effects.contains_key("mood_sound"));
+    }
+}
@@ -1,26 +1,87 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use bevy::log::warn;
 use rand::Rng;
-use rsa::pkcs8::EncodePublicKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::traits::PublicKeyParts;
 use rsa::{errors::Result, Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
 
+/// The file, relative to the run directory, that the server keypair is stored in.
+pub const KEY_FILE: &str = "server-key.pem";
+
+/// The range of key sizes, in bits, accepted for the server keypair.
+/// Smaller keys are trivial to factor, and larger ones take minutes to
+/// generate.
+pub const KEY_SIZES: RangeInclusive<usize> = 1024..=4096;
+
 pub struct ServerEncryption {
     private_key: RsaPrivateKey,
     pub(crate) public_key: RsaPublicKey,
-    verify_token: Vec<u8>,
 }
 
 impl ServerEncryption {
-    pub fn new() -> ServerEncryption {
+    /// Generates a keypair. Panics if `key_size` is not a valid RSA key
+    /// size, see [KEY_SIZES].
+    pub fn new(key_size: usize) -> ServerEncryption {
         let mut rng = rand::thread_rng();
-        let private_key = RsaPrivateKey::new(&mut rng, 1024).expect("failed to generate a key");
+        let private_key = RsaPrivateKey::new(&mut rng, key_size).expect("failed to generate a key");
+        Self::from_private_key(private_key)
+    }
+
+    /// Loads the keypair stored in the run directory, or generates a new one
+    /// and saves it as a PKCS#8 PEM file if none exists yet.
+    ///
+    /// A stored key is kept even if its size differs from `key_size`, with a
+    /// warning. Delete the file to generate a key of the new size. Fails if
+    /// `key_size` is outside of [KEY_SIZES].
+    ///
+    /// The file is only readable by its owner on unix, as the key lets
+    /// anyone impersonate the server.
+    pub fn load_or_create(run_directory: &Path, key_size: usize) -> std::io::Result<ServerEncryption> {
+        if !KEY_SIZES.contains(&key_size) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("the key size must be between {} and {} bits, not {}", KEY_SIZES.start(), KEY_SIZES.end(), key_size),
+            ));
+        }
+        let path = run_directory.join(KEY_FILE);
+        if path.exists() {
+            let pem = fs::read_to_string(&path)?;
+            let private_key = RsaPrivateKey::from_pkcs8_pem(&pem)
+                .map_err(|why| Error::new(ErrorKind::InvalidData, format!("invalid key in {:?}: {}", path, why)))?;
+            if private_key.size() * 8 != key_size {
+                warn!(
+                    "the key in {:?} has {} bits instead of the configured {}, delete it to generate a new one",
+                    path, private_key.size() * 8, key_size
+                );
+            }
+            return Ok(Self::from_private_key(private_key));
+        }
+        let encryption = Self::new(key_size);
+        let pem = encryption.private_key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(|why| Error::new(ErrorKind::InvalidData, why.to_string()))?;
+        write_private(&path, pem.as_bytes())?;
+        Ok(encryption)
+    }
+
+    fn from_private_key(private_key: RsaPrivateKey) -> ServerEncryption {
         let public_key = RsaPublicKey::from(&private_key);
-        let verify_token = rand::thread_rng().gen::<[u8; 4]>().to_vec();
         ServerEncryption {
             private_key,
             public_key,
-            verify_token,
         }
     }
 
+    /// Generates a fresh verify token. A new token is issued to every
+    /// connection so that a captured response cannot be replayed on another.
+    pub fn generate_verify_token() -> Vec<u8> {
+        rand::thread_rng().gen::<[u8; 4]>().to_vec()
+    }
+
     pub fn encrypt(&self, data: &Vec<u8>) -> Result<Vec<u8>> {
         let mut rng = rand::thread_rng();
         self.public_key.encrypt(
@@ -41,12 +102,82 @@ impl ServerEncryption {
             .as_ref()
             .to_vec()
     }
+}
+
+/// Creates the file, readable and writable only by its owner on unix
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+
+    use crate::encryption::server::{KEY_FILE, ServerEncryption};
+    use crate::util::test_directory;
+
+    #[test]
+    fn test_saved_key_reloads() {
+        let run_directory = test_directory("key-reload");
+        let directory = run_directory.path();
+        let created = ServerEncryption::load_or_create(directory, 1024).unwrap();
+        assert!(directory.join(KEY_FILE).exists());
+        let loaded = ServerEncryption::load_or_create(directory, 1024).unwrap();
+        assert_eq!(loaded.public_key_encoded(), created.public_key_encoded());
+
+        let secret = b"0123456789abcdef".to_vec();
+        assert_eq!(loaded.decrypt(&created.encrypt(&secret).unwrap()).unwrap(), secret);
+    }
 
-    pub fn verify_token(&self) -> Vec<u8> {
-        self.verify_token.to_vec()
+    #[test]
+    fn test_key_of_other_size_is_kept() {
+        let run_directory = test_directory("key-size");
+        let directory = run_directory.path();
+        let created = ServerEncryption::load_or_create(directory, 1024).unwrap();
+        let pem = fs::read_to_string(directory.join(KEY_FILE)).unwrap();
+        let loaded = ServerEncryption::load_or_create(directory, 2048).unwrap();
+        assert_eq!(loaded.public_key_encoded(), created.public_key_encoded());
+        assert_eq!(fs::read_to_string(directory.join(KEY_FILE)).unwrap(), pem);
     }
 
-    pub fn compare_verify_tokens(&self, token: &Vec<u8>) -> bool {
-        &self.verify_token == token
+    #[test]
+    fn test_invalid_key_fails() {
+        let run_directory = test_directory("key-invalid");
+        let directory = run_directory.path();
+        fs::write(directory.join(KEY_FILE), "not a key").unwrap();
+        assert!(ServerEncryption::load_or_create(directory, 1024).is_err());
+    }
+
+    #[test]
+    fn test_key_size_out_of_range_fails() {
+        let run_directory = test_directory("key-range");
+        let directory = run_directory.path();
+        for key_size in [0, 512, 65536] {
+            let error = ServerEncryption::load_or_create(directory, key_size).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+        assert!(!directory.join(KEY_FILE).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_only_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let run_directory = test_directory("key-mode");
+        let directory = run_directory.path();
+        ServerEncryption::load_or_create(directory, 1024).unwrap();
+        let mode = fs::metadata(directory.join(KEY_FILE)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
     }
 }
@@ -0,0 +1,26 @@
+use crate::generation::ChunkGenerator;
+use crate::region::column::ChunkColumn;
+
+/// Runs another generator, then sets the whole column to one biome
+#[derive(Debug, Clone)]
+pub struct SingleBiomeGenerator<G> {
+    biome: i32,
+    generator: G,
+}
+
+impl<G: ChunkGenerator> SingleBiomeGenerator<G> {
+    pub fn new(biome: i32, generator: G) -> Self {
+        Self { biome, generator }
+    }
+
+    pub fn biome(&self) -> i32 {
+        self.biome
+    }
+}
+
+impl<G: ChunkGenerator> ChunkGenerator for SingleBiomeGenerator<G> {
+    fn generate(&self, column: &mut ChunkColumn) {
+        self.generator.generate(column);
+        column.fill_biome(self.biome);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, ensure, Context};
+
+use crate::block::BlockState;
+use crate::generation::ChunkGenerator;
+use crate::region::column::ChunkColumn;
+
+/// The preset of vanilla's Classic Flat world
+pub const CLASSIC_FLAT: &str = "minecraft:bedrock,2*minecraft:dirt,minecraft:grass_block;minecraft:plains";
+
+/// Fills every column with the same layers of blocks, starting at the bottom
+/// of the world, and one biome
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatGenerator {
+    // Each block and the number of layers it fills, from the bottom up
+    layers: Vec<(BlockState, i32)>,
+    biome: i32,
+}
+
+impl FlatGenerator {
+    pub fn new(layers: Vec<(BlockState, i32)>, biome: i32) -> Self {
+        Self { layers, biome }
+    }
+
+    /// Parses a preset string like vanilla's superflat presets, e.g.
+    /// `minecraft:bedrock,2*minecraft:dirt,minecraft:grass_block;minecraft:plains`.
+    ///
+    /// The layers are listed from the bottom up, each optionally prefixed
+    /// with the number of layers it fills and suffixed with block state
+    /// properties, like `minecraft:snow[layers=3]`. The biome defaults to
+    /// plains, and anything after it, like structures, is ignored.
+    pub fn from_preset(preset: &str, biome_id: impl Fn(&str) -> Option<i32>) -> anyhow::Result<Self> {
+        let mut parts = preset.split(';');
+        let layers = parts.next().unwrap_or_default().trim();
+        let layers = if layers.is_empty() {
+            Vec::new()
+        } else {
+            layers.split(',').map(parse_layer).collect::<anyhow::Result<_>>()?
+        };
+        let biome = parts.next().map(str::trim).filter(|biome| !biome.is_empty()).unwrap_or("minecraft:plains");
+        let biome = biome_id(biome).with_context(|| format!("unknown biome {}", biome))?;
+        Ok(Self { layers, biome })
+    }
+
+    pub fn layers(&self) -> &[(BlockState, i32)] {
+        &self.layers
+    }
+
+    pub fn biome(&self) -> i32 {
+        self.biome
+    }
+}
+
+impl ChunkGenerator for FlatGenerator {
+    fn generate(&self, column: &mut ChunkColumn) {
+        let mut y = column.min_y();
+        for (state, count) in &self.layers {
+            if !state.is_air() {
+                column.fill(y, y + count - 1, *state);
+            }
+            y += count;
+        }
+        column.fill_biome(self.biome);
+    }
+}
+
+/// Parses a layer like `2*minecraft:dirt`
+fn parse_layer(layer: &str) -> anyhow::Result<(BlockState, i32)> {
+    let layer = layer.trim();
+    let (count, block) = match layer.split_once('*') {
+        Some((count, block)) => {
+            let count = count.trim().parse::<i32>()
+                .with_context(|| format!("invalid layer count in {}", layer))?;
+            (count, block.trim())
+        }
+        None => (1, layer),
+    };
+    ensure!(count > 0, "the layer {} must fill at least one block", layer);
+    let (key, properties) = match block.split_once('[') {
+        Some((key, properties)) => {
+            let Some(properties) = properties.strip_suffix(']') else {
+                bail!("unclosed properties in {}", layer);
+            };
+            (key, parse_properties(properties).with_context(|| format!("invalid properties in {}", layer))?)
+        }
+        None => (block, HashMap::new()),
+    };
+    // Like commands, the namespace may be omitted
+    let key = if key.contains(':') { key.to_string() } else { format!("minecraft:{}", key) };
+    let state = BlockState::from_properties(&key, &properties)
+        .with_context(|| format!("unknown block state {}", block))?;
+    Ok((state, count))
+}
+
+fn parse_properties(properties: &str) -> anyhow::Result<HashMap<String, String>> {
+    properties.split(',')
+        .filter(|property| !property.trim().is_empty())
+        .map(|property| {
+            let (name, value) = property.split_once('=').context("expected name=value")?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{Block, BlockState};
+    use crate::generation::ChunkGenerator;
+    use crate::generation::flat::{CLASSIC_FLAT, FlatGenerator};
+    use crate::region::column::ChunkColumn;
+
+    fn biome_id(name: &str) -> Option<i32> {
+        match name {
+            "minecraft:plains" => Some(1),
+            "minecraft:desert" => Some(2),
+            _ => None,
+        }
+    }
+
+    fn state(key: &str) -> BlockState {
+        BlockState::from_properties(key, &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_classic_flat() {
+        let generator = FlatGenerator::from_preset(CLASSIC_FLAT, biome_id).unwrap();
+        assert_eq!(generator.layers(), &[
+            (state("minecraft:bedrock"), 1),
+            (state("minecraft:dirt"), 2),
+            (state("minecraft:grass_block"), 1),
+        ]);
+        assert_eq!(generator.biome(), 1);
+    }
+
+    #[test]
+    fn test_preset_details() {
+        let generator = FlatGenerator::from_preset("3*stone, minecraft:sand;minecraft:desert;village", biome_id).unwrap();
+        assert_eq!(generator.layers(), &[(state("minecraft:stone"), 3), (state("minecraft:sand"), 1)]);
+        assert_eq!(generator.biome(), 2);
+
+        let snow = FlatGenerator::from_preset("minecraft:snow[layers=3]", biome_id).unwrap();
+        assert_eq!(snow.layers()[0].0.to_string(), "minecraft:snow[layers=3]");
+        assert_eq!(snow.biome(), 1);
+    }
+
+    #[test]
+    fn test_invalid_presets() {
+        assert!(FlatGenerator::from_preset("0*minecraft:stone", biome_id).is_err());
+        assert!(FlatGenerator::from_preset("minecraft:not_a_block", biome_id).is_err());
+        assert!(FlatGenerator::from_preset("minecraft:stone;minecraft:not_a_biome", biome_id).is_err());
+        assert!(FlatGenerator::from_preset("minecraft:snow[layers=3", biome_id).is_err());
+    }
+
+    #[test]
+    fn test_generate() {
+        let generator = FlatGenerator::from_preset(CLASSIC_FLAT, biome_id).unwrap();
+        let mut column = ChunkColumn::new(2, -3, -64, 384, 0);
+        generator.generate(&mut column);
+        for z in 0..16 {
+            for x in 0..16 {
+                assert_eq!(column.get_block(x, -64, z).block(), Block::Bedrock);
+                assert_eq!(column.get_block(x, -63, z).block(), Block::Dirt);
+                assert_eq!(column.get_block(x, -62, z).block(), Block::Dirt);
+                assert_eq!(column.get_block(x, -61, z).block(), Block::GrassBlock);
+                assert!(column.get_block(x, -60, z).is_air());
+                assert!(column.get_block(x, 319, z).is_air());
+            }
+        }
+        for y in [-64, -61, 0, 319] {
+            assert_eq!(column.get_biome(0, y, 0), 1);
+            assert_eq!(column.get_biome(15, y, 15), 1);
+        }
+        assert_eq!(column.highest_block(7, 7), Some(-60));
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bevy::prelude::{ResMut, Resource};
+use flume::{Receiver, Sender};
+use serde::Deserialize;
+use tokio::runtime::Handle;
+
+use protocol::fields::key::Key;
+
+use crate::dimension::biome::BiomeRegistry;
+use crate::generation::biome::SingleBiomeGenerator;
+use crate::generation::flat::FlatGenerator;
+use crate::generation::noise::{NoiseGenerator, TerrainBiomes};
+use crate::generation::void::VoidGenerator;
+use crate::region::column::ChunkColumn;
+use crate::region::section::ChunkPos;
+use crate::world::chunks::WorldChunks;
+
+pub mod void;
+pub mod flat;
+pub mod biome;
+pub mod noise;
+
+/// Fills the columns of a world that were never saved.
+///
+/// Generators run on the blocking threads of the tokio runtime, several
+/// columns at once, so they must not depend on the order columns are
+/// generated in.
+pub trait ChunkGenerator: Send + Sync {
+    /// Fills an empty column, whose position is given by its coordinates.
+    /// The column is sized for the world and fully lit by the sky, if the
+    /// dimension has one.
+    fn generate(&self, column: &mut ChunkColumn);
+
+    /// The lowest y players can stand at above the given block column, if
+    /// the generator knows it without generating the column
+    fn surface_y(&self, _x: i32, _z: i32) -> Option<i32> {
+        None
+    }
+}
+
+/// The built-in generators, as chosen in `server.toml`
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GeneratorType {
+    /// Empty columns in the void biome
+    #[default]
+    Void,
+    /// Layers of blocks given by a superflat preset, see [FlatGenerator::from_preset]
+    Flat,
+    /// Hills and oceans from the world seed, see [NoiseGenerator]
+    Noise,
+}
+
+impl GeneratorType {
+    /// Creates the generator, configured by the settings string
+    pub fn create(&self, settings: &str, seed: i64, biomes: &BiomeRegistry) -> anyhow::Result<Arc<dyn ChunkGenerator>> {
+        let biome_id = |name: &str| Key::parse(name).ok().and_then(|key| biomes.id(&key));
+        Ok(match self {
+            GeneratorType::Void => {
+                let biome = biome_id("minecraft:the_void").unwrap_or(0);
+                Arc::new(SingleBiomeGenerator::new(biome, VoidGenerator))
+            }
+            GeneratorType::Flat => Arc::new(FlatGenerator::from_preset(settings, biome_id)?),
+            GeneratorType::Noise => {
+                let biomes = TerrainBiomes {
+                    land: biome_id("minecraft:plains").unwrap_or(0),
+                    beach: biome_id("minecraft:beach").unwrap_or(0),
+                    ocean: biome_id("minecraft:ocean").unwrap_or(0),
+                };
+                Arc::new(NoiseGenerator::new(seed, biomes))
+            }
+        })
+    }
+}
+
+/// Generates the columns of a world in the background. The generated
+/// columns are picked up by the world on the next tick.
+#[derive(Resource)]
+pub struct WorldGenerator {
+    generator: Arc<dyn ChunkGenerator>,
+    runtime: Handle,
+    pending: HashSet<ChunkPos>,
+    sender: Sender<ChunkColumn>,
+    receiver: Receiver<ChunkColumn>,
+}
+
+impl WorldGenerator {
+    pub fn new(generator: Arc<dyn ChunkGenerator>, runtime: Handle) -> Self {
+        let (sender, receiver) = flume::unbounded();
+        Self {
+            generator,
+            runtime,
+            pending: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn generator(&self) -> &Arc<dyn ChunkGenerator> {
+        &self.generator
+    }
+
+    /// A generator using the same [ChunkGenerator], e.g. for an instance
+    /// of the world
+    pub fn share(&self) -> WorldGenerator {
+        Self::new(Arc::clone(&self.generator), self.runtime.clone())
+    }
+
+    /// Starts generating the empty column, unless its position is already
+    /// being generated
+    pub fn request(&mut self, mut column: ChunkColumn) {
+        if !self.pending.insert(ChunkPos::new(column.x(), column.z())) {
+            return;
+        }
+        let generator = Arc::clone(&self.generator);
+        let sender = self.sender.clone();
+        self.runtime.spawn_blocking(move || {
+            generator.generate(&mut column);
+            let _ = sender.send(column);
+        });
+    }
+
+    pub fn is_pending(&self, pos: &ChunkPos) -> bool {
+        self.pending.contains(pos)
+    }
+
+    /// The columns generated since the last call
+    pub fn take_generated(&mut self) -> Vec<ChunkColumn> {
+        let generated: Vec<_> = self.receiver.try_iter().collect();
+        for column in &generated {
+            self.pending.remove(&ChunkPos::new(column.x(), column.z()));
+        }
+        generated
+    }
+}
+
+/// Adds the columns generated since the last tick to the world, unless they
+/// were created in the meantime
+pub fn receive_generated(generator: Option<ResMut<WorldGenerator>>, mut chunks: ResMut<WorldChunks>) {
+    let Some(mut generator) = generator else {
+        return;
+    };
+    for column in generator.take_generated() {
+        if chunks.get(&ChunkPos::new(column.x(), column.z())).is_none() {
+            chunks.insert(column);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use bevy::prelude::{Schedule, World};
+    use tokio::runtime::Runtime;
+
+    use protocol::fields::key::Key;
+
+    use crate::block::Block;
+    use crate::dimension::registry::RegistryCodec;
+    use crate::generation::{GeneratorType, receive_generated, WorldGenerator};
+    use crate::generation::flat::CLASSIC_FLAT;
+    use crate::region::section::ChunkPos;
+    use crate::world::chunks::WorldChunks;
+
+    #[test]
+    fn test_create_with_vanilla_biomes() {
+        let codec = RegistryCodec::vanilla();
+        for generator in [GeneratorType::Void, GeneratorType::Flat, GeneratorType::Noise] {
+            assert!(generator.create(CLASSIC_FLAT, 0, &codec.biome).is_ok(), "{:?} failed", generator);
+        }
+        assert!(GeneratorType::Flat.create("minecraft:not_a_block", 0, &codec.biome).is_err());
+    }
+
+    #[test]
+    fn test_generate_in_background() {
+        let runtime = Runtime::new().unwrap();
+        let codec = RegistryCodec::vanilla();
+        let flat = GeneratorType::Flat.create(CLASSIC_FLAT, 0, &codec.biome).unwrap();
+        let overworld = codec.dimension_type.get(&Key::minecraft("overworld")).unwrap();
+        let chunks = WorldChunks::new(overworld, codec.biome.palette_kind());
+
+        let mut generator = WorldGenerator::new(Arc::clone(&flat), runtime.handle().clone());
+        let pos = ChunkPos::new(4, -1);
+        generator.request(chunks.empty_column(pos));
+        // Requests for a pending position are ignored
+        generator.request(chunks.empty_column(pos));
+        assert!(generator.is_pending(&pos));
+
+        let mut world = World::new();
+        world.insert_resource(generator);
+        world.insert_resource(chunks);
+        let mut schedule = Schedule::new();
+        schedule.add_system(receive_generated);
+        let start = Instant::now();
+        while world.resource::<WorldChunks>().get(&pos).is_none() {
+            assert!(start.elapsed() < Duration::from_secs(10), "the column was never generated");
+            schedule.run(&mut world);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(!world.resource::<WorldGenerator>().is_pending(&pos));
+        let chunks = world.resource::<WorldChunks>();
+        assert_eq!(chunks.get_block(64, -61, -16).map(|state| state.block()), Some(Block::GrassBlock));
+        assert_eq!(chunks.columns().count(), 1);
+        // The second request never generated another column
+        schedule.run(&mut world);
+        assert!(world.resource_mut::<WorldGenerator>().take_generated().is_empty());
+    }
+}
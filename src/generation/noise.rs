@@ -0,0 +1,344 @@
+use crate::block::Block;
+use crate::generation::ChunkGenerator;
+use crate::region::column::ChunkColumn;
+
+/// The y of the surface of oceans, as in vanilla
+pub const SEA_LEVEL: i32 = 63;
+
+/// A small deterministic random number generator (SplitMix64). Unlike the
+/// generators of `rand`, its output is guaranteed to never change, so the
+/// terrain of a seed stays the same across versions.
+#[derive(Debug, Clone)]
+pub struct SeedRandom(u64);
+
+impl SeedRandom {
+    pub fn new(seed: i64) -> Self {
+        Self(seed as u64)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A value between 0 (inclusive) and 1 (exclusive)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A value between 0 (inclusive) and `bound` (exclusive)
+    pub fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// Ken Perlin's improved noise, with a permutation and an origin drawn from
+/// a random generator. Samples are roughly between -1 and 1.
+#[derive(Debug, Clone)]
+pub struct PerlinNoise {
+    // The shuffled values 0 to 255, repeated so lookups never wrap
+    permutation: [u8; 512],
+    origin: (f64, f64, f64),
+}
+
+impl PerlinNoise {
+    pub fn new(random: &mut SeedRandom) -> Self {
+        let origin = (random.next_f64() * 256.0, random.next_f64() * 256.0, random.next_f64() * 256.0);
+        let mut values: [u8; 256] = std::array::from_fn(|index| index as u8);
+        for index in (1..256).rev() {
+            values.swap(index, random.next_below(index + 1));
+        }
+        let permutation = std::array::from_fn(|index| values[index & 255]);
+        Self { permutation, origin }
+    }
+
+    pub fn sample(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (x, y, z) = (x + self.origin.0, y + self.origin.1, z + self.origin.2);
+        let (floor_x, floor_y, floor_z) = (x.floor(), y.floor(), z.floor());
+        let (x, y, z) = (x - floor_x, y - floor_y, z - floor_z);
+        let cell_x = (floor_x as i64 & 255) as usize;
+        let cell_y = (floor_y as i64 & 255) as usize;
+        let cell_z = (floor_z as i64 & 255) as usize;
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let p = |index: usize| self.permutation[index] as usize;
+        let a = p(cell_x) + cell_y;
+        let (aa, ab) = (p(a) + cell_z, p(a + 1) + cell_z);
+        let b = p(cell_x + 1) + cell_y;
+        let (ba, bb) = (p(b) + cell_z, p(b + 1) + cell_z);
+
+        lerp(w,
+            lerp(v,
+                lerp(u, grad(p(aa), x, y, z), grad(p(ba), x - 1.0, y, z)),
+                lerp(u, grad(p(ab), x, y - 1.0, z), grad(p(bb), x - 1.0, y - 1.0, z))),
+            lerp(v,
+                lerp(u, grad(p(aa + 1), x, y, z - 1.0), grad(p(ba + 1), x - 1.0, y, z - 1.0)),
+                lerp(u, grad(p(ab + 1), x, y - 1.0, z - 1.0), grad(p(bb + 1), x - 1.0, y - 1.0, z - 1.0))))
+    }
+}
+
+/// Octaves of Perlin noise, each twice the frequency and `persistence`
+/// times the amplitude of the previous one. Samples are normalized to
+/// roughly between -1 and 1.
+#[derive(Debug, Clone)]
+pub struct OctaveNoise {
+    octaves: Vec<PerlinNoise>,
+    persistence: f64,
+}
+
+impl OctaveNoise {
+    pub fn new(random: &mut SeedRandom, octaves: usize, persistence: f64) -> Self {
+        let octaves = (0..octaves.max(1)).map(|_| PerlinNoise::new(random)).collect();
+        Self { octaves, persistence }
+    }
+
+    pub fn sample(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (mut value, mut total, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, 1.0);
+        for octave in &self.octaves {
+            value += octave.sample(x * frequency, y * frequency, z * frequency) * amplitude;
+            total += amplitude;
+            amplitude *= self.persistence;
+            frequency *= 2.0;
+        }
+        value / total
+    }
+}
+
+/// The ids of the biomes the noise generator places
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainBiomes {
+    pub land: i32,
+    pub beach: i32,
+    pub ocean: i32,
+}
+
+/// Rolling hills and oceans from seeded height noise, with grass and dirt
+/// over stone on land, sand on beaches and ocean floors, water up to sea
+/// level and winding caves. The same seed always generates the same terrain.
+#[derive(Debug, Clone)]
+pub struct NoiseGenerator {
+    seed: i64,
+    biomes: TerrainBiomes,
+    continents: OctaveNoise,
+    hills: OctaveNoise,
+    // Caves are carved where both noises are close to zero
+    caves: (OctaveNoise, OctaveNoise),
+}
+
+impl NoiseGenerator {
+    pub fn new(seed: i64, biomes: TerrainBiomes) -> Self {
+        let mut random = SeedRandom::new(seed);
+        Self {
+            seed,
+            biomes,
+            continents: OctaveNoise::new(&mut random, 4, 0.5),
+            hills: OctaveNoise::new(&mut random, 3, 0.5),
+            caves: (OctaveNoise::new(&mut random, 2, 0.5), OctaveNoise::new(&mut random, 2, 0.5)),
+        }
+    }
+
+    pub fn seed(&self) -> i64 {
+        self.seed
+    }
+
+    /// The y of the highest solid block at the given block coordinates,
+    /// before caves are carved
+    pub fn height(&self, x: i32, z: i32) -> i32 {
+        let (x, z) = (x as f64, z as f64);
+        let continents = self.continents.sample(x / 256.0, 0.0, z / 256.0);
+        let hills = self.hills.sample(x / 48.0, 0.0, z / 48.0);
+        SEA_LEVEL + 4 + (continents * 40.0 + hills * 8.0).round() as i32
+    }
+
+    /// Whether a cave is carved at the given block coordinates
+    pub fn is_cave(&self, x: i32, y: i32, z: i32) -> bool {
+        let (x, y, z) = (x as f64 / 64.0, y as f64 / 32.0, z as f64 / 64.0);
+        let first = self.caves.0.sample(x, y, z);
+        let second = self.caves.1.sample(x, y, z);
+        first * first + second * second < 0.003
+    }
+
+    fn biome(&self, height: i32) -> i32 {
+        if height < SEA_LEVEL - 1 {
+            self.biomes.ocean
+        } else if height <= SEA_LEVEL + 1 {
+            self.biomes.beach
+        } else {
+            self.biomes.land
+        }
+    }
+}
+
+impl ChunkGenerator for NoiseGenerator {
+    fn generate(&self, column: &mut ChunkColumn) {
+        let bedrock = Block::Bedrock.default_state();
+        let stone = Block::Stone.default_state();
+        let dirt = Block::Dirt.default_state();
+        let grass = Block::GrassBlock.default_state();
+        let sand = Block::Sand.default_state();
+        let water = Block::Water.default_state();
+
+        let (min_y, max_y) = (column.min_y(), column.max_y());
+        let (origin_x, origin_z) = (column.x() * 16, column.z() * 16);
+        for z in 0..16 {
+            for x in 0..16 {
+                let (world_x, world_z) = (origin_x + x, origin_z + z);
+                let height = self.height(world_x, world_z).clamp(min_y, max_y - 1);
+                let beach = height <= SEA_LEVEL + 1;
+                // Caves stay away from the ocean floor so they don't flood
+                let cave_top = if height < SEA_LEVEL { height - 8 } else { height };
+                for y in min_y..=height {
+                    let state = if y == min_y {
+                        bedrock
+                    } else if y == height {
+                        if beach { sand } else { grass }
+                    } else if y > height - 4 {
+                        if beach { sand } else { dirt }
+                    } else {
+                        stone
+                    };
+                    if y > min_y && y <= cave_top && self.is_cave(world_x, y, world_z) {
+                        continue;
+                    }
+                    column.set_block(x, y, z, state);
+                }
+                for y in height + 1..=SEA_LEVEL.min(max_y - 1) {
+                    column.set_block(x, y, z, water);
+                }
+            }
+        }
+
+        // Biomes are stored per 4x4x4 cell, and picked at the center of each
+        for cell_z in 0..4 {
+            for cell_x in 0..4 {
+                let height = self.height(origin_x + cell_x * 4 + 2, origin_z + cell_z * 4 + 2);
+                let biome = self.biome(height);
+                for y in (min_y..max_y).step_by(4) {
+                    column.set_biome(cell_x * 4, y, cell_z * 4, biome);
+                }
+            }
+        }
+    }
+
+    fn surface_y(&self, x: i32, z: i32) -> Option<i32> {
+        Some(self.height(x, z).max(SEA_LEVEL) + 1)
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, from: f64, to: f64) -> f64 {
+    from + t * (to - from)
+}
+
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let hash = hash & 15;
+    let u = if hash < 8 { x } else { y };
+    let v = if hash < 4 { y } else if hash == 12 || hash == 14 { x } else { z };
+    (if hash & 1 == 0 { u } else { -u }) + (if hash & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generation::ChunkGenerator;
+    use crate::block::Block;
+    use crate::generation::noise::{NoiseGenerator, PerlinNoise, SEA_LEVEL, SeedRandom, TerrainBiomes};
+    use crate::region::column::ChunkColumn;
+
+    const BIOMES: TerrainBiomes = TerrainBiomes { land: 1, beach: 2, ocean: 3 };
+
+    #[test]
+    fn test_random_is_stable() {
+        let mut random = SeedRandom::new(0);
+        assert_eq!(random.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(random.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+    }
+
+    #[test]
+    fn test_perlin_range() {
+        let noise = PerlinNoise::new(&mut SeedRandom::new(42));
+        for index in 0..10_000 {
+            let value = noise.sample(index as f64 * 0.37, index as f64 * 0.11, index as f64 * 0.73);
+            assert!((-1.1..=1.1).contains(&value), "{} is out of range", value);
+        }
+    }
+
+    #[test]
+    fn test_heights_depend_on_seed_only() {
+        let first = NoiseGenerator::new(1234, BIOMES);
+        let again = NoiseGenerator::new(1234, BIOMES);
+        let other = NoiseGenerator::new(4321, BIOMES);
+        let positions: Vec<_> = (-64..64).step_by(7).flat_map(|x| (-64..64).step_by(5).map(move |z| (x, z))).collect();
+        for (x, z) in &positions {
+            assert_eq!(first.height(*x, *z), again.height(*x, *z));
+        }
+        assert!(positions.iter().any(|(x, z)| first.height(*x, *z) != other.height(*x, *z)));
+    }
+
+    #[test]
+    fn test_generation_is_deterministic() {
+        let generator = NoiseGenerator::new(-20, BIOMES);
+        let mut first = ChunkColumn::new(3, -2, -64, 384, 0);
+        let mut second = ChunkColumn::new(3, -2, -64, 384, 0);
+        generator.generate(&mut first);
+        NoiseGenerator::new(-20, BIOMES).generate(&mut second);
+        for y in -64..320 {
+            for z in 0..16 {
+                for x in 0..16 {
+                    assert_eq!(first.get_block(x, y, z), second.get_block(x, y, z));
+                }
+            }
+        }
+        assert!(first.get_block(0, -64, 0) != first.get_block(0, 319, 0));
+    }
+
+    #[test]
+    fn test_surface_rules() {
+        let generator = NoiseGenerator::new(99, BIOMES);
+        // Block columns at the center of a biome cell, where the biome is picked
+        let positions: Vec<_> = (-1024..1024).step_by(12)
+            .flat_map(|x| (-1024..1024).step_by(12).map(move |z| (x + 2, z + 2)))
+            .collect();
+        let find = |is_kind: &dyn Fn(i32) -> bool| {
+            positions.iter().copied()
+                .find(|(x, z)| {
+                    let height = generator.height(*x, *z);
+                    is_kind(height) && !generator.is_cave(*x, height, *z) && !generator.is_cave(*x, height - 1, *z)
+                })
+                .expect("no such block column near the origin")
+        };
+        let generate = |x: i32, z: i32| {
+            let mut column = ChunkColumn::new(x >> 4, z >> 4, -64, 384, 0);
+            generator.generate(&mut column);
+            column
+        };
+
+        let (x, z) = find(&|height| height == SEA_LEVEL || height == SEA_LEVEL + 1);
+        let (height, column) = (generator.height(x, z), generate(x, z));
+        assert_eq!(column.get_block(x & 15, height, z & 15).block(), Block::Sand);
+        assert_eq!(column.get_block(x & 15, height - 1, z & 15).block(), Block::Sand);
+        assert!(column.get_block(x & 15, height + 1, z & 15).is_air());
+        assert_eq!(column.get_biome(x & 15, height, z & 15), BIOMES.beach);
+
+        let (x, z) = find(&|height| height < SEA_LEVEL - 1);
+        let (height, column) = (generator.height(x, z), generate(x, z));
+        assert_eq!(column.get_block(x & 15, height, z & 15).block(), Block::Sand);
+        assert_eq!(column.get_block(x & 15, height + 1, z & 15).block(), Block::Water);
+        assert_eq!(column.get_block(x & 15, SEA_LEVEL, z & 15).block(), Block::Water);
+        assert!(column.get_block(x & 15, SEA_LEVEL + 1, z & 15).is_air());
+        assert_eq!(column.get_biome(x & 15, height, z & 15), BIOMES.ocean);
+
+        let (x, z) = find(&|height| height > SEA_LEVEL + 1);
+        let (height, column) = (generator.height(x, z), generate(x, z));
+        assert_eq!(column.get_block(x & 15, height, z & 15).block(), Block::GrassBlock);
+        assert_eq!(column.get_block(x & 15, height - 1, z & 15).block(), Block::Dirt);
+        assert!(column.get_block(x & 15, height + 1, z & 15).is_air());
+        assert_eq!(column.get_block(x & 15, -64, z & 15).block(), Block::Bedrock);
+        assert_eq!(column.get_biome(x & 15, height, z & 15), BIOMES.land);
+    }
+}
@@ -0,0 +1,10 @@
+use crate::generation::ChunkGenerator;
+use crate::region::column::ChunkColumn;
+
+/// Leaves every column empty
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VoidGenerator;
+
+impl ChunkGenerator for VoidGenerator {
+    fn generate(&self, _column: &mut ChunkColumn) {}
+}
@@ -17,10 +17,12 @@ use crate::server::server::Server;
 pub use protocol_derive::*;
 use crate::app::setup_netherite_app;
 
+mod block;
 mod config;
 mod dimension;
 mod encryption;
 mod game_mode;
+mod generation;
 mod client;
 mod packets;
 mod region;
@@ -1,37 +1,40 @@
 use std::sync::Arc;
-use crate::client::client::Client;
-use crate::packets::login::{EncryptionResponse, LoginSuccess};
+use crate::client::client::{Client, LoginState};
+use crate::packets::handler::login::{expect_state, finish_authentication};
+use crate::packets::handler::login::error::LoginError;
+use crate::packets::login::EncryptionResponse;
 use crate::server::server::Server;
 
 pub(crate) async fn handle_encryption_response(
     packet: EncryptionResponse,
     client: &mut Client,
     server: Arc<Server>,
-) {
-    let shared_secret = server.encryption().decrypt(&packet.shared_secret).unwrap();
-    let verify_token = packet.verify_token;
-    let verify_token = server.encryption().decrypt(&verify_token).unwrap();
-    if !server.encryption().compare_verify_tokens(&verify_token) {
-        panic!("verify tokens do not match.");
+) -> Result<(), LoginError> {
+    expect_state(client, LoginState::AwaitingEncryption, "EncryptionResponse")?;
+    let shared_secret = server.encryption().decrypt(&packet.shared_secret)
+        .map_err(|_| LoginError::DecryptionFailed)?;
+    let verify_token = server.encryption().decrypt(&packet.verify_token)
+        .map_err(|_| LoginError::DecryptionFailed)?;
+    if client.verify_token().as_ref() != Some(&verify_token) {
+        return Err(LoginError::VerifyTokenMismatch);
     }
+    let secret_length = shared_secret.len();
+    let shared_secret: [u8; 16] = shared_secret.try_into()
+        .map_err(|_| LoginError::InvalidSharedSecret(secret_length))?;
 
-    client.enable_encryption(shared_secret.try_into().unwrap());
+    client.enable_encryption(shared_secret);
     let hex = auth::generate_server_hash(
         &server.encryption().public_key_encoded(),
-        &client.encryption().as_ref().unwrap().secret(),
+        &shared_secret,
     );
     let profile = auth::authenticate(
         client.player_name().as_ref().unwrap(),
         &hex,
         server.properties().server().address(),
-    ).await;
+    ).await.map_err(|_| LoginError::AuthenticationFailed)?;
 
-    client.enable_compression(*server.properties().server().compression_threshold())
-        .await;
+    server.profile_cache().insert(&profile);
+    Server::save_profile_cache(&server);
 
-    let success = LoginSuccess { profile };
-    client.send_packet(&success).await.unwrap();
-    client.set_profile(success.profile);
-
-    Server::finish_login(server, client).await;
+    finish_authentication(profile, client, server).await
 }
@@ -0,0 +1,114 @@
+use std::fmt;
+use std::fmt::Formatter;
+
+use chat::style::NamedTextColor;
+use chat::text_component::TextComponent;
+
+use crate::client::client::LoginState;
+use crate::server::access;
+use crate::server::access::{AccessDenied, BanDetails};
+
+/// A reason for aborting the login sequence of a client. Every variant is
+/// reported back to the client through a `DisconnectLogin` packet.
+#[derive(Debug)]
+pub enum LoginError {
+    /// A packet arrived that is not valid in the current [LoginState]
+    UnexpectedPacket { packet: &'static str, state: LoginState },
+    /// A packet ID that does not exist in the login stage
+    UnknownPacket(i32),
+    /// The shared secret or verify token could not be decrypted
+    DecryptionFailed,
+    /// The decrypted shared secret is not 16 bytes long
+    InvalidSharedSecret(usize),
+    /// The verify token sent back does not match the one issued to the client
+    VerifyTokenMismatch,
+    /// The session server did not confirm that the player has joined
+    AuthenticationFailed,
+    /// The name in `LoginStart` is not a valid player name
+    InvalidName(String),
+    /// The UUID in `LoginStart` does not match the authenticated profile
+    UuidMismatch,
+    /// A session with the same UUID is connected, and the duplicate login policy rejects new ones
+    AlreadyLoggedIn,
+    /// The player or their address is banned, or the player is not whitelisted
+    AccessDenied(AccessDenied),
+    /// A plugin response arrived for a message that was never sent
+    UnknownPluginMessage(i32),
+}
+
+impl LoginError {
+    /// The message shown to the client on the disconnect screen
+    pub fn reason(&self) -> TextComponent {
+        if let LoginError::AccessDenied(AccessDenied::Banned(details) | AccessDenied::IpBanned(details)) = self {
+            return ban_reason(self.to_string(), details);
+        }
+        TextComponent::builder()
+            .text(self.to_string())
+            .color(&NamedTextColor::Red)
+            .build()
+    }
+}
+
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::UnexpectedPacket { packet, state } => {
+                write!(f, "Unexpected {} packet while {}", packet, state)
+            }
+            LoginError::UnknownPacket(id) => write!(f, "Unknown login packet 0x{:02X}", id),
+            LoginError::DecryptionFailed => write!(f, "Failed to decrypt the encryption response"),
+            LoginError::InvalidSharedSecret(len) => {
+                write!(f, "Invalid shared secret length. Expected 16 bytes, found {}", len)
+            }
+            LoginError::VerifyTokenMismatch => write!(f, "Verify tokens do not match"),
+            LoginError::AuthenticationFailed => write!(f, "Failed to verify username!"),
+            LoginError::InvalidName(name) => write!(f, "Invalid player name: {:?}", name),
+            LoginError::UuidMismatch => write!(f, "Your UUID does not match your profile"),
+            LoginError::AlreadyLoggedIn => write!(f, "You are already logged in from another location"),
+            LoginError::AccessDenied(AccessDenied::Banned(_)) => write!(f, "You are banned from this server."),
+            LoginError::AccessDenied(AccessDenied::IpBanned(_)) => write!(f, "Your IP address is banned from this server."),
+            LoginError::AccessDenied(AccessDenied::NotWhitelisted) => write!(f, "You are not whitelisted on this server!"),
+            LoginError::UnknownPluginMessage(id) => {
+                write!(f, "Received a response to an unknown plugin message ({})", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoginError {}
+
+fn ban_reason(title: String, details: &BanDetails) -> TextComponent {
+    let mut builder = TextComponent::builder()
+        .text(title)
+        .color(&NamedTextColor::Red)
+        .push_child(TextComponent::builder()
+            .text(format!("\nReason: {}", details.reason))
+            .color(&NamedTextColor::Gray)
+            .build());
+    if let Some(expires) = details.expires {
+        let remaining = expires.saturating_sub(access::now());
+        builder = builder.push_child(TextComponent::builder()
+            .text(format!("\nYour ban will be removed in {}", format_duration(remaining)))
+            .color(&NamedTextColor::Gray)
+            .build());
+    }
+    builder.build()
+}
+
+/// Formats a number of seconds as e.g. "2d 3h 15m"
+fn format_duration(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = (seconds % 3600 / 60).max(if days == 0 && hours == 0 { 1 } else { 0 });
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{}d", days));
+    }
+    if hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if minutes > 0 {
+        parts.push(format!("{}m", minutes));
+    }
+    parts.join(" ")
+}
@@ -1,50 +1,331 @@
 use std::sync::Arc;
 use bytebuffer::ByteBuffer;
 use auth::profile::GameProfile;
+use chat::text_component::TextComponent;
 use protocol::Serverbound;
-use crate::client::client::Client;
-use crate::packets::login::{EncryptionRequest, EncryptionResponse, LoginPluginResponse, LoginStart, LoginSuccess};
+use crate::client::client::{Client, ClientMessage, LoginState};
+use crate::encryption::server::ServerEncryption;
+use crate::packets::handler::login::error::LoginError;
+use crate::packets::login::{DisconnectLogin, EncryptionRequest, EncryptionResponse, LoginPluginResponse, LoginStart, LoginSuccess};
 use crate::server;
+use crate::server::player_count::{ConnectedPlayer, JoinOutcome};
 use crate::server::server::Server;
 
 mod encryption;
+pub mod error;
 
 pub async fn receive_login(id: i32, data: &mut ByteBuffer, client: &mut Client, server: Arc<Server>) {
-    if id == LoginStart::id() {
+    let result = if id == LoginStart::id() {
         let packet = LoginStart::read_packet(data);
-        handle_login_start(packet, client, server).await;
+        handle_login_start(packet, client, Arc::clone(&server)).await
     } else if id == EncryptionResponse::id() {
         let packet = EncryptionResponse::read_packet(data);
-        encryption::handle_encryption_response(packet, client, server).await;
+        encryption::handle_encryption_response(packet, client, Arc::clone(&server)).await
     } else if id == LoginPluginResponse::id() {
+        let packet = LoginPluginResponse::read_packet(data);
+        handle_plugin_response(packet, client, Arc::clone(&server)).await
+    } else {
+        Err(LoginError::UnknownPacket(id))
+    };
+    if let Err(why) = result {
+        disconnect(client, server.as_ref(), why).await;
+    }
+}
+
+/// Kicks the client from the login stage with the reason described by `why`.
+pub(crate) async fn disconnect(client: &mut Client, server: &Server, why: LoginError) {
+    let _ = client.send_packet(&DisconnectLogin { reason: why.reason() }).await;
+    client.close_connection(server).await;
+}
 
+/// Fails with [LoginError::UnexpectedPacket] unless the client is in the `expected` state.
+pub(crate) fn expect_state(client: &Client, expected: LoginState, packet: &'static str) -> Result<(), LoginError> {
+    if client.login_state() != expected {
+        return Err(LoginError::UnexpectedPacket { packet, state: client.login_state() });
     }
+    Ok(())
 }
 
 async fn handle_login_start(
     packet: LoginStart,
     client: &mut Client,
     server: Arc<Server>,
-) {
+) -> Result<(), LoginError> {
+    expect_state(client, LoginState::AwaitingStart, "LoginStart")?;
+    server.access().check_ip(&client.address().ip())
+        .map_err(LoginError::AccessDenied)?;
     let name = packet.name;
+    if !auth::profile::is_valid_name(&name) {
+        return Err(LoginError::InvalidName(name));
+    }
+    client.set_claimed_uuid(packet.uuid.0);
     let public_key = server.encryption().public_key.clone();
     // let public_key = RsaPublicKey::from_public_key_der(public_key.as_slice()).unwrap();
     client.set_public_key(public_key);
+    client.set_player_name(name.clone());
     if *server.properties().server().online_mode() {
-        client.send_packet(&EncryptionRequest {
+        let verify_token = ServerEncryption::generate_verify_token();
+        let request = EncryptionRequest {
             server_id: "".to_string(),
             public_key: server.encryption().public_key_encoded(),
-            verify_token: server.encryption().verify_token(),
-        }).await.unwrap();
-    } else {
-        client.enable_compression(*server.properties().server().compression_threshold())
-            .await;
-        let success = LoginSuccess {
-            profile: GameProfile::offline(&name),
+            verify_token: verify_token.clone(),
         };
-        client.send_packet(&success).await.unwrap();
-        client.set_profile(success.profile);
-        Server::finish_login(server, client).await;
+        if client.send_packet(&request).await.is_err() {
+            // The client is gone, so there is nobody to send a disconnect to
+            client.close_connection(server.as_ref()).await;
+            return Ok(());
+        }
+        client.set_verify_token(verify_token);
+        client.set_login_state(LoginState::AwaitingEncryption);
+        Ok(())
+    } else {
+        let profile = offline_profile(&name, &server).await;
+        finish_authentication(profile, client, server).await
+    }
+}
+
+/// Creates the profile of an offline-mode player. If offline skins are
+/// enabled, the skin of the premium account with the same name is attached.
+async fn offline_profile(name: &str, server: &Arc<Server>) -> GameProfile {
+    let mut profile = GameProfile::offline(name);
+    let profiles = server.properties().profiles();
+    if *profiles.offline_skins() {
+        match server.profile_cache().fetch(profiles.endpoint(), name).await {
+            Ok(Some(premium)) => {
+                profile.properties = premium.properties;
+                Server::save_profile_cache(server);
+            }
+            Ok(None) => {}
+            Err(why) => eprintln!("failed to fetch the skin of {}: {}", name, why),
+        }
+    }
+    profile
+}
+
+async fn handle_plugin_response(
+    packet: LoginPluginResponse,
+    client: &mut Client,
+    server: Arc<Server>,
+) -> Result<(), LoginError> {
+    expect_state(client, LoginState::AwaitingPluginResponses, "LoginPluginResponse")?;
+    if !client.complete_plugin_request(packet.message_id.0) {
+        return Err(LoginError::UnknownPluginMessage(packet.message_id.0));
+    }
+    if !client.has_pending_plugin_requests() {
+        send_login_success(client, server).await;
+    }
+    Ok(())
+}
+
+/// Called once the player's profile is known. Registers the player,
+/// enables compression and either finishes the login or waits for pending
+/// plugin responses.
+pub(crate) async fn finish_authentication(
+    profile: GameProfile,
+    client: &mut Client,
+    server: Arc<Server>,
+) -> Result<(), LoginError> {
+    // Offline profiles never match the UUID a vanilla client sends
+    if *server.properties().server().online_mode() {
+        if let Some(claimed) = client.claimed_uuid() {
+            if *claimed != profile.id {
+                return Err(LoginError::UuidMismatch);
+            }
+        }
+    }
+    server.access().check_player(&profile.id)
+        .map_err(LoginError::AccessDenied)?;
+    let player = ConnectedPlayer::new(profile.name.clone(), *client.address(), client.packets().clone());
+    let policy = *server.properties().server().duplicate_login();
+    match server.players().player_joined(profile.id, player, policy) {
+        JoinOutcome::Joined => {}
+        JoinOutcome::Replaced(old) => {
+            for old in old {
+                let reason = TextComponent::plain("You logged in from another location");
+                let _ = old.messages().send(ClientMessage::Disconnect(reason));
+            }
+        }
+        JoinOutcome::Rejected => return Err(LoginError::AlreadyLoggedIn),
+    }
+    client.enable_compression(*server.properties().server().compression_threshold())
+        .await;
+    client.set_profile(profile);
+    if client.has_pending_plugin_requests() {
+        client.set_login_state(LoginState::AwaitingPluginResponses);
+    } else {
+        send_login_success(client, server).await;
+    }
+    Ok(())
+}
+
+async fn send_login_success(client: &mut Client, server: Arc<Server>) {
+    let profile = client.take_profile().expect("profile must be set before LoginSuccess");
+    let success = LoginSuccess { profile };
+    let sent = client.send_packet(&success).await;
+    client.set_profile(success.profile);
+    if sent.is_err() {
+        client.close_connection(server.as_ref()).await;
+        return;
+    }
+    client.set_login_state(LoginState::Done);
+    Server::finish_login(server, client).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bytebuffer::ByteBuffer;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    use protocol::codec::write_packet;
+    use protocol::fields::io_ext::PacketWriterExt;
+    use protocol::fields::key::Key;
+    use protocol::fields::numeric::VarInt;
+    use protocol::Serverbound;
+
+    use crate::client::client::{Client, ClientMessage, LoginState, ProtocolStage};
+    use crate::packets::handler::login::error::LoginError;
+    use crate::packets::handler::login::{expect_state, receive_login};
+    use crate::packets::login::{DisconnectLogin, EncryptionResponse, LoginPluginResponse, LoginStart};
+    use crate::server::server::{Server, test_server};
+    use crate::util::test_directory;
+
+    struct TestClient {
+        client: Client,
+        // The client's end of the connection
+        peer: TcpStream,
+        _messages: UnboundedReceiver<ClientMessage>,
+    }
+
+    async fn connect() -> TestClient {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (socket, address) = listener.accept().await.unwrap();
+        let (messages, receiver) = unbounded_channel();
+        let mut client = Client::new(socket, address, messages);
+        client.set_stage(ProtocolStage::Login);
+        TestClient { client, peer, _messages: receiver }
+    }
+
+    async fn receive(test: &mut TestClient, server: &Arc<Server>, id: i32, mut data: ByteBuffer) {
+        receive_login(id, &mut data, &mut test.client, Arc::clone(server)).await;
+    }
+
+    // Everything the client was sent, once the connection is closed
+    async fn sent(test: &mut TestClient) -> Vec<u8> {
+        let mut data = Vec::new();
+        test.peer.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    fn disconnect_packet(why: LoginError, compression_threshold: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_packet(&DisconnectLogin { reason: why.reason() }, &mut data, compression_threshold).unwrap();
+        data
+    }
+
+    fn login_start(name: &str) -> ByteBuffer {
+        let mut data = ByteBuffer::new();
+        data.write_utf8(&name.to_string()).unwrap();
+        // No UUID
+        data.write_bool(false).unwrap();
+        data
+    }
+
+    fn encryption_response() -> ByteBuffer {
+        let mut data = ByteBuffer::new();
+        data.write_field(&vec![0_u8; 16]).unwrap();
+        data.write_field(&vec![0_u8; 4]).unwrap();
+        data
+    }
+
+    fn plugin_response(message_id: i32) -> ByteBuffer {
+        let mut data = ByteBuffer::new();
+        data.write_varint(&VarInt(message_id)).unwrap();
+        data.write_bool(false).unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_expect_state() {
+        let test = connect().await;
+        assert!(expect_state(&test.client, LoginState::AwaitingStart, "LoginStart").is_ok());
+        let why = expect_state(&test.client, LoginState::AwaitingEncryption, "EncryptionResponse").unwrap_err();
+        assert!(matches!(why, LoginError::UnexpectedPacket { packet: "EncryptionResponse", state: LoginState::AwaitingStart }));
+        assert_eq!(why.to_string(), "Unexpected EncryptionResponse packet while awaiting login start");
+    }
+
+    #[tokio::test]
+    async fn test_errors_disconnect() {
+        let run_directory = test_directory("login-errors");
+        let server = Arc::new(test_server(run_directory.path()));
+
+        let mut test = connect().await;
+        receive(&mut test, &server, EncryptionResponse::id(), encryption_response()).await;
+        assert!(test.client.is_closed());
+        let why = LoginError::UnexpectedPacket { packet: "EncryptionResponse", state: LoginState::AwaitingStart };
+        assert_eq!(sent(&mut test).await, disconnect_packet(why, -1));
+
+        let mut test = connect().await;
+        receive(&mut test, &server, 0x05, ByteBuffer::new()).await;
+        assert!(test.client.is_closed());
+        assert_eq!(sent(&mut test).await, disconnect_packet(LoginError::UnknownPacket(0x05), -1));
+
+        let mut test = connect().await;
+        receive(&mut test, &server, LoginStart::id(), login_start("not a name")).await;
+        assert!(test.client.is_closed());
+        let why = LoginError::InvalidName("not a name".to_string());
+        assert_eq!(sent(&mut test).await, disconnect_packet(why, -1));
+    }
+
+    #[tokio::test]
+    async fn test_plugin_requests() {
+        let run_directory = test_directory("login-plugins");
+        let server = Arc::new(test_server(run_directory.path()));
+        let mut test = connect().await;
+        let first = test.client.send_login_plugin_request(Key::new("test", "first"), vec![1]).await.unwrap();
+        let second = test.client.send_login_plugin_request(Key::new("test", "second"), vec![2]).await.unwrap();
+        assert_ne!(first, second);
+
+        // Once the player is known, the login waits for the responses
+        receive(&mut test, &server, LoginStart::id(), login_start("Steve")).await;
+        assert_eq!(test.client.login_state(), LoginState::AwaitingPluginResponses);
+        assert!(matches!(test.client.stage(), ProtocolStage::Login));
+
+        // Which may come in any order
+        receive(&mut test, &server, LoginPluginResponse::id(), plugin_response(second)).await;
+        assert_eq!(test.client.login_state(), LoginState::AwaitingPluginResponses);
+        assert!(test.client.has_pending_plugin_requests());
+        receive(&mut test, &server, LoginPluginResponse::id(), plugin_response(first)).await;
+        assert_eq!(test.client.login_state(), LoginState::Done);
+        assert!(matches!(test.client.stage(), ProtocolStage::Play));
+        assert!(!test.client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_plugin_response() {
+        let run_directory = test_directory("login-unknown-plugin");
+        let server = Arc::new(test_server(run_directory.path()));
+        let mut test = connect().await;
+        let message_id = test.client.send_login_plugin_request(Key::new("test", "channel"), Vec::new()).await.unwrap();
+
+        // Responses are only expected once the player is known
+        receive(&mut test, &server, LoginPluginResponse::id(), plugin_response(message_id)).await;
+        assert!(test.client.is_closed());
+        let why = LoginError::UnexpectedPacket { packet: "LoginPluginResponse", state: LoginState::AwaitingStart };
+        assert!(sent(&mut test).await.ends_with(&disconnect_packet(why, -1)));
+
+        let mut test = connect().await;
+        let message_id = test.client.send_login_plugin_request(Key::new("test", "channel"), Vec::new()).await.unwrap();
+        receive(&mut test, &server, LoginStart::id(), login_start("Alex")).await;
+        receive(&mut test, &server, LoginPluginResponse::id(), plugin_response(message_id + 1)).await;
+        assert!(test.client.is_closed());
+        // Compression is enabled once the player is known
+        let threshold = *server.properties().server().compression_threshold() as i32;
+        let why = LoginError::UnknownPluginMessage(message_id + 1);
+        assert!(sent(&mut test).await.ends_with(&disconnect_packet(why, threshold)));
     }
-    client.set_player_name(name);
 }
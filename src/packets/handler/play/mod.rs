@@ -2,37 +2,61 @@ use std::sync::Arc;
 use std::sync::atomic::Ordering::SeqCst;
 use bytebuffer::ByteBuffer;
 use enum_utils::OrdinalEnum;
-use nbt::Blob;
-use protocol::fields::key::Key;
 use protocol::fields::numeric::VarInt;
 use protocol::fields::position::Position;
+use protocol::Serverbound;
 use crate::client::client::{Client, ProtocolStage};
 use crate::packets::login::LoginPlay;
+use crate::packets::play::{ClientInformation, ConfirmTeleportation, SetPlayerPosition, SetPlayerPositionAndRotation};
 use crate::server::server;
 use crate::server::server::Server;
+use crate::world::player::{PlayerConnection, PlayerPosition, Session};
+use crate::world::worlds::{DEFAULT_WORLD, PlayerEvent, Worlds};
 
-pub async fn receive_play(id: i32, data: &mut ByteBuffer, client: &mut Client, server: Arc<Server>) {}
+pub async fn receive_play(id: i32, data: &mut ByteBuffer, client: &mut Client, server: Arc<Server>) {
+    let Some(profile) = client.profile() else {
+        return;
+    };
+    let session = Session::new(profile.id, *client.address());
+    let event = if id == ConfirmTeleportation::id() {
+        let packet = ConfirmTeleportation::read_packet(data);
+        PlayerEvent::ConfirmTeleport(session, packet.teleport_id.0)
+    } else if id == ClientInformation::id() {
+        let packet = ClientInformation::read_packet(data);
+        PlayerEvent::ViewDistance(session, packet.view_distance())
+    } else if id == SetPlayerPosition::id() {
+        let packet = SetPlayerPosition::read_packet(data);
+        PlayerEvent::Move(session, PlayerPosition::new(packet.x, packet.feet_y, packet.z))
+    } else if id == SetPlayerPositionAndRotation::id() {
+        let packet = SetPlayerPositionAndRotation::read_packet(data);
+        PlayerEvent::Move(session, PlayerPosition::new(packet.x, packet.feet_y, packet.z))
+    } else {
+        return;
+    };
+    let _ = server.player_events().send(event);
+}
 
-pub(crate) async fn join_player(client: &mut Client,
-                                server: Arc<Server>) {
-    let mut registry_codec = ByteBuffer::from_bytes(
-        include_bytes!("../../../../codecs/1.19.4/registry-1.19.4.nbt")
-    );
-    let registry_codec = Blob::from_reader(&mut registry_codec).unwrap();
-    let play = LoginPlay {
+/// Lets the player into the default world at the next tick, which sends
+/// them the Login (play) packet and streams their chunks
+pub(crate) fn join_player(client: &Client, server: &Server) {
+    let profile = client.profile().as_ref().expect("players have a profile once they join");
+    let connection = PlayerConnection::new(profile.id, *client.address(), client.packets().clone());
+    let _ = server.player_events().send(PlayerEvent::Join(connection));
+}
+
+/// The Login (play) packet that puts a joining player in the default world
+pub(crate) fn login_play(server: &Server, worlds: &Worlds) -> LoginPlay {
+    let info = worlds.info(DEFAULT_WORLD).expect("the default world exists");
+    LoginPlay {
         entity_id: server::ENTITY_ID_COUNTER.fetch_add(1, SeqCst) as i32,
         is_hardcore: false,
         game_mode: server.properties().game().default_gamemode().ordinal() as u8,
         previous_gamemode: -1,
-        dimension_names: vec![
-            Key::new("dimension", "world"),
-            Key::new("dimension", "world_nether"),
-            Key::new("dimension", "world_the_end"),
-        ],
-        dimesion_codec: registry_codec,
-        dimension_type: Key::minecraft("overworld"),
-        dimension_name: Key::new("dimension", "world"),
-        hashed_seed: -20,
+        dimension_names: worlds.names().cloned().collect(),
+        dimesion_codec: server.registry_blob(),
+        dimension_type: info.dimension_type_name().clone(),
+        dimension_name: info.name().clone(),
+        hashed_seed: info.hashed_seed(),
         max_players: VarInt(*server.properties().status().max_players() as i32),
         view_distance: VarInt(*server.properties().game().view_distance() as i32),
         simulation_distance: VarInt(*server.properties().game().simulation_disance() as i32),
@@ -41,14 +65,11 @@ pub(crate) async fn join_player(client: &mut Client,
         is_debug: false,
         is_flat: false,
         has_death_location: true,
-        death_dimension_name: Some(Key::new("dimension", "world")),
+        death_dimension_name: Some(info.name().clone()),
         death_location: Some(Position {
             x: 10,
             y: 10,
             z: 10,
         }),
-    };
-    print!("Still !Finished!");
-    client.send_packet(&play).await.unwrap();
-    print!("Finished!");
-}
\ No newline at end of file
+    }
+}
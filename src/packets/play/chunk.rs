@@ -8,32 +8,154 @@ use protocol::fields::numeric::{BitSet, VarInt};
 use protocol::fields::PacketField;
 use protocol::fields::io_ext::{PacketReaderExt, PacketWriterExt};
 
+use crate::packets::play::section::LIGHT_LENGTH;
+use crate::region::column::ChunkColumn;
+
 #[derive(Clientbound)]
-#[packet(id = 0x21)]
+#[packet(id = 0x24)]
 pub struct ChunkPacket {
     pub chunk_x: i32,
     pub chunk_z: i32,
-    pub heightmaps: Value,
+    pub heightmaps: Blob,
     pub data: Vec<u8>,
-    pub block_entities: Vec<u8>,
+    pub block_entities: Vec<BlockEntity>,
+    pub light: LightData,
+}
+
+impl ChunkPacket {
+    /// Serializes the sections, block entities and light of the column
+    pub fn from_column(column: &ChunkColumn) -> Self {
+        let mut heightmaps = Blob::new();
+        heightmaps.insert("MOTION_BLOCKING", Value::LongArray(column.heightmap()))
+            .expect("failed to write the heightmap");
+        let mut data = Vec::new();
+        for section in column.sections() {
+            data.write_i16::<BigEndian>(section.block_count()).expect("failed to write the block count");
+            section.blocks().write_to(&mut data).expect("failed to write the block states");
+            section.biomes().write_to(&mut data).expect("failed to write the biomes");
+        }
+        Self {
+            chunk_x: column.x(),
+            chunk_z: column.z(),
+            heightmaps,
+            data,
+            block_entities: column.block_entities().cloned().collect(),
+            light: LightData::from_column(column),
+        }
+    }
+}
+
+#[derive(Clientbound)]
+#[packet(id = 0x27)]
+pub struct UpdateLight {
+    pub chunk_x: VarInt,
+    pub chunk_z: VarInt,
+    pub light: LightData,
+}
+
+impl UpdateLight {
+    pub fn from_column(column: &ChunkColumn) -> Self {
+        Self {
+            chunk_x: VarInt(column.x()),
+            chunk_z: VarInt(column.z()),
+            light: LightData::from_column(column),
+        }
+    }
+}
+
+/// The light of a column, shared by the Chunk Data and Update Light packets.
+///
+/// The masks have one bit per section, plus one for the section below the
+/// column and one for the section above it. Sections whose light is all
+/// zero go in the empty masks instead of sending an array.
+pub struct LightData {
     pub trust_edges: bool,
     pub sky_light_mask: BitSet,
     pub block_light_mask: BitSet,
     pub empty_sky_light_mask: BitSet,
     pub empty_block_light_mask: BitSet,
-    pub sky_light: [u8; 2048],
-    pub block_light: [u8; 2048],
+    pub sky_light: Vec<LightEntry>,
+    pub block_light: Vec<LightEntry>,
+}
+
+impl LightData {
+    pub fn from_column(column: &ChunkColumn) -> Self {
+        let mut light = Self {
+            trust_edges: true,
+            sky_light_mask: BitSet::new(),
+            block_light_mask: BitSet::new(),
+            empty_sky_light_mask: BitSet::new(),
+            empty_block_light_mask: BitSet::new(),
+            sky_light: Vec::new(),
+            block_light: Vec::new(),
+        };
+        for (index, section) in column.sections().iter().enumerate() {
+            // The first bit is the section below the column
+            let bit = index + 1;
+            if let Some(sky_light) = section.sky_light() {
+                add_light(bit, sky_light, &mut light.sky_light_mask, &mut light.empty_sky_light_mask, &mut light.sky_light);
+            }
+            if let Some(block_light) = section.block_light() {
+                add_light(bit, block_light, &mut light.block_light_mask, &mut light.empty_block_light_mask, &mut light.block_light);
+            }
+        }
+        // The sky fully lights the section above the column
+        let sections = column.sections();
+        if sections.last().map_or(false, |section| section.sky_light().is_some()) {
+            let full = [0xFF; LIGHT_LENGTH];
+            add_light(sections.len() + 1, &full, &mut light.sky_light_mask, &mut light.empty_sky_light_mask, &mut light.sky_light);
+        }
+        light
+    }
+}
+
+fn add_light(bit: usize, light: &[u8], mask: &mut BitSet, empty_mask: &mut BitSet, entries: &mut Vec<LightEntry>) {
+    if light.iter().all(|byte| *byte == 0) {
+        empty_mask.set(bit, true);
+    } else {
+        mask.set(bit, true);
+        entries.push(LightEntry::new(light.to_vec()));
+    }
+}
+
+impl PacketField for LightData {
+    fn read_field<R: Read>(input: &mut R) -> std::io::Result<Self>
+        where
+            Self: Sized,
+    {
+        Ok(LightData {
+            trust_edges: input.read_field()?,
+            sky_light_mask: input.read_field()?,
+            block_light_mask: input.read_field()?,
+            empty_sky_light_mask: input.read_field()?,
+            empty_block_light_mask: input.read_field()?,
+            sky_light: input.read_field()?,
+            block_light: input.read_field()?,
+        })
+    }
+
+    fn write_field<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
+        output.write_field(&self.trust_edges)?;
+        output.write_field(&self.sky_light_mask)?;
+        output.write_field(&self.block_light_mask)?;
+        output.write_field(&self.empty_sky_light_mask)?;
+        output.write_field(&self.empty_block_light_mask)?;
+        output.write_field(&self.sky_light)?;
+        output.write_field(&self.block_light)?;
+        Ok(())
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct BlockEntity {
     section: u8,
-    y: u16,
+    y: i16,
     block_type: VarInt,
     data: Blob,
 }
 
 impl BlockEntity {
-    pub fn new(block_x: u8, block_z: u8, y: u16, block_type: VarInt, data: Blob) -> Self {
+    pub fn new(block_x: u8, block_z: u8, y: i16, block_type: VarInt, data: Blob) -> Self {
         let section = ((block_x & 15) << 4) | (block_z & 15);
         Self {
             section,
@@ -42,6 +164,28 @@ impl BlockEntity {
             data,
         }
     }
+
+    /// The x relative to the chunk
+    pub fn x(&self) -> i32 {
+        (self.section >> 4) as i32
+    }
+
+    pub fn y(&self) -> i16 {
+        self.y
+    }
+
+    /// The z relative to the chunk
+    pub fn z(&self) -> i32 {
+        (self.section & 15) as i32
+    }
+
+    pub fn block_type(&self) -> VarInt {
+        self.block_type
+    }
+
+    pub fn data(&self) -> &Blob {
+        &self.data
+    }
 }
 
 impl PacketField for BlockEntity {
@@ -50,7 +194,7 @@ impl PacketField for BlockEntity {
             Self: Sized,
     {
         let section = input.read_u8().expect("failed to read 'section'");
-        let y = input.read_u16::<BigEndian>().expect("failed to read 'y'");
+        let y = input.read_i16::<BigEndian>().expect("failed to read 'y'");
         let block_type = input.read_varint().expect("failed to read 'block_type'");
         let data = input.read_field::<Blob>().expect("failed to read 'data'");
         Ok(BlockEntity {
@@ -63,7 +207,7 @@ impl PacketField for BlockEntity {
 
     fn write_field<W: Write>(&self, output: &mut W) -> std::io::Result<()> {
         output.write_u8(self.section)?;
-        output.write_u16::<BigEndian>(self.y)?;
+        output.write_i16::<BigEndian>(self.y)?;
         output.write_varint(&self.block_type)?;
         output.write_field(&self.data)?;
         Ok(())
@@ -76,8 +220,13 @@ pub struct LightEntry {
 
 impl LightEntry {
     pub fn new(light: Vec<u8>) -> Self {
+        debug_assert_eq!(light.len(), LIGHT_LENGTH);
         Self { light }
     }
+
+    pub fn light(&self) -> &Vec<u8> {
+        &self.light
+    }
 }
 
 impl PacketField for LightEntry {
@@ -95,3 +244,98 @@ impl PacketField for LightEntry {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{BigEndian, WriteBytesExt};
+    use protocol::codec::encode_packet;
+
+    use crate::block::Block;
+    use crate::packets::play::chunk::{ChunkPacket, LightData};
+    use crate::packets::play::section::LIGHT_LENGTH;
+    use crate::region::column::ChunkColumn;
+
+    /// Two sections: stone below y 16 and air above, in the biome with id 3.
+    /// The upper section has sky light and the lower one block light.
+    fn small_column() -> ChunkColumn {
+        let mut column = ChunkColumn::new(1, -2, 0, 32, 3);
+        column.fill(0, 15, Block::Stone.default_state());
+        column.reset_light(true);
+        column.set_sky_light_at(0, 20, 0, 15);
+        column.set_block_light_at(0, 2, 0, 7);
+        column
+    }
+
+    /// A bit set of one long, as the packet writes it
+    fn bit_set(bits: i64) -> Vec<u8> {
+        let mut bytes = vec![1];
+        bytes.write_i64::<BigEndian>(bits).unwrap();
+        bytes
+    }
+
+    /// A light array, prefixed with its length 2048 as a VarInt
+    fn light_array(light: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x80, 0x10];
+        bytes.extend_from_slice(light);
+        bytes
+    }
+
+    #[test]
+    fn test_light_masks() {
+        let light = LightData::from_column(&small_column());
+        // Bit 0 is the section below the column, bit 3 the one above it
+        assert!(light.trust_edges);
+        assert_eq!((0..4).map(|bit| light.sky_light_mask.get(bit)).collect::<Vec<_>>(), [false, false, true, true]);
+        assert_eq!((0..4).map(|bit| light.empty_sky_light_mask.get(bit)).collect::<Vec<_>>(), [false, true, false, false]);
+        assert_eq!((0..4).map(|bit| light.block_light_mask.get(bit)).collect::<Vec<_>>(), [false, true, false, false]);
+        assert_eq!((0..4).map(|bit| light.empty_block_light_mask.get(bit)).collect::<Vec<_>>(), [false, false, true, false]);
+        assert_eq!(light.sky_light.len(), 2);
+        assert_eq!(light.sky_light[1].light(), &vec![0xFF; LIGHT_LENGTH]);
+        assert_eq!(light.block_light.len(), 1);
+    }
+
+    #[test]
+    fn test_chunk_packet_bytes() {
+        let mut expected = vec![0x24];
+        expected.write_i32::<BigEndian>(1).unwrap();
+        expected.write_i32::<BigEndian>(-2).unwrap();
+
+        // The heightmap: 256 heights of 16, 6 bits each, 10 per long
+        expected.extend_from_slice(&[0x0A, 0, 0, 0x0C, 0, 15]);
+        expected.extend_from_slice(b"MOTION_BLOCKING");
+        expected.write_i32::<BigEndian>(26).unwrap();
+        let full: i64 = (0..10).map(|index| 16 << (index * 6)).sum();
+        let last: i64 = (0..6).map(|index| 16 << (index * 6)).sum();
+        for _ in 0..25 {
+            expected.write_i64::<BigEndian>(full).unwrap();
+        }
+        expected.write_i64::<BigEndian>(last).unwrap();
+        expected.push(0);
+
+        // Each section: the block count, then the single valued block and
+        // biome containers with no data
+        let stone = Block::Stone.default_state().id() as u8;
+        let sections = [0x10, 0x00, 0, stone, 0, 0, 3, 0, 0x00, 0x00, 0, 0, 0, 0, 3, 0];
+        expected.push(sections.len() as u8);
+        expected.extend_from_slice(&sections);
+        // No block entities
+        expected.push(0);
+
+        expected.push(1);
+        expected.extend(bit_set(0b1100));
+        expected.extend(bit_set(0b0010));
+        expected.extend(bit_set(0b0010));
+        expected.extend(bit_set(0b0100));
+        let mut sky_light = [0; LIGHT_LENGTH];
+        sky_light[4 * 128] = 0x0F;
+        let mut block_light = [0; LIGHT_LENGTH];
+        block_light[2 * 128] = 0x07;
+        expected.push(2);
+        expected.extend(light_array(&sky_light));
+        expected.extend(light_array(&[0xFF; LIGHT_LENGTH]));
+        expected.push(1);
+        expected.extend(light_array(&block_light));
+
+        assert_eq!(encode_packet(&ChunkPacket::from_column(&small_column())).unwrap(), expected);
+    }
+}
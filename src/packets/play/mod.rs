@@ -3,6 +3,7 @@ use protocol::{Clientbound, Serverbound};
 
 use chat::text_component::TextComponent;
 use protocol::fields::generic::Ordinal;
+use protocol::fields::key::Key;
 use protocol::fields::numeric::VarInt;
 use protocol::fields::position::Position;
 
@@ -19,6 +20,14 @@ pub struct DisconnectPlay {
     pub reason: TextComponent,
 }
 
+/// Sent by the client once it moved to the position of a Synchronize Player
+/// Position packet
+#[derive(Serverbound, Debug)]
+#[packet(id = 0x00)]
+pub struct ConfirmTeleportation {
+    pub teleport_id: VarInt,
+}
+
 #[derive(Serverbound, Debug)]
 #[packet(id = 0x08)]
 pub struct ClientInformation {
@@ -30,9 +39,79 @@ pub struct ClientInformation {
     main_hand: VarInt,
 }
 
+impl ClientInformation {
+    pub fn view_distance(&self) -> u8 {
+        self.view_distance
+    }
+}
+
+#[derive(Serverbound, Debug)]
+#[packet(id = 0x14)]
+pub struct SetPlayerPosition {
+    pub x: f64,
+    pub feet_y: f64,
+    pub z: f64,
+    pub on_ground: bool,
+}
+
+#[derive(Serverbound, Debug)]
+#[packet(id = 0x15)]
+pub struct SetPlayerPositionAndRotation {
+    pub x: f64,
+    pub feet_y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+#[derive(Clientbound, Debug)]
+#[packet(id = 0x1E)]
+pub struct UnloadChunk {
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+}
+
+#[derive(Clientbound, Debug)]
+#[packet(id = 0x4E)]
+pub struct SetCenterChunk {
+    pub chunk_x: VarInt,
+    pub chunk_z: VarInt,
+}
+
 #[derive(Clientbound, Debug)]
 #[packet(id = 0x50)]
 pub struct SetDefaultSpawnPosition {
     pub position: Position,
     pub angle: f32,
-}
\ No newline at end of file
+}
+#[derive(Clientbound, Debug)]
+#[packet(id = 0x3C)]
+pub struct SynchronizePlayerPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Which of the fields are relative, one bit each in the order above
+    pub flags: i8,
+    pub teleport_id: VarInt,
+}
+
+/// Moves the player to another dimension, or respawns them after death
+#[derive(Clientbound, Debug)]
+#[packet(id = 0x41)]
+pub struct Respawn {
+    pub dimension_type: Key,
+    pub dimension_name: Key,
+    pub hashed_seed: i64,
+    pub game_mode: u8,
+    pub previous_game_mode: i8,
+    pub is_debug: bool,
+    pub is_flat: bool,
+    /// 1 keeps the attributes, 2 keeps the entity metadata
+    pub data_kept: u8,
+    pub has_death_location: bool,
+    pub death_dimension_name: Option<Key>,
+    pub death_location: Option<Position>,
+}
@@ -1,9 +1,186 @@
-use std::ops::Index;
-use serde::{Deserialize, Serialize};
+use crate::block::BlockState;
+use crate::region::container::PalettedContainer;
+use crate::region::palette::{Palette, PaletteKind, PaletteMut};
 
-#[derive(Serialize, Deserialize)]
+/// The number of bytes of a section's light array: one nibble per block
+pub const LIGHT_LENGTH: usize = 2048;
+
+/// Whether the state id is air. Air blocks are not counted as blocks of
+/// the section, unknown states are.
+fn is_air(id: i32) -> bool {
+    BlockState::from_id(id).map_or(false, |state| state.is_air())
+}
+
+/// A 16x16x16 section of a chunk column
+#[derive(Debug, Clone)]
 pub struct Section {
-    #[serde(rename = "Y")]
-    y: i8,
-    // block_states: nbt
+    /// The section coordinate, i.e. the block y divided by 16
+    y: i32,
+    blocks: PalettedContainer,
+    biomes: PalettedContainer,
+    // The number of non-air blocks
+    block_count: i16,
+    // None when the light was never computed
+    sky_light: Option<Vec<u8>>,
+    block_light: Option<Vec<u8>>,
+    // Whether blocks changed since the section was last sent
+    changed: bool,
+}
+
+impl Section {
+    /// Creates a section filled with air and the given biome
+    pub fn new(y: i32, biome: i32) -> Self {
+        Self {
+            y,
+            blocks: PalettedContainer::blocks(BlockState::AIR.id()),
+            biomes: PalettedContainer::biomes(biome),
+            block_count: 0,
+            sky_light: None,
+            block_light: None,
+            changed: false,
+        }
+    }
+
+    /// Creates a section from existing containers, counting its blocks
+    pub fn from_containers(y: i32, blocks: PalettedContainer, biomes: PalettedContainer) -> Self {
+        let block_count = blocks.count(|state| !is_air(state)) as i16;
+        Self {
+            y,
+            blocks,
+            biomes,
+            block_count,
+            sky_light: None,
+            block_light: None,
+            changed: false,
+        }
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    /// Returns the block state at the given section-relative coordinates.
+    /// States unknown to the registry are read as air.
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> BlockState {
+        BlockState::from_id(self.blocks.get(x, y, z)).unwrap_or(BlockState::AIR)
+    }
+
+    /// Sets the block state at the given section-relative coordinates,
+    /// returning the previous one.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, state: BlockState) -> BlockState {
+        let previous = self.blocks.get(x, y, z);
+        if previous == state.id() {
+            return state;
+        }
+        self.blocks.set(x, y, z, state.id());
+        match (is_air(previous), state.is_air()) {
+            (true, false) => self.block_count += 1,
+            (false, true) => self.block_count -= 1,
+            _ => {}
+        }
+        self.changed = true;
+        BlockState::from_id(previous).unwrap_or(BlockState::AIR)
+    }
+
+    /// Sets every block of the section
+    pub fn fill(&mut self, state: BlockState) {
+        self.blocks.fill(state.id());
+        self.block_count = if state.is_air() { 0 } else { 4096 };
+        self.changed = true;
+    }
+
+    /// Returns the biome of the 4x4x4 cell containing the given
+    /// section-relative block coordinates
+    pub fn get_biome(&self, x: i32, y: i32, z: i32) -> i32 {
+        self.biomes.get(x >> 2, y >> 2, z >> 2)
+    }
+
+    pub fn set_biome(&mut self, x: i32, y: i32, z: i32, biome: i32) {
+        self.biomes.set(x >> 2, y >> 2, z >> 2, biome);
+        self.changed = true;
+    }
+
+    /// Sets the biome of every cell of the section
+    pub fn fill_biome(&mut self, biome: i32) {
+        self.biomes.fill(biome);
+        self.changed = true;
+    }
+
+    /// Sizes the biome container after the biome registry
+    pub fn set_biome_kind(&mut self, kind: PaletteKind) {
+        self.biomes.set_kind(kind);
+    }
+
+    pub fn block_count(&self) -> i16 {
+        self.block_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.block_count == 0
+    }
+
+    pub fn blocks(&self) -> &PalettedContainer {
+        &self.blocks
+    }
+
+    pub fn biomes(&self) -> &PalettedContainer {
+        &self.biomes
+    }
+
+    pub fn sky_light(&self) -> Option<&Vec<u8>> {
+        self.sky_light.as_ref()
+    }
+
+    pub fn block_light(&self) -> Option<&Vec<u8>> {
+        self.block_light.as_ref()
+    }
+
+    pub fn set_sky_light(&mut self, light: Option<Vec<u8>>) {
+        self.sky_light = light;
+    }
+
+    pub fn set_block_light(&mut self, light: Option<Vec<u8>>) {
+        self.block_light = light;
+    }
+
+    pub fn get_sky_light(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.sky_light.as_ref().map_or(0, |light| get_nibble(light, x, y, z))
+    }
+
+    pub fn get_block_light(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.block_light.as_ref().map_or(0, |light| get_nibble(light, x, y, z))
+    }
+
+    pub fn set_sky_light_at(&mut self, x: i32, y: i32, z: i32, level: u8) {
+        let light = self.sky_light.get_or_insert_with(|| vec![0; LIGHT_LENGTH]);
+        set_nibble(light, x, y, z, level);
+    }
+
+    pub fn set_block_light_at(&mut self, x: i32, y: i32, z: i32, level: u8) {
+        let light = self.block_light.get_or_insert_with(|| vec![0; LIGHT_LENGTH]);
+        set_nibble(light, x, y, z, level);
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub(crate) fn clear_changed(&mut self) {
+        self.changed = false;
+    }
+}
+
+fn light_index(x: i32, y: i32, z: i32) -> usize {
+    (((y & 15) << 8) | ((z & 15) << 4) | (x & 15)) as usize
+}
+
+fn get_nibble(light: &[u8], x: i32, y: i32, z: i32) -> u8 {
+    let index = light_index(x, y, z);
+    (light[index >> 1] >> ((index & 1) * 4)) & 15
+}
+
+fn set_nibble(light: &mut [u8], x: i32, y: i32, z: i32, level: u8) {
+    let index = light_index(x, y, z);
+    let shift = (index & 1) * 4;
+    light[index >> 1] = (light[index >> 1] & !(15 << shift)) | ((level & 15) << shift);
 }
@@ -1,6 +1,543 @@
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::block::BlockState;
+use crate::packets::play::chunk::BlockEntity;
+use crate::packets::play::section::{LIGHT_LENGTH, Section};
+use crate::region::container::PalettedContainer;
+use crate::region::data::{
+    BlockStateEntry, ByteArray, ChunkSection, DATA_VERSION, Heightmap, LevelChunk, LongArray, PalettedData,
+    RegionStatus, Structures, UnmodeledTags,
+};
+use crate::region::palette::{ceil_log2, PaletteKind};
+use crate::region::storage::PackedArray;
+
+/// A column of sections spanning the height of its dimension
+#[derive(Debug, Clone)]
 pub struct ChunkColumn {
     x: i32,
     z: i32,
     min_y: i32,
+    sections: Vec<Section>,
+    block_entities: HashMap<(i32, i32, i32), BlockEntity>,
+    // Counts the changes made to the column. Saves remember the count of
+    // the copy they wrote, to tell whether it changed since.
+    changes: u64,
+}
+
+impl ChunkColumn {
+    /// Creates an empty column for a dimension starting at `min_y` and
+    /// `height` blocks high. Both must be multiples of 16.
+    pub fn new(x: i32, z: i32, min_y: i32, height: i32, biome: i32) -> Self {
+        let min_section = min_y >> 4;
+        let sections = (0..height >> 4)
+            .map(|index| Section::new(min_section + index, biome))
+            .collect();
+        Self {
+            x,
+            z,
+            min_y,
+            sections,
+            block_entities: HashMap::new(),
+            changes: 0,
+        }
+    }
+
+    /// Creates a column from existing sections, ordered from the bottom up
+    pub fn from_sections(x: i32, z: i32, min_y: i32, sections: Vec<Section>) -> Self {
+        Self {
+            x,
+            z,
+            min_y,
+            sections,
+            block_entities: HashMap::new(),
+            changes: 0,
+        }
+    }
+
+    /// Converts a chunk loaded from an Anvil region file, for a dimension
+    /// starting at `min_y` and `height` blocks high. Sections outside of
+    /// the dimension are dropped, and missing ones are left empty.
+    ///
+    /// Block states unknown to the registry become air (see
+    /// [unknown_block_states]), and biomes for which `biome_id` returns
+    /// `None` become the biome with id 0. Block entities are not loaded yet,
+    /// as their types have no registry.
+    pub fn from_level_chunk(
+        chunk: &LevelChunk,
+        min_y: i32,
+        height: i32,
+        biome_id: impl Fn(&str) -> Option<i32>,
+    ) -> Result<Self> {
+        let mut column = ChunkColumn::new(chunk.x_pos, chunk.z_pos, min_y, height, 0);
+        for stored in &chunk.sections {
+            let y = stored.y as i32;
+            let Some(index) = column.section_index(y * 16) else {
+                continue;
+            };
+            column.sections[index] = convert_section(stored, &biome_id)?;
+        }
+        Ok(column)
+    }
+
+    /// Converts the column to a new Anvil chunk. Biomes for which
+    /// `biome_name` returns `None` are stored as plains.
+    pub fn to_level_chunk(&self, biome_name: impl Fn(i32) -> Option<String>) -> LevelChunk {
+        let mut chunk = LevelChunk {
+            data_version: DATA_VERSION,
+            x_pos: self.x,
+            y_pos: self.min_y >> 4,
+            z_pos: self.z,
+            status: RegionStatus::Full,
+            last_update: 0,
+            inhabited_time: 0,
+            is_light_on: false,
+            sections: Vec::new(),
+            block_entities: Vec::new(),
+            heightmaps: Heightmap::default(),
+            block_ticks: Vec::new(),
+            fluid_ticks: Vec::new(),
+            post_processing: Vec::new(),
+            structures: Structures::default(),
+            other: UnmodeledTags::default(),
+        };
+        self.write_level_chunk(&mut chunk, biome_name);
+        chunk
+    }
+
+    /// Writes the sections and heightmaps of the column over an Anvil chunk,
+    /// keeping its other data such as block entities and structures, which
+    /// are not loaded yet. The chunk is marked unlit, so vanilla relights it.
+    pub fn write_level_chunk(&self, chunk: &mut LevelChunk, biome_name: impl Fn(i32) -> Option<String>) {
+        chunk.data_version = DATA_VERSION;
+        chunk.y_pos = self.min_y >> 4;
+        chunk.status = RegionStatus::Full;
+        chunk.is_light_on = false;
+        chunk.sections = self.sections.iter()
+            .map(|section| store_section(section, &biome_name))
+            .collect();
+        let heights = LongArray(self.heightmap());
+        chunk.heightmaps = Heightmap {
+            motion_blocking: Some(heights.clone()),
+            world_surface: Some(heights),
+            ..Heightmap::default()
+        };
+        // Stale post-processing could refer to blocks that changed
+        chunk.post_processing.clear();
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z
+    }
+
+    pub fn min_y(&self) -> i32 {
+        self.min_y
+    }
+
+    pub fn height(&self) -> i32 {
+        self.sections.len() as i32 * 16
+    }
+
+    /// The lowest y above the column
+    pub fn max_y(&self) -> i32 {
+        self.min_y + self.height()
+    }
+
+    pub fn sections(&self) -> &Vec<Section> {
+        &self.sections
+    }
+
+    pub fn sections_mut(&mut self) -> &mut Vec<Section> {
+        self.changes += 1;
+        &mut self.sections
+    }
+
+    /// Returns the section containing the block y
+    pub fn section(&self, y: i32) -> Option<&Section> {
+        self.section_index(y).map(|index| &self.sections[index])
+    }
+
+    pub fn section_mut(&mut self, y: i32) -> Option<&mut Section> {
+        let index = self.section_index(y)?;
+        self.changes += 1;
+        Some(&mut self.sections[index])
+    }
+
+    /// Returns the block state at the given coordinates. x and z are
+    /// relative to the column, y is absolute. Air is returned outside of
+    /// the column.
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> BlockState {
+        self.section(y).map_or(BlockState::AIR, |section| section.get_block(x, y, z))
+    }
+
+    /// Sets the block state at the given coordinates, returning the previous
+    /// one, or `None` if y is outside of the column.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, state: BlockState) -> Option<BlockState> {
+        let index = self.section_index(y)?;
+        let previous = self.sections[index].set_block(x, y, z, state);
+        if previous != state {
+            self.changes += 1;
+        }
+        Some(previous)
+    }
+
+    pub fn get_biome(&self, x: i32, y: i32, z: i32) -> i32 {
+        let y = y.clamp(self.min_y, self.max_y() - 1);
+        self.section(y).map_or(0, |section| section.get_biome(x, y, z))
+    }
+
+    pub fn set_biome(&mut self, x: i32, y: i32, z: i32, biome: i32) {
+        if let Some(section) = self.section_mut(y) {
+            section.set_biome(x, y, z, biome);
+        }
+    }
+
+    pub fn fill_biome(&mut self, biome: i32) {
+        for section in &mut self.sections {
+            section.fill_biome(biome);
+        }
+        self.changes += 1;
+    }
+
+    /// Sizes the biome containers of every section after the biome registry
+    pub fn set_biome_kind(&mut self, kind: PaletteKind) {
+        for section in &mut self.sections {
+            section.set_biome_kind(kind);
+        }
+    }
+
+    /// Returns the sky light at the given coordinates, like [Self::get_block]
+    pub fn get_sky_light(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.section(y).map_or(0, |section| section.get_sky_light(x, y, z))
+    }
+
+    pub fn get_block_light(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.section(y).map_or(0, |section| section.get_block_light(x, y, z))
+    }
+
+    /// Sets the sky light at the given coordinates. Light is computed from
+    /// the blocks, so changing it doesn't count as a change.
+    pub fn set_sky_light_at(&mut self, x: i32, y: i32, z: i32, level: u8) {
+        if let Some(index) = self.section_index(y) {
+            self.sections[index].set_sky_light_at(x, y, z, level);
+        }
+    }
+
+    /// Fully lights every section by the sky
+    pub fn fill_sky_light(&mut self) {
+        for section in &mut self.sections {
+            section.set_sky_light(Some(vec![0xFF; LIGHT_LENGTH]));
+        }
+    }
+
+    pub fn set_block_light_at(&mut self, x: i32, y: i32, z: i32, level: u8) {
+        if let Some(index) = self.section_index(y) {
+            self.sections[index].set_block_light_at(x, y, z, level);
+        }
+    }
+
+    /// Darkens every section, before the light is computed again. Sky light
+    /// is only stored in dimensions with a sky.
+    pub fn reset_light(&mut self, has_skylight: bool) {
+        for section in &mut self.sections {
+            section.set_sky_light(has_skylight.then(|| vec![0; LIGHT_LENGTH]));
+            section.set_block_light(Some(vec![0; LIGHT_LENGTH]));
+        }
+    }
+
+    /// The y above the highest non-air block at the given column-relative
+    /// coordinates, or `None` if the column is empty there.
+    pub fn highest_block(&self, x: i32, z: i32) -> Option<i32> {
+        for section in self.sections.iter().rev().filter(|section| !section.is_empty()) {
+            for y in (0..16).rev() {
+                if !section.get_block(x, y, z).is_air() {
+                    return Some(section.y() * 16 + y + 1);
+                }
+            }
+        }
+        None
+    }
+
+    /// The heights above the highest blocks, relative to `min_y`, packed
+    /// like the heightmaps of Anvil and the Chunk Data packet.
+    pub fn heightmap(&self) -> Vec<i64> {
+        let mut heights = PackedArray::new(ceil_log2(self.height() as usize + 1), 256);
+        for z in 0..16 {
+            for x in 0..16 {
+                let height = self.highest_block(x, z).map_or(0, |y| y - self.min_y);
+                heights.set((z * 16 + x) as usize, height as u32);
+            }
+        }
+        heights.data().clone()
+    }
+
+    pub fn block_entities(&self) -> impl Iterator<Item=&BlockEntity> {
+        self.block_entities.values()
+    }
+
+    pub fn get_block_entity(&self, x: i32, y: i32, z: i32) -> Option<&BlockEntity> {
+        self.block_entities.get(&(x & 15, y, z & 15))
+    }
+
+    /// Adds a block entity, replacing the one at the same position
+    pub fn set_block_entity(&mut self, entity: BlockEntity) {
+        self.block_entities.insert((entity.x(), entity.y() as i32, entity.z()), entity);
+        self.changes += 1;
+    }
+
+    pub fn remove_block_entity(&mut self, x: i32, y: i32, z: i32) -> Option<BlockEntity> {
+        let removed = self.block_entities.remove(&(x & 15, y, z & 15));
+        if removed.is_some() {
+            self.changes += 1;
+        }
+        removed
+    }
+
+    /// Sets every block between `from_y` and `to_y`, both inclusive
+    pub fn fill(&mut self, from_y: i32, to_y: i32, state: BlockState) {
+        let from_y = from_y.max(self.min_y);
+        let to_y = to_y.min(self.max_y() - 1);
+        let mut y = from_y;
+        while y <= to_y {
+            let index = self.section_index(y).expect("y is inside the column");
+            let section = &mut self.sections[index];
+            if y & 15 == 0 && to_y >= y + 15 {
+                section.fill(state);
+            } else {
+                for layer in y..=to_y.min(y | 15) {
+                    for z in 0..16 {
+                        for x in 0..16 {
+                            section.set_block(x, layer, z, state);
+                        }
+                    }
+                }
+            }
+            y = (y | 15) + 1;
+        }
+        self.changes += 1;
+    }
+
+    /// The section coordinates of the sections that changed since the last
+    /// call, so they can be sent again.
+    pub fn take_changed_sections(&mut self) -> Vec<i32> {
+        self.sections.iter_mut()
+            .filter(|section| section.is_changed())
+            .map(|section| {
+                section.clear_changed();
+                section.y()
+            })
+            .collect()
+    }
+
+    /// The number of changes made to the column since it was created or
+    /// loaded. Light is computed from the blocks, so it does not count.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    fn section_index(&self, y: i32) -> Option<usize> {
+        if y < self.min_y || y >= self.max_y() {
+            return None;
+        }
+        Some(((y - self.min_y) >> 4) as usize)
+    }
+}
+
+/// The names of the block states of the chunk that the registry does not
+/// know, which are loaded as air. Saving over such a chunk would lose them.
+pub fn unknown_block_states(chunk: &LevelChunk) -> Vec<String> {
+    let mut unknown: Vec<String> = chunk.sections.iter()
+        .filter_map(|section| section.block_states.as_ref())
+        .flat_map(|states| &states.palette)
+        .filter(|entry| BlockState::from_properties(&entry.name, &entry.properties).is_none())
+        .map(|entry| entry.name.clone())
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    unknown
+}
+
+fn convert_section(stored: &ChunkSection, biome_id: &impl Fn(&str) -> Option<i32>) -> Result<Section> {
+    let y = stored.y as i32;
+    let blocks = match &stored.block_states {
+        Some(states) => {
+            let palette = states.palette.iter()
+                .map(|entry| BlockState::from_properties(&entry.name, &entry.properties)
+                    .unwrap_or(BlockState::AIR)
+                    .id())
+                .collect();
+            let data = states.data.as_ref().map(|data| data.0.clone());
+            PalettedContainer::from_anvil(PaletteKind::BLOCKS, palette, data)?
+        }
+        None => PalettedContainer::blocks(BlockState::AIR.id()),
+    };
+    let biomes = match &stored.biomes {
+        Some(biomes) => {
+            let palette = biomes.palette.iter()
+                .map(|name| biome_id(name).unwrap_or(0))
+                .collect();
+            let data = biomes.data.as_ref().map(|data| data.0.clone());
+            PalettedContainer::from_anvil(PaletteKind::BIOMES, palette, data)?
+        }
+        None => PalettedContainer::biomes(0),
+    };
+    let mut section = Section::from_containers(y, blocks, biomes);
+    section.set_sky_light(stored.sky_light.as_ref().and_then(light_array));
+    section.set_block_light(stored.block_light.as_ref().and_then(light_array));
+    Ok(section)
+}
+
+fn store_section(section: &Section, biome_name: &impl Fn(i32) -> Option<String>) -> ChunkSection {
+    let (palette, data) = section.blocks().to_anvil();
+    let block_states = PalettedData {
+        palette: palette.into_iter()
+            .map(|id| {
+                let state = BlockState::from_id(id).unwrap_or(BlockState::AIR);
+                BlockStateEntry {
+                    name: state.block().key().to_string(),
+                    properties: state.properties().into_iter()
+                        .map(|(name, value)| (name.name().to_string(), value.name().to_string()))
+                        .collect(),
+                }
+            })
+            .collect(),
+        data: data.map(LongArray),
+    };
+    let (palette, data) = section.biomes().to_anvil();
+    let biomes = PalettedData {
+        palette: palette.into_iter()
+            .map(|id| biome_name(id).unwrap_or_else(|| String::from("minecraft:plains")))
+            .collect(),
+        data: data.map(LongArray),
+    };
+    let signed = |light: &Vec<u8>| ByteArray(light.iter().map(|byte| *byte as i8).collect());
+    ChunkSection {
+        y: section.y() as i8,
+        block_states: Some(block_states),
+        biomes: Some(biomes),
+        block_light: section.block_light().map(signed),
+        sky_light: section.sky_light().map(signed),
+    }
+}
+
+/// Anvil stores light as signed bytes. Arrays of the wrong length are dropped.
+fn light_array(light: &ByteArray) -> Option<Vec<u8>> {
+    if light.0.len() != LIGHT_LENGTH {
+        return None;
+    }
+    Some(light.0.iter().map(|byte| *byte as u8).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::fields::key::Key;
+
+    use crate::block::{Block, BlockState};
+    use crate::dimension::registry::RegistryCodec;
+    use crate::region::{fixture, FIXTURE_CHUNK};
+    use crate::region::column::ChunkColumn;
+    use crate::region::data::LevelChunk;
+    use crate::region::region::Region;
+
+    fn fixture_column() -> ChunkColumn {
+        let region = Region::open(&fixture("column").join("r.0.0.mca"), 0, 0).unwrap();
+        let blob = region.get_chunk_data(FIXTURE_CHUNK.0, FIXTURE_CHUNK.1).unwrap().unwrap();
+        let chunk = LevelChunk::from_blob(&blob).unwrap();
+        let biomes = RegistryCodec::vanilla().biome.ids_by_name();
+        ChunkColumn::from_level_chunk(&chunk, -64, 384, |name| biomes.get(name).copied()).unwrap()
+    }
+
+    fn block_counts(column: &ChunkColumn) -> Vec<(i32, i16)> {
+        column.sections().iter()
+            .filter(|section| !section.is_empty())
+            .map(|section| (section.y(), section.block_count()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fill_across_sections() {
+        let stone = Block::Stone.default_state();
+        let mut column = ChunkColumn::new(0, 0, -64, 384, 0);
+        column.fill(-60, 20, stone);
+        assert!(column.get_block(5, -61, 5).is_air());
+        assert_eq!(column.get_block(5, -60, 5), stone);
+        assert_eq!(column.get_block(15, 0, 15), stone);
+        assert_eq!(column.get_block(0, 20, 0), stone);
+        assert!(column.get_block(0, 21, 0).is_air());
+        assert_eq!(block_counts(&column), vec![(-4, 12 * 256), (-3, 4096), (-2, 4096), (-1, 4096), (0, 4096), (1, 5 * 256)]);
+
+        // Filling past the bottom of the column stops at it
+        let dirt = Block::Dirt.default_state();
+        column.fill(-100, -61, dirt);
+        assert_eq!(column.get_block(3, -64, 3), dirt);
+        assert_eq!(block_counts(&column)[0], (-4, 4096));
+
+        // Filling with air empties whole sections
+        column.fill(0, 31, BlockState::AIR);
+        assert_eq!(block_counts(&column), vec![(-4, 4096), (-3, 4096), (-2, 4096), (-1, 4096)]);
+        assert_eq!(column.highest_block(8, 8), Some(0));
+    }
+
+    #[test]
+    fn test_block_count() {
+        let mut column = ChunkColumn::new(0, 0, -64, 384, 0);
+        let stone = Block::Stone.default_state();
+        assert_eq!(column.set_block(1, 70, 1, stone), Some(BlockState::AIR));
+        assert_eq!(column.set_block(1, 70, 1, stone), Some(stone));
+        column.set_block(2, 70, 1, Block::Dirt.default_state());
+        assert_eq!(block_counts(&column), vec![(4, 2)]);
+
+        // Replacing a block, or air with another air, keeps the count
+        column.set_block(1, 70, 1, Block::Granite.default_state());
+        column.set_block(3, 70, 1, Block::CaveAir.default_state());
+        assert_eq!(block_counts(&column), vec![(4, 2)]);
+
+        column.set_block(1, 70, 1, BlockState::AIR);
+        column.set_block(2, 70, 1, Block::VoidAir.default_state());
+        assert!(column.section(70).unwrap().is_empty());
+        assert_eq!(column.set_block(0, 320, 0, stone), None);
+    }
+
+    #[test]
+    fn test_take_changed_sections() {
+        let mut column = ChunkColumn::new(0, 0, -64, 384, 0);
+        assert!(column.take_changed_sections().is_empty());
+
+        column.fill(-60, 20, Block::Stone.default_state());
+        assert_eq!(column.take_changed_sections(), vec![-4, -3, -2, -1, 0, 1]);
+        assert!(column.take_changed_sections().is_empty());
+
+        column.set_block(1, 100, 1, Block::Stone.default_state());
+        column.set_block(1, -10, 1, Block::Dirt.default_state());
+        assert_eq!(column.take_changed_sections(), vec![-1, 6]);
+
+        // Setting a block to the state it already has changes nothing
+        column.set_block(1, 100, 1, Block::Stone.default_state());
+        assert!(column.take_changed_sections().is_empty());
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_load_fixture_chunk() {
+        let column = fixture_column();
+        let biomes = RegistryCodec::vanilla().biome;
+        let forest = biomes.id(&Key::minecraft("forest")).unwrap();
+        assert_eq!((column.x(), column.z()), FIXTURE_CHUNK);
+        assert_eq!((column.min_y(), column.height()), (-64, 384));
+        assert_eq!(column.get_block(0, -64, 0), Block::Bedrock.default_state());
+        assert_eq!(column.get_block(7, -50, 9).block(), Block::Deepslate);
+        assert_eq!(column.get_block(15, 0, 15), Block::Stone.default_state());
+        assert_eq!(column.get_block(4, 60, 4), Block::Stone.default_state());
+        assert_eq!(column.get_block(4, 62, 4), Block::Dirt.default_state());
+        assert_eq!(column.get_block(4, 63, 4).block(), Block::GrassBlock);
+        assert!(column.get_block(4, 64, 4).is_air());
+        assert_eq!(column.highest_block(4, 4), Some(64));
+        assert_eq!(column.get_biome(0, 70, 0), forest);
+        assert_eq!(column.get_block(3, 64, 5).block(), Block::Chest);
+        assert_eq!(column.get_block(4, 64, 5).block(), Block::Beehive);
+        assert!(column.sections().iter().filter(|section| section.y() > 4).all(|section| section.is_empty()));
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use protocol::fields::io_ext::{PacketReaderExt, PacketWriterExt};
+use protocol::fields::numeric::VarInt;
+
+use crate::region::direct::DirectPalette;
+use crate::region::indirect::IndirectPalette;
+use crate::region::palette::{ceil_log2, Palette, PaletteKind, PaletteMut};
+use crate::region::single::SingleValuePalette;
+use crate::region::storage::PackedArray;
+
+/// Stores the block states or biomes of a section, switching between the
+/// single value, indirect and direct palettes as values are set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PalettedContainer {
+    Single(SingleValuePalette),
+    Indirect(IndirectPalette),
+    Direct(DirectPalette),
+}
+
+impl PalettedContainer {
+    /// Creates a container where every entry is `value`
+    pub fn new(kind: PaletteKind, value: i32) -> Self {
+        PalettedContainer::Single(SingleValuePalette::new(kind, value))
+    }
+
+    pub fn blocks(value: i32) -> Self {
+        Self::new(PaletteKind::BLOCKS, value)
+    }
+
+    pub fn biomes(value: i32) -> Self {
+        Self::new(PaletteKind::BIOMES, value)
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        match self {
+            PalettedContainer::Single(palette) => palette.kind(),
+            PalettedContainer::Indirect(palette) => palette.kind(),
+            PalettedContainer::Direct(palette) => palette.kind(),
+        }
+    }
+
+    /// Changes the kind of the container, e.g. when the biome registry
+    /// grows. Only the direct bits may differ.
+    pub fn set_kind(&mut self, kind: PaletteKind) {
+        let current = self.kind();
+        assert_eq!((kind.dimension, kind.max_indirect_bits), (current.dimension, current.max_indirect_bits),
+            "only the direct bits of a container can change");
+        match self {
+            PalettedContainer::Single(palette) => palette.set_kind(kind),
+            PalettedContainer::Indirect(palette) => palette.set_kind(kind),
+            PalettedContainer::Direct(palette) => palette.set_kind(kind),
+        }
+    }
+
+    /// Returns the value at `index`, in YZX order
+    pub fn get_index(&self, index: usize) -> i32 {
+        match self {
+            PalettedContainer::Single(palette) => palette.value(),
+            PalettedContainer::Indirect(palette) => palette.get_index(index),
+            PalettedContainer::Direct(palette) => palette.get_index(index),
+        }
+    }
+
+    /// Sets the value at `index`, in YZX order, growing the palette if needed
+    pub fn set_index(&mut self, index: usize, value: i32) {
+        match self {
+            PalettedContainer::Single(palette) => {
+                if palette.value() == value {
+                    return;
+                }
+                let mut indirect = IndirectPalette::new(palette.kind(), palette.value());
+                indirect.try_set_index(index, value);
+                *self = PalettedContainer::Indirect(indirect);
+            }
+            PalettedContainer::Indirect(palette) => {
+                if !palette.try_set_index(index, value) {
+                    let mut direct = DirectPalette::from_indirect(palette);
+                    direct.set_index(index, value);
+                    *self = PalettedContainer::Direct(direct);
+                }
+            }
+            PalettedContainer::Direct(palette) => palette.set_index(index, value),
+        }
+    }
+
+    /// The number of entries whose value matches `predicate`
+    pub fn count(&self, predicate: impl Fn(i32) -> bool) -> usize {
+        match self {
+            PalettedContainer::Single(palette) if predicate(palette.value()) => palette.kind().size(),
+            PalettedContainer::Single(_) => 0,
+            _ => (0..self.kind().size()).filter(|index| predicate(self.get_index(*index))).count(),
+        }
+    }
+
+    /// Writes the container in the format of the Chunk Data packet: the bits
+    /// per entry, the palette and the packed data.
+    pub fn write_to<W: Write>(&self, output: &mut W) -> Result<()> {
+        match self {
+            PalettedContainer::Single(palette) => {
+                output.write_u8(0)?;
+                output.write_varint(&VarInt(palette.value()))?;
+                output.write_varint(&VarInt(0))?;
+            }
+            PalettedContainer::Indirect(palette) => {
+                output.write_u8(palette.storage().bits())?;
+                output.write_varint(&VarInt(palette.palette().len() as i32))?;
+                for value in palette.palette() {
+                    output.write_varint(&VarInt(*value))?;
+                }
+                write_longs(output, palette.storage().data())?;
+            }
+            PalettedContainer::Direct(palette) => {
+                output.write_u8(palette.storage().bits())?;
+                write_longs(output, palette.storage().data())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a container written by [`PalettedContainer::write_to`]
+    pub fn read_from<R: Read>(kind: PaletteKind, input: &mut R) -> Result<Self> {
+        let bits = input.read_u8()?;
+        if bits == 0 {
+            let value = input.read_varint()?.0;
+            read_longs(input)?;
+            return Ok(Self::new(kind, value));
+        }
+        if bits <= kind.max_indirect_bits {
+            // The client treats anything smaller as the minimum
+            let bits = bits.max(kind.min_bits);
+            let length = input.read_varint()?.0;
+            if length <= 0 || length > 1 << bits {
+                return Err(Error::new(ErrorKind::InvalidData, format!("invalid palette length {}", length)));
+            }
+            let mut palette = Vec::with_capacity(length as usize);
+            for _ in 0..length {
+                palette.push(input.read_varint()?.0);
+            }
+            let storage = PackedArray::from_data(bits, kind.size(), read_longs(input)?)?;
+            return Ok(PalettedContainer::Indirect(IndirectPalette::from_parts(kind, palette, storage)?));
+        }
+        let storage = PackedArray::from_data(kind.direct_bits, kind.size(), read_longs(input)?)?;
+        Ok(PalettedContainer::Direct(DirectPalette::from_storage(kind, storage)))
+    }
+
+    /// Converts the palette and packed data of an Anvil section, with the
+    /// palette entries already mapped to their ids.
+    pub fn from_anvil(kind: PaletteKind, palette: Vec<i32>, data: Option<Vec<i64>>) -> Result<Self> {
+        if palette.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "empty palette"));
+        }
+        if palette.len() == 1 {
+            return Ok(Self::new(kind, palette[0]));
+        }
+        let data = data.ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing data for a palette with several entries"))?;
+        let storage = PackedArray::from_data(kind.anvil_bits(palette.len()), kind.size(), data)?;
+        if palette.len() <= 1 << kind.max_indirect_bits {
+            let bits = ceil_log2(palette.len()).max(kind.min_bits);
+            let storage = if storage.bits() == bits { storage } else { storage.resize(bits) };
+            return Ok(PalettedContainer::Indirect(IndirectPalette::from_parts(kind, palette, storage)?));
+        }
+        let mut direct = PackedArray::new(kind.direct_bits, kind.size());
+        for index in 0..kind.size() {
+            let id = palette.get(storage.get(index) as usize)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "palette index out of bounds"))?;
+            direct.set(index, *id as u32);
+        }
+        Ok(PalettedContainer::Direct(DirectPalette::from_storage(kind, direct)))
+    }
+
+    /// Converts the container to the palette and packed data of an Anvil
+    /// section. Anvil always uses a local palette, and omits the data when
+    /// the palette has a single entry.
+    pub fn to_anvil(&self) -> (Vec<i32>, Option<Vec<i64>>) {
+        let kind = self.kind();
+        match self {
+            PalettedContainer::Single(palette) => (vec![palette.value()], None),
+            PalettedContainer::Indirect(palette) if palette.palette().len() == 1 => (palette.palette().clone(), None),
+            PalettedContainer::Indirect(palette) => {
+                let bits = kind.anvil_bits(palette.palette().len());
+                let storage = palette.storage();
+                let data = if storage.bits() == bits { storage.data().clone() } else { storage.resize(bits).data().clone() };
+                (palette.palette().clone(), Some(data))
+            }
+            PalettedContainer::Direct(palette) => {
+                let mut values = Vec::new();
+                let mut ids = HashMap::new();
+                let indices: Vec<u32> = (0..kind.size())
+                    .map(|index| {
+                        let value = palette.get_index(index);
+                        *ids.entry(value).or_insert_with(|| {
+                            values.push(value);
+                            values.len() as u32 - 1
+                        })
+                    })
+                    .collect();
+                let mut storage = PackedArray::new(kind.anvil_bits(values.len()), kind.size());
+                for (index, id) in indices.into_iter().enumerate() {
+                    storage.set(index, id);
+                }
+                let data = if values.len() == 1 { None } else { Some(storage.data().clone()) };
+                (values, data)
+            }
+        }
+    }
+}
+
+impl Palette for PalettedContainer {
+    fn get(&self, x: i32, y: i32, z: i32) -> i32 {
+        self.get_index(self.kind().index(x, y, z))
+    }
+
+    fn dimension(&self) -> i32 {
+        self.kind().dimension
+    }
+
+    fn max_bits_per_entry(&self) -> Option<i32> {
+        Some(self.kind().direct_bits as i32)
+    }
+
+    fn bits_per_entry(&self) -> Option<i32> {
+        match self {
+            PalettedContainer::Single(palette) => palette.bits_per_entry(),
+            PalettedContainer::Indirect(palette) => palette.bits_per_entry(),
+            PalettedContainer::Direct(palette) => palette.bits_per_entry(),
+        }
+    }
+}
+
+impl PaletteMut for PalettedContainer {
+    fn set(&mut self, x: i32, y: i32, z: i32, value: i32) {
+        let index = self.kind().index(x, y, z);
+        self.set_index(index, value);
+    }
+
+    fn fill(&mut self, value: i32) {
+        *self = Self::new(self.kind(), value);
+    }
+
+    fn replace(&mut self, x: i32, y: i32, z: i32, remap: impl FnOnce(i32) -> i32) {
+        let index = self.kind().index(x, y, z);
+        let value = remap(self.get_index(index));
+        self.set_index(index, value);
+    }
+}
+
+fn write_longs<W: Write>(output: &mut W, data: &[i64]) -> Result<()> {
+    output.write_varint(&VarInt(data.len() as i32))?;
+    for long in data {
+        output.write_i64::<BigEndian>(*long)?;
+    }
+    Ok(())
+}
+
+fn read_longs<R: Read>(input: &mut R) -> Result<Vec<i64>> {
+    let length = input.read_varint()?.0;
+    if length < 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "negative data length"));
+    }
+    (0..length).map(|_| input.read_i64::<BigEndian>()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::region::container::PalettedContainer;
+    use crate::region::palette::{Palette, PaletteKind, PaletteMut};
+
+    #[test]
+    fn test_palette_growth() {
+        let mut container = PalettedContainer::blocks(0);
+        assert_eq!(container.bits_per_entry(), None);
+        container.set(1, 2, 3, 5);
+        assert_eq!(container.bits_per_entry(), Some(4));
+        for value in 0..17 {
+            container.set(value, 0, 0, value + 100);
+        }
+        assert_eq!(container.bits_per_entry(), Some(5));
+        for value in 0..256 {
+            container.set(value & 15, 1, value >> 4, value + 1000);
+        }
+        assert!(matches!(container, PalettedContainer::Direct(_)));
+        assert_eq!(container.get(1, 2, 3), 5);
+        assert_eq!(container.get(0, 0, 1), 0);
+        assert_eq!(container.get(3, 0, 0), 103);
+        assert_eq!(container.get(15, 1, 15), 1255);
+        container.fill(7);
+        assert_eq!(container, PalettedContainer::blocks(7));
+    }
+
+    #[test]
+    fn test_biome_growth() {
+        let mut container = PalettedContainer::biomes(0);
+        for value in 0..8 {
+            container.set(value & 3, value >> 2, 0, value);
+        }
+        assert_eq!(container.bits_per_entry(), Some(3));
+        container.set(3, 3, 3, 40);
+        assert_eq!(container.bits_per_entry(), Some(6));
+        assert_eq!(container.get(3, 1, 0), 7);
+        assert_eq!(container.get(3, 3, 3), 40);
+
+        // Custom biomes past 64 need a seventh bit
+        container.set_kind(PaletteKind::biomes(70));
+        assert_eq!(container.bits_per_entry(), Some(7));
+        container.set(0, 3, 3, 69);
+        assert_eq!(container.get(0, 3, 3), 69);
+        assert_eq!(container.get(3, 3, 3), 40);
+    }
+
+    #[test]
+    fn test_network_round_trip() {
+        let mut containers = vec![PalettedContainer::blocks(9)];
+        let mut indirect = PalettedContainer::blocks(0);
+        indirect.set(4, 5, 6, 20);
+        indirect.set(15, 15, 15, 21);
+        containers.push(indirect.clone());
+        let mut direct = indirect;
+        for value in 0..300 {
+            direct.set_index(value as usize * 13, value);
+        }
+        containers.push(direct);
+
+        for container in containers {
+            let mut buf = Vec::new();
+            container.write_to(&mut buf).unwrap();
+            let read = PalettedContainer::read_from(PaletteKind::BLOCKS, &mut &buf[..]).unwrap();
+            assert_eq!(read, container);
+        }
+
+        let mut buf = Vec::new();
+        PalettedContainer::blocks(9).write_to(&mut buf).unwrap();
+        assert_eq!(buf, vec![0, 9, 0]);
+    }
+
+    #[test]
+    fn test_anvil_round_trip() {
+        let mut container = PalettedContainer::blocks(0);
+        for index in 0..4096 {
+            container.set_index(index, (index % 300) as i32);
+        }
+        let (palette, data) = container.to_anvil();
+        assert_eq!(palette.len(), 300);
+        // 9 bits per entry, 7 entries per long
+        assert_eq!(data.as_ref().unwrap().len(), 586);
+        let read = PalettedContainer::from_anvil(PaletteKind::BLOCKS, palette, data).unwrap();
+        assert_eq!(read, container);
+
+        let mut biomes = PalettedContainer::biomes(0);
+        biomes.set(1, 1, 1, 3);
+        let (palette, data) = biomes.to_anvil();
+        assert_eq!(palette, vec![0, 3]);
+        // Anvil uses a single bit for two biomes
+        assert_eq!(data, Some(vec![1 << 21]));
+        assert_eq!(PalettedContainer::from_anvil(PaletteKind::BIOMES, palette, data).unwrap(), biomes);
+
+        assert_eq!(PalettedContainer::from_anvil(PaletteKind::BIOMES, vec![4], None).unwrap(), PalettedContainer::biomes(4));
+        assert!(PalettedContainer::from_anvil(PaletteKind::BLOCKS, vec![1, 2], Some(vec![0; 3])).is_err());
+    }
+}
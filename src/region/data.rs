@@ -1,32 +1,249 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
 
-#[derive(Serialize, Deserialize)]
+use nbt::{Blob, Map, Value};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as DeError;
+use serde::ser::SerializeMap;
+
+use crate::util::StringVisitor;
+
+/// The first data version using the 1.18 chunk format (sections without a `Level` tag)
+pub const MIN_DATA_VERSION: i32 = 2860;
+
+/// The data version of the chunks written by this server (1.19.4)
+pub const DATA_VERSION: i32 = 3337;
+
+/// A chunk as stored in an Anvil region file, in the 1.18+ format.
+///
+/// Tags that are not modeled are kept in `other`, and in the block entities
+/// and structure starts, so loading and saving a chunk does not lose data.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct LevelChunk {
-    data_version: u32,
+    pub data_version: i32,
     #[serde(rename = "xPos")]
-    x_pos: i32,
+    pub x_pos: i32,
     #[serde(rename = "yPos")]
-    y_pos: i32,
+    pub y_pos: i32,
     #[serde(rename = "zPos")]
-    z_pos: i32,
-    heightmaps: Heightmap,
-    inhabited_time: i64,
-    last_update: u64,
-    post_processing: Vec<Vec<i16>>,
-    status: RegionStatus,
+    pub z_pos: i32,
+    pub status: RegionStatus,
+    #[serde(default)]
+    pub last_update: i64,
+    #[serde(default)]
+    pub inhabited_time: i64,
+    #[serde(rename = "isLightOn", default)]
+    pub is_light_on: bool,
+    #[serde(rename = "sections", default)]
+    pub sections: Vec<ChunkSection>,
+    #[serde(rename = "block_entities", default)]
+    pub block_entities: Vec<LevelBlockEntity>,
+    #[serde(default)]
+    pub heightmaps: Heightmap,
+    #[serde(rename = "block_ticks", default)]
+    pub block_ticks: Vec<ScheduledTick>,
+    #[serde(rename = "fluid_ticks", default)]
+    pub fluid_ticks: Vec<ScheduledTick>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_processing: Vec<Vec<i16>>,
+    #[serde(rename = "structures", default)]
+    pub structures: Structures,
+    #[serde(flatten)]
+    pub other: UnmodeledTags,
+}
+
+impl LevelChunk {
+    /// Converts chunk data read from a region file, failing if the chunk
+    /// uses a format older than 1.18.
+    pub fn from_blob(blob: &Blob) -> Result<LevelChunk> {
+        let mut buf = Vec::new();
+        blob.to_writer(&mut buf).map_err(invalid_data)?;
+        let mut chunk: LevelChunk = nbt::from_reader(&buf[..]).map_err(invalid_data)?;
+        if chunk.data_version < MIN_DATA_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "chunk [{}, {}] has data version {}. Chunks older than 1.18 ({}) must be upgraded first",
+                chunk.x_pos, chunk.z_pos, chunk.data_version, MIN_DATA_VERSION
+            )));
+        }
+        let compound = blob_to_compound(blob)?;
+        chunk.other.read_values(&compound);
+        if let Some(Value::List(entities)) = compound.get("block_entities") {
+            for (entity, value) in chunk.block_entities.iter_mut().zip(entities) {
+                if let Value::Compound(value) = value {
+                    entity.data.read_values(value);
+                }
+            }
+        }
+        if let Some(starts) = compound_at(&compound, &["structures", "starts"]) {
+            chunk.structures.starts.read_values(starts);
+        }
+        Ok(chunk)
+    }
+
+    pub fn to_blob(&self) -> Result<Blob> {
+        let mut buf = Vec::new();
+        nbt::to_writer(&mut buf, self, None).map_err(invalid_data)?;
+        let blob = Blob::from_reader(&mut &buf[..]).map_err(invalid_data)?;
+        let mut compound = blob_to_compound(&blob)?;
+        if let Some(Value::List(entities)) = compound.get_mut("block_entities") {
+            for (entity, value) in self.block_entities.iter().zip(entities) {
+                if let Value::Compound(value) = value {
+                    entity.data.write_values(value);
+                }
+            }
+        }
+        if let Some(starts) = compound_at_mut(&mut compound, &["structures", "starts"]) {
+            self.structures.starts.write_values(starts);
+        }
+        self.other.write_values(&mut compound);
+        compound_to_blob(compound)
+    }
+}
+
+/// Tags of a compound that are not modeled, kept so that loading and saving
+/// it does not lose them.
+///
+/// Serde can't tell NBT arrays from lists, so the values read through it
+/// have the wrong types and none are written through it. Instead, the owner
+/// copies the exact values from the compound with [UnmodeledTags::read_values]
+/// after reading it, and puts them back with [UnmodeledTags::write_values].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnmodeledTags(pub HashMap<String, Value>);
+
+impl UnmodeledTags {
+    /// Replaces the values of the tags with those of the compound they were read from
+    pub(crate) fn read_values(&mut self, compound: &Map<String, Value>) {
+        for (name, value) in self.0.iter_mut() {
+            if let Some(exact) = compound.get(name) {
+                *value = exact.clone();
+            }
+        }
+    }
+
+    /// Adds the tags to the compound, unless it already has a modeled tag of the same name
+    pub(crate) fn write_values(&self, compound: &mut Map<String, Value>) {
+        for (name, value) in &self.0 {
+            compound.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+}
+
+impl Serialize for UnmodeledTags {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_map(Some(0))?.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for UnmodeledTags {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        HashMap::deserialize(deserializer).map(UnmodeledTags)
+    }
+}
+
+/// The tags of the blob, with their exact types
+pub(crate) fn blob_to_compound(blob: &Blob) -> Result<Map<String, Value>> {
+    let mut buf = Vec::new();
+    blob.to_writer(&mut buf).map_err(invalid_data)?;
+    // Skip the tag id and the name of the root compound
+    let name_length = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+    match Value::from_reader(0x0a, &mut &buf[3 + name_length..]).map_err(invalid_data)? {
+        Value::Compound(compound) => Ok(compound),
+        _ => unreachable!("blobs are compounds"),
+    }
+}
+
+pub(crate) fn compound_to_blob(compound: Map<String, Value>) -> Result<Blob> {
+    let mut blob = Blob::new();
+    for (name, value) in compound {
+        blob.insert(name, value).map_err(invalid_data)?;
+    }
+    Ok(blob)
+}
+
+/// The compound nested in `compound` under the given path of names
+pub(crate) fn compound_at<'a>(compound: &'a Map<String, Value>, path: &[&str]) -> Option<&'a Map<String, Value>> {
+    path.iter().try_fold(compound, |compound, name| match compound.get(*name) {
+        Some(Value::Compound(nested)) => Some(nested),
+        _ => None,
+    })
+}
+
+pub(crate) fn compound_at_mut<'a>(compound: &'a mut Map<String, Value>, path: &[&str]) -> Option<&'a mut Map<String, Value>> {
+    path.iter().try_fold(compound, |compound, name| match compound.get_mut(*name) {
+        Some(Value::Compound(nested)) => Some(nested),
+        _ => None,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChunkSection {
+    #[serde(rename = "Y")]
+    pub y: i8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_states: Option<PalettedData<BlockStateEntry>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub biomes: Option<PalettedData<String>>,
+    #[serde(rename = "BlockLight", default, skip_serializing_if = "Option::is_none")]
+    pub block_light: Option<ByteArray>,
+    #[serde(rename = "SkyLight", default, skip_serializing_if = "Option::is_none")]
+    pub sky_light: Option<ByteArray>,
+}
+
+/// A palette and the packed indices into it. `data` is absent when the
+/// palette holds a single entry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PalettedData<T> {
+    pub palette: Vec<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<LongArray>,
+}
+
+/// An entry of a block state palette, e.g. `minecraft:oak_log[axis=y]`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockStateEntry {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "Properties", default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LevelBlockEntity {
-    keep_packed: bool,
-    id: String,
-    x: i32,
-    y: i32,
-    z: i32,
+    #[serde(rename = "keepPacked", default)]
+    pub keep_packed: bool,
+    pub id: String,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+    /// The block entity specific data
+    #[serde(flatten)]
+    pub data: UnmodeledTags,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
+/// A block or fluid tick scheduled in a chunk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledTick {
+    /// The block or fluid to tick
+    pub i: String,
+    /// The priority
+    pub p: i32,
+    /// The number of ticks until the tick runs
+    pub t: i32,
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Structures {
+    #[serde(rename = "References", default)]
+    pub references: HashMap<String, LongArray>,
+    #[serde(default)]
+    pub starts: UnmodeledTags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RegionStatus {
     Empty,
     StructureStarts,
@@ -40,14 +257,151 @@ pub enum RegionStatus {
     Light,
     Spawn,
     Heightmaps,
-    Full
+    Full,
+}
+
+impl RegionStatus {
+    const ALL: [RegionStatus; 13] = [
+        RegionStatus::Empty,
+        RegionStatus::StructureStarts,
+        RegionStatus::StructureReferences,
+        RegionStatus::Biomes,
+        RegionStatus::Noise,
+        RegionStatus::Surface,
+        RegionStatus::Carvers,
+        RegionStatus::LiquidCarvers,
+        RegionStatus::Features,
+        RegionStatus::Light,
+        RegionStatus::Spawn,
+        RegionStatus::Heightmaps,
+        RegionStatus::Full,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegionStatus::Empty => "empty",
+            RegionStatus::StructureStarts => "structure_starts",
+            RegionStatus::StructureReferences => "structure_references",
+            RegionStatus::Biomes => "biomes",
+            RegionStatus::Noise => "noise",
+            RegionStatus::Surface => "surface",
+            RegionStatus::Carvers => "carvers",
+            RegionStatus::LiquidCarvers => "liquid_carvers",
+            RegionStatus::Features => "features",
+            RegionStatus::Light => "light",
+            RegionStatus::Spawn => "spawn",
+            RegionStatus::Heightmaps => "heightmaps",
+            RegionStatus::Full => "full",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+impl Serialize for RegionStatus {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&format!("minecraft:{}", self.name()))
+    }
+}
+
+impl<'de> Deserialize<'de> for RegionStatus {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let string = deserializer.deserialize_string(StringVisitor)?;
+        // 1.18 and 1.19 omit the namespace
+        let name = string.strip_prefix("minecraft:").unwrap_or(&string);
+        RegionStatus::ALL.iter()
+            .find(|status| status.name() == name)
+            .copied()
+            .ok_or_else(|| D::Error::custom(format!("invalid chunk status: {}", string)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub struct Heightmap {
-    pub motion_blocking: Vec<i64>,
-    pub ocean_floor: Vec<i64>,
-    pub world_surface: Vec<i64>,
-    pub motion_blocking_no_leaves: Vec<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion_blocking: Option<LongArray>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ocean_floor: Option<LongArray>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world_surface: Option<LongArray>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion_blocking_no_leaves: Option<LongArray>,
+}
+
+/// A `Vec<i64>` stored as an NBT long array rather than a list of longs
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(transparent)]
+pub struct LongArray(pub Vec<i64>);
+
+impl Serialize for LongArray {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        nbt::i64_array(&self.0, serializer)
+    }
+}
+
+/// A `Vec<i8>` stored as an NBT byte array rather than a list of bytes
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(transparent)]
+pub struct ByteArray(pub Vec<i8>);
+
+impl Serialize for ByteArray {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        nbt::i8_array(&self.0, serializer)
+    }
+}
+
+pub(crate) fn invalid_data(why: nbt::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, why.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use nbt::{Blob, Map, Value};
+
+    use crate::region::{fixture, FIXTURE_CHUNK};
+    use crate::region::data::{LevelChunk, RegionStatus};
+    use crate::region::region::Region;
+
+    fn compound(value: &Value) -> &Map<String, Value> {
+        match value {
+            Value::Compound(compound) => compound,
+            other => panic!("expected a compound, got {:?}", other),
+        }
+    }
+
+    fn list(value: &Value) -> &Vec<Value> {
+        match value {
+            Value::List(list) => list,
+            other => panic!("expected a list, got {:?}", other),
+        }
+    }
+
+    fn fixture_chunk() -> Blob {
+        let region = Region::open(&fixture("data").join("r.0.0.mca"), 0, 0).unwrap();
+        region.get_chunk_data(FIXTURE_CHUNK.0, FIXTURE_CHUNK.1).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_read_chunk() {
+        let chunk = LevelChunk::from_blob(&fixture_chunk()).unwrap();
+        assert_eq!((chunk.x_pos, chunk.y_pos, chunk.z_pos), (8, -4, 8));
+        assert_eq!(chunk.status, RegionStatus::Full);
+        assert_eq!(chunk.sections.len(), 26);
+        assert_eq!(chunk.block_entities[0].id, "minecraft:chest");
+        assert_eq!(chunk.structures.references["minecraft:buried_treasure"].0.len(), 1);
+    }
+
+    #[test]
+    fn test_blob_round_trip() {
+        let blob = fixture_chunk();
+        let chunk = LevelChunk::from_blob(&blob).unwrap();
+        // Arrays nested in tags that are not modeled keep their types
+        let bee = compound(&list(&chunk.block_entities[1].data.0["Bees"])[0]);
+        assert!(matches!(compound(&bee["EntityData"])["UUID"], Value::IntArray(_)));
+        let start = compound(&chunk.structures.starts.0["minecraft:buried_treasure"]);
+        assert!(matches!(compound(&list(&start["Children"])[0])["BB"], Value::IntArray(_)));
+        assert!(chunk.other.0.contains_key("blending_data"));
+
+        // Every tag comes back with its type and value
+        assert_eq!(chunk.to_blob().unwrap(), blob);
+    }
 }
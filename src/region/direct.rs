@@ -0,0 +1,77 @@
+use crate::region::indirect::IndirectPalette;
+use crate::region::palette::{Palette, PaletteKind};
+use crate::region::storage::PackedArray;
+
+/// A palette where each entry directly stores its global registry id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectPalette {
+    kind: PaletteKind,
+    storage: PackedArray,
+}
+
+impl DirectPalette {
+    /// Creates a palette where every entry is `value`
+    pub fn new(kind: PaletteKind, value: i32) -> Self {
+        let mut storage = PackedArray::new(kind.direct_bits, kind.size());
+        for index in 0..kind.size() {
+            storage.set(index, value as u32);
+        }
+        Self { kind, storage }
+    }
+
+    /// Converts a full indirect palette
+    pub fn from_indirect(indirect: &IndirectPalette) -> Self {
+        let kind = indirect.kind();
+        let mut storage = PackedArray::new(kind.direct_bits, kind.size());
+        for index in 0..kind.size() {
+            storage.set(index, indirect.get_index(index) as u32);
+        }
+        Self { kind, storage }
+    }
+
+    pub(crate) fn from_storage(kind: PaletteKind, storage: PackedArray) -> Self {
+        Self { kind, storage }
+    }
+
+    pub fn get_index(&self, index: usize) -> i32 {
+        self.storage.get(index) as i32
+    }
+
+    pub fn set_index(&mut self, index: usize, value: i32) {
+        self.storage.set(index, value as u32);
+    }
+
+    pub fn storage(&self) -> &PackedArray {
+        &self.storage
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        self.kind
+    }
+
+    /// Changes the kind, repacking the entries if its direct bits differ
+    pub(crate) fn set_kind(&mut self, kind: PaletteKind) {
+        if kind.direct_bits != self.kind.direct_bits {
+            self.storage = self.storage.resize(kind.direct_bits);
+        }
+        self.kind = kind;
+    }
+}
+
+impl Palette for DirectPalette {
+    fn get(&self, x: i32, y: i32, z: i32) -> i32 {
+        self.get_index(self.kind.index(x, y, z))
+    }
+
+    fn dimension(&self) -> i32 {
+        self.kind.dimension
+    }
+
+    fn max_bits_per_entry(&self) -> Option<i32> {
+        Some(self.kind.direct_bits as i32)
+    }
+
+    fn bits_per_entry(&self) -> Option<i32> {
+        Some(self.kind.direct_bits as i32)
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::region::palette::{Palette, PaletteKind};
+use crate::region::storage::PackedArray;
+
+/// Palettes up to this length are searched linearly, like vanilla does for
+/// palettes of 4 bits or less. Longer ones use the lookup map.
+const LINEAR_LENGTH: usize = 16;
+
+/// A palette storing the distinct values of the section, with each entry
+/// holding an index into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndirectPalette {
+    kind: PaletteKind,
+    palette: Vec<i32>,
+    lookup: HashMap<i32, u32>,
+    storage: PackedArray,
+}
+
+impl IndirectPalette {
+    /// Creates a palette where every entry is `value`
+    pub fn new(kind: PaletteKind, value: i32) -> Self {
+        Self {
+            kind,
+            palette: vec![value],
+            lookup: HashMap::from([(value, 0)]),
+            storage: PackedArray::new(kind.min_bits, kind.size()),
+        }
+    }
+
+    /// Creates a palette from its values and the packed indices into them,
+    /// failing if an index is out of bounds.
+    pub fn from_parts(kind: PaletteKind, palette: Vec<i32>, storage: PackedArray) -> Result<Self> {
+        if (0..storage.size()).any(|index| storage.get(index) as usize >= palette.len()) {
+            return Err(Error::new(ErrorKind::InvalidData, "palette index out of bounds"));
+        }
+        let mut lookup = HashMap::with_capacity(palette.len());
+        for (id, value) in palette.iter().enumerate() {
+            lookup.entry(*value).or_insert(id as u32);
+        }
+        Ok(Self { kind, palette, lookup, storage })
+    }
+
+    /// Returns the value at `index`, in YZX order
+    pub fn get_index(&self, index: usize) -> i32 {
+        self.palette[self.storage.get(index) as usize]
+    }
+
+    /// Sets the value at `index`, in YZX order. Returns `false`, without
+    /// changing anything, if the palette is full and already uses the
+    /// maximum bits per entry of an indirect palette.
+    pub fn try_set_index(&mut self, index: usize, value: i32) -> bool {
+        match self.index_of_or_insert(value) {
+            Some(id) => {
+                self.storage.set(index, id);
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn palette(&self) -> &Vec<i32> {
+        &self.palette
+    }
+
+    pub fn storage(&self) -> &PackedArray {
+        &self.storage
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        self.kind
+    }
+
+    /// Changes the kind. The indirect bits of both kinds must be the same.
+    pub(crate) fn set_kind(&mut self, kind: PaletteKind) {
+        self.kind = kind;
+    }
+
+    fn index_of(&self, value: i32) -> Option<u32> {
+        if self.palette.len() <= LINEAR_LENGTH {
+            self.palette.iter().position(|entry| *entry == value).map(|id| id as u32)
+        } else {
+            self.lookup.get(&value).copied()
+        }
+    }
+
+    fn index_of_or_insert(&mut self, value: i32) -> Option<u32> {
+        if let Some(id) = self.index_of(value) {
+            return Some(id);
+        }
+        let bits = self.storage.bits();
+        if self.palette.len() >= 1 << bits {
+            if bits >= self.kind.max_indirect_bits {
+                return None;
+            }
+            self.storage = self.storage.resize(bits + 1);
+        }
+        let id = self.palette.len() as u32;
+        self.palette.push(value);
+        self.lookup.insert(value, id);
+        Some(id)
+    }
+}
+
+impl Palette for IndirectPalette {
+    fn get(&self, x: i32, y: i32, z: i32) -> i32 {
+        self.get_index(self.kind.index(x, y, z))
+    }
+
+    fn dimension(&self) -> i32 {
+        self.kind.dimension
+    }
+
+    fn max_bits_per_entry(&self) -> Option<i32> {
+        Some(self.kind.max_indirect_bits as i32)
+    }
+
+    fn bits_per_entry(&self) -> Option<i32> {
+        Some(self.storage.bits() as i32)
+    }
+}
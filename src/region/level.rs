@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Result};
+use std::path::Path;
+
+use bevy::prelude::Resource;
+use nbt::Blob;
+use serde::{Deserialize, Serialize};
+
+use crate::region::data::{blob_to_compound, compound_at, compound_at_mut, compound_to_blob, invalid_data, UnmodeledTags};
+
+/// The name of the file storing the level data, in the world folder
+pub const LEVEL_FILE: &str = "level.dat";
+
+/// The root compound of `level.dat`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LevelFile {
+    #[serde(rename = "Data")]
+    data: LevelData,
+}
+
+/// The data of a world that is not stored in chunks, read from `level.dat`.
+///
+/// Tags that are not modeled are kept in `other` with their exact types, so
+/// loading and saving the level does not lose data.
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct LevelData {
+    #[serde(default)]
+    pub data_version: i32,
+    #[serde(default)]
+    pub level_name: String,
+    pub spawn_x: i32,
+    pub spawn_y: i32,
+    pub spawn_z: i32,
+    #[serde(default)]
+    pub spawn_angle: f32,
+    /// The ticks the world has run for
+    #[serde(default)]
+    pub time: i64,
+    /// The time of day, in ticks. Unlike `time`, commands can change it.
+    #[serde(default)]
+    pub day_time: i64,
+    /// Every value is stored as a string, e.g. `"true"` or `"3"`
+    #[serde(default)]
+    pub game_rules: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub world_gen_settings: Option<WorldGenSettings>,
+    #[serde(flatten)]
+    pub other: UnmodeledTags,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldGenSettings {
+    pub seed: i64,
+    #[serde(flatten)]
+    pub other: UnmodeledTags,
+}
+
+impl LevelData {
+    /// Reads the gzip-compressed `level.dat` of the world folder
+    pub fn read(world_directory: &Path) -> Result<LevelData> {
+        let file = File::open(world_directory.join(LEVEL_FILE))?;
+        let blob = Blob::from_gzip_reader(&mut BufReader::new(file)).map_err(invalid_data)?;
+        let mut buf = Vec::new();
+        blob.to_writer(&mut buf).map_err(invalid_data)?;
+        let mut level: LevelFile = nbt::from_reader(&buf[..]).map_err(invalid_data)?;
+        let compound = blob_to_compound(&blob)?;
+        if let Some(data) = compound_at(&compound, &["Data"]) {
+            level.data.other.read_values(data);
+        }
+        if let (Some(settings), Some(values)) = (&mut level.data.world_gen_settings, compound_at(&compound, &["Data", "WorldGenSettings"])) {
+            settings.other.read_values(values);
+        }
+        Ok(level.data)
+    }
+
+    /// Writes `level.dat` to the world folder. The previous file is kept as
+    /// `level.dat_old`, like vanilla does.
+    pub fn write(&self, world_directory: &Path) -> Result<()> {
+        let path = world_directory.join(LEVEL_FILE);
+        let new_path = world_directory.join("level.dat_new");
+        let old_path = world_directory.join("level.dat_old");
+        let mut file = File::create(&new_path)?;
+        self.to_blob()?.to_gzip_writer(&mut file).map_err(invalid_data)?;
+        file.sync_all()?;
+        if path.exists() {
+            fs::rename(&path, old_path)?;
+        }
+        fs::rename(new_path, path)
+    }
+
+    fn to_blob(&self) -> Result<Blob> {
+        let mut buf = Vec::new();
+        nbt::to_writer(&mut buf, &LevelFile { data: self.clone() }, None).map_err(invalid_data)?;
+        let blob = Blob::from_reader(&mut &buf[..]).map_err(invalid_data)?;
+        let mut compound = blob_to_compound(&blob)?;
+        if let (Some(settings), Some(values)) = (&self.world_gen_settings, compound_at_mut(&mut compound, &["Data", "WorldGenSettings"])) {
+            settings.other.write_values(values);
+        }
+        if let Some(data) = compound_at_mut(&mut compound, &["Data"]) {
+            self.other.write_values(data);
+        }
+        compound_to_blob(compound)
+    }
+
+    /// The world seed, or 0 if the level has no generation settings
+    pub fn seed(&self) -> i64 {
+        self.world_gen_settings.as_ref().map_or(0, |settings| settings.seed)
+    }
+
+    pub fn game_rule(&self, name: &str) -> Option<&str> {
+        self.game_rules.get(name).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::BufReader;
+    use std::path::Path;
+
+    use nbt::{Blob, Value};
+
+    use crate::region::data::{blob_to_compound, compound_at};
+    use crate::region::fixture_world;
+    use crate::region::level::{LEVEL_FILE, LevelData};
+
+    fn read_blob(path: &Path) -> Blob {
+        Blob::from_gzip_reader(&mut BufReader::new(File::open(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_read_level() {
+        let directory = fixture_world("level-read");
+        let level = LevelData::read(&directory).unwrap();
+        assert_eq!(level.data_version, 3337);
+        assert_eq!(level.level_name, "fixture");
+        assert_eq!((level.spawn_x, level.spawn_y, level.spawn_z), (136, 64, 136));
+        assert_eq!((level.time, level.day_time), (48213, 6000));
+        assert_eq!(level.seed(), -4172144997902289642);
+        assert_eq!(level.game_rule("randomTickSpeed"), Some("3"));
+        assert_eq!(level.game_rule("doFireTick"), None);
+        assert!(matches!(level.other.0["WanderingTraderId"], Value::IntArray(_)));
+        assert!(level.world_gen_settings.unwrap().other.0.contains_key("dimensions"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let directory = fixture_world("level-write");
+        let original = read_blob(&directory.join(LEVEL_FILE));
+        let mut level = LevelData::read(&directory).unwrap();
+        level.write(&directory).unwrap();
+        assert_eq!(read_blob(&directory.join("level.dat_old")), original);
+        let written = blob_to_compound(&read_blob(&directory.join(LEVEL_FILE))).unwrap();
+        assert_eq!(written, blob_to_compound(&original).unwrap());
+
+        level.day_time = 18000;
+        level.write(&directory).unwrap();
+        let written = blob_to_compound(&read_blob(&directory.join(LEVEL_FILE))).unwrap();
+        let data = compound_at(&written, &["Data"]).unwrap();
+        assert_eq!(data["DayTime"], Value::Long(18000));
+        assert!(matches!(compound_at(data, &["DragonFight"]).unwrap()["DragonUUID"], Value::IntArray(_)));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use flume::{Receiver, Sender};
+use nbt::Blob;
+use tokio::sync::oneshot;
+
+use crate::region::region::{ChunkCompression, Region};
+use crate::region::section::ChunkPos;
+
+type RegionKey = (i32, i32);
+
+/// The name of the file storing the given region
+pub fn region_file_name(region_x: i32, region_z: i32) -> String {
+    format!("r.{}.{}.mca", region_x, region_z)
+}
+
+enum Job {
+    Load(ChunkPos, oneshot::Sender<Result<Option<Blob>>>),
+    Save(ChunkPos, Blob, oneshot::Sender<Result<()>>),
+    Delete(ChunkPos, oneshot::Sender<Result<()>>),
+}
+
+struct OpenRegion {
+    region: Arc<Mutex<Region>>,
+    last_used: u64,
+}
+
+/// The region files of a directory, shared by the I/O threads
+struct RegionStore {
+    directory: PathBuf,
+    compression: ChunkCompression,
+    /// Whether region files are opened without write access
+    read_only: bool,
+    max_open_files: usize,
+    open: Mutex<HashMap<RegionKey, OpenRegion>>,
+    /// Notified whenever a thread stops using a region
+    released: Condvar,
+    /// Regions that have a file on disk
+    existing: Mutex<HashSet<RegionKey>>,
+    clock: AtomicU64,
+}
+
+impl RegionStore {
+    /// Returns the open region, opening it if needed. Returns `None` if the
+    /// region has no file and `create` is false.
+    ///
+    /// When the limit of open files is reached and every open region is in
+    /// use, waits until another thread releases one.
+    fn region(&self, key: RegionKey, create: bool) -> Result<Option<Arc<Mutex<Region>>>> {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        let mut open = self.open.lock().unwrap();
+        loop {
+            if let Some(entry) = open.get_mut(&key) {
+                entry.last_used = now;
+                return Ok(Some(Arc::clone(&entry.region)));
+            }
+            if !create && !self.existing.lock().unwrap().contains(&key) {
+                return Ok(None);
+            }
+            if open.len() < self.max_open_files || Self::evict(&mut open) {
+                break;
+            }
+            open = self.released.wait(open).unwrap();
+        }
+        let path = self.directory.join(region_file_name(key.0, key.1));
+        let region = if self.read_only {
+            Region::open_read_only(&path, key.0, key.1)?
+        } else {
+            Region::open(&path, key.0, key.1)?
+        };
+        let region = Arc::new(Mutex::new(region));
+        self.existing.lock().unwrap().insert(key);
+        open.insert(key, OpenRegion { region: Arc::clone(&region), last_used: now });
+        Ok(Some(region))
+    }
+
+    /// Runs `action` on the region of the chunk, then releases the region.
+    /// Returns `None` if the region has no file and `create` is false.
+    fn with_region<R>(&self, pos: ChunkPos, create: bool, action: impl FnOnce(&mut Region) -> Result<R>) -> Result<Option<R>> {
+        let Some(region) = self.region((pos.region_x(), pos.region_z()), create)? else {
+            return Ok(None);
+        };
+        let result = action(&mut region.lock().unwrap());
+        // Released under the lock, so a thread that just found every region
+        // in use is already waiting when notified
+        let _open = self.open.lock().unwrap();
+        drop(region);
+        self.released.notify_all();
+        result.map(Some)
+    }
+
+    /// Closes the least recently used region that no thread is using.
+    /// Returns false if every open region is in use.
+    fn evict(open: &mut HashMap<RegionKey, OpenRegion>) -> bool {
+        let victim = open.iter()
+            .filter(|(_, entry)| Arc::strong_count(&entry.region) == 1)
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| *key);
+        match victim {
+            Some(key) => open.remove(&key).is_some(),
+            None => false
+        }
+    }
+
+    fn run(&self, job: Job) {
+        match job {
+            Job::Load(pos, reply) => {
+                let _ = reply.send(self.load(pos));
+            }
+            Job::Save(pos, blob, reply) => {
+                let _ = reply.send(self.save(pos, &blob));
+            }
+            Job::Delete(pos, reply) => {
+                let _ = reply.send(self.delete(pos));
+            }
+        }
+    }
+
+    fn load(&self, pos: ChunkPos) -> Result<Option<Blob>> {
+        Ok(self.with_region(pos, false, |region| region.get_chunk_data(pos.x(), pos.z()))?.flatten())
+    }
+
+    fn save(&self, pos: ChunkPos, blob: &Blob) -> Result<()> {
+        self.check_writable()?;
+        self.with_region(pos, true, |region| region.write_chunk_data(pos.x(), pos.z(), blob, self.compression))?;
+        Ok(())
+    }
+
+    fn delete(&self, pos: ChunkPos) -> Result<()> {
+        self.check_writable()?;
+        self.with_region(pos, false, |region| region.delete_chunk(pos.x(), pos.z()))?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::new(ErrorKind::PermissionDenied, "the region files are opened read-only"));
+        }
+        Ok(())
+    }
+}
+
+/// Loads and saves chunks of a region directory.
+///
+/// Region files are kept open between calls, up to a limit of open files,
+/// after which the least recently used one is closed. Jobs wait while
+/// every open file is in use, so the limit is never exceeded. All file I/O runs on
+/// a dedicated pool of threads, so the async callers never block.
+pub struct RegionManager {
+    store: Arc<RegionStore>,
+    jobs: Sender<Job>,
+}
+
+impl RegionManager {
+    /// Indexes the region files in `directory`, creating the directory if
+    /// needed, and starts `threads` I/O threads.
+    pub fn new(directory: &Path, max_open_files: usize, threads: usize) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        Self::start(directory, max_open_files, threads, false)
+    }
+
+    /// Like [RegionManager::new], but never creates nor writes to any file.
+    /// A missing directory has no chunks, and saving chunks fails.
+    pub fn read_only(directory: &Path, max_open_files: usize, threads: usize) -> Result<Self> {
+        Self::start(directory, max_open_files, threads, true)
+    }
+
+    fn start(directory: &Path, max_open_files: usize, threads: usize, read_only: bool) -> Result<Self> {
+        let mut existing = HashSet::new();
+        if directory.is_dir() {
+            for entry in fs::read_dir(directory)? {
+                if let Some(pos) = ChunkPos::get_from_file(&entry?.path()) {
+                    existing.insert((pos.region_x(), pos.region_z()));
+                }
+            }
+        }
+        let store = Arc::new(RegionStore {
+            directory: directory.to_path_buf(),
+            compression: ChunkCompression::Zlib,
+            read_only,
+            max_open_files: max_open_files.max(1),
+            open: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+            existing: Mutex::new(existing),
+            clock: AtomicU64::new(0),
+        });
+        let (jobs, receiver) = flume::unbounded();
+        for index in 0..threads.max(1) {
+            let store = Arc::clone(&store);
+            let receiver: Receiver<Job> = receiver.clone();
+            thread::Builder::new()
+                .name(format!("region-io-{}", index))
+                .spawn(move || {
+                    // Exits once the manager, and with it the sender, is dropped
+                    while let Ok(job) = receiver.recv() {
+                        store.run(job);
+                    }
+                })?;
+        }
+        Ok(Self { store, jobs })
+    }
+
+    /// Reads the chunk at the given position. Returns `None` if it was never saved.
+    pub async fn load_chunk(&self, pos: ChunkPos) -> Result<Option<Blob>> {
+        let (reply, result) = oneshot::channel();
+        self.submit(Job::Load(pos, reply))?;
+        result.await.map_err(|_| closed())?
+    }
+
+    /// Writes the chunk at the given position, creating its region file if needed.
+    pub async fn save_chunk(&self, pos: ChunkPos, blob: Blob) -> Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.submit(Job::Save(pos, blob, reply))?;
+        result.await.map_err(|_| closed())?
+    }
+
+    pub async fn delete_chunk(&self, pos: ChunkPos) -> Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.submit(Job::Delete(pos, reply))?;
+        result.await.map_err(|_| closed())?
+    }
+
+    /// The coordinates of every region that has a file on disk
+    pub fn regions(&self) -> Vec<(i32, i32)> {
+        self.store.existing.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.store.directory
+    }
+
+    fn submit(&self, job: Job) -> Result<()> {
+        self.jobs.send(job).map_err(|_| closed())
+    }
+}
+
+fn closed() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "the region I/O threads have stopped")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    use nbt::{Blob, Value};
+
+    use crate::region::fixture;
+    use crate::region::manager::{region_file_name, RegionManager};
+    use crate::region::section::ChunkPos;
+
+    fn chunk(x: i32) -> Blob {
+        let mut blob = Blob::new();
+        blob.insert("xPos", Value::Int(x)).unwrap();
+        blob
+    }
+
+    fn x_pos(blob: Option<Blob>) -> Option<Value> {
+        blob.and_then(|blob| blob.get("xPos").cloned())
+    }
+
+    fn open_files(manager: &RegionManager) -> usize {
+        manager.store.open.lock().unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn test_load_and_save() {
+        let directory = fixture("manager-load");
+        let manager = RegionManager::new(&directory, 4, 2).unwrap();
+        assert_eq!(manager.regions(), vec![(0, 0)]);
+        assert_eq!(x_pos(manager.load_chunk(ChunkPos::new(2, 0)).await.unwrap()), Some(Value::Int(2)));
+        assert!(manager.load_chunk(ChunkPos::new(10, 10)).await.unwrap().is_none());
+        // Regions without a file are not created by reads
+        assert!(manager.load_chunk(ChunkPos::new(-1, 40)).await.unwrap().is_none());
+        assert!(!directory.join(region_file_name(-1, 1)).exists());
+
+        manager.save_chunk(ChunkPos::new(-1, 40), chunk(-1)).await.unwrap();
+        assert!(directory.join(region_file_name(-1, 1)).exists());
+        manager.delete_chunk(ChunkPos::new(2, 0)).await.unwrap();
+        drop(manager);
+
+        let manager = RegionManager::new(&directory, 4, 2).unwrap();
+        let mut regions = manager.regions();
+        regions.sort();
+        assert_eq!(regions, vec![(-1, 1), (0, 0)]);
+        assert_eq!(x_pos(manager.load_chunk(ChunkPos::new(-1, 40)).await.unwrap()), Some(Value::Int(-1)));
+        assert!(manager.load_chunk(ChunkPos::new(2, 0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let directory = fixture("manager-read-only");
+        let contents = fs::read(directory.join(region_file_name(0, 0))).unwrap();
+        let manager = RegionManager::read_only(&directory, 4, 2).unwrap();
+        assert_eq!(x_pos(manager.load_chunk(ChunkPos::new(2, 0)).await.unwrap()), Some(Value::Int(2)));
+        assert_eq!(manager.save_chunk(ChunkPos::new(-1, 40), chunk(-1)).await.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(manager.delete_chunk(ChunkPos::new(2, 0)).await.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(!directory.join(region_file_name(-1, 1)).exists());
+        drop(manager);
+        assert_eq!(fs::read(directory.join(region_file_name(0, 0))).unwrap(), contents);
+
+        // Missing directories have no chunks, and are not created
+        let missing = directory.join("missing");
+        let manager = RegionManager::read_only(&missing, 4, 2).unwrap();
+        assert!(manager.load_chunk(ChunkPos::new(0, 0)).await.unwrap().is_none());
+        assert!(!missing.exists());
+    }
+
+    #[tokio::test]
+    async fn test_eviction() {
+        let directory = fixture("manager-evict");
+        let manager = RegionManager::new(&directory, 2, 1).unwrap();
+        for region in 0..5 {
+            manager.save_chunk(ChunkPos::new(region * 32, 0), chunk(region)).await.unwrap();
+            assert!(open_files(&manager) <= 2);
+        }
+        // The least recently used regions were closed, and reopen on demand
+        for region in 0..5 {
+            assert_eq!(x_pos(manager.load_chunk(ChunkPos::new(region * 32, 0)).await.unwrap()), Some(Value::Int(region)));
+        }
+        assert_eq!(open_files(&manager), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_limit_with_busy_threads() {
+        let directory = fixture("manager-busy");
+        let manager = Arc::new(RegionManager::new(&directory, 2, 8).unwrap());
+        let tasks: Vec<_> = (0..64).map(|index| {
+            let manager = Arc::clone(&manager);
+            tokio::spawn(async move {
+                let pos = ChunkPos::new((index % 8) * 32 + index / 8, 0);
+                manager.save_chunk(pos, chunk(index)).await.unwrap();
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert!(open_files(&manager) <= 2);
+        for index in 0..64 {
+            let pos = ChunkPos::new((index % 8) * 32 + index / 8, 0);
+            assert_eq!(x_pos(manager.load_chunk(pos).await.unwrap()), Some(Value::Int(index)));
+        }
+    }
+}
@@ -2,6 +2,38 @@ pub mod region;
 pub mod section;
 pub mod single;
 pub mod palette;
+pub mod storage;
+pub mod indirect;
+pub mod direct;
+pub mod container;
 pub mod column;
 pub mod coordinates;
+pub mod manager;
 pub mod data;
+pub mod level;
+
+/// The chunk of the fixture region holding a full 1.19.4 chunk, with block
+/// entities, structures and tags that are not modeled
+#[cfg(test)]
+pub(crate) const FIXTURE_CHUNK: (i32, i32) = (8, 8);
+
+/// Copies the fixture world into a fresh directory named after the test, so
+/// tests never touch the originals. The copy is kept to inspect failures.
+#[cfg(test)]
+pub(crate) fn fixture_world(name: &str) -> std::path::PathBuf {
+    let source = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let directory = crate::util::test_directory(name).keep();
+    std::fs::copy(source.join(level::LEVEL_FILE), directory.join(level::LEVEL_FILE)).unwrap();
+    std::fs::create_dir(directory.join("region")).unwrap();
+    for entry in std::fs::read_dir(source.join("region")).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), directory.join("region").join(entry.file_name())).unwrap();
+    }
+    directory
+}
+
+/// The region folder of a fresh copy of the fixture world
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> std::path::PathBuf {
+    fixture_world(name).join("region")
+}
@@ -24,4 +24,77 @@ pub trait PaletteMut {
 
     fn replace(&mut self, x: i32, y: i32, z: i32, remap: impl FnOnce(i32) -> i32);
 
-}
\ No newline at end of file
+}
+
+/// Describes what a palette stores: its dimension and how many bits it uses
+/// per entry at each size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PaletteKind {
+    /// The length of each side of the cube
+    pub dimension: i32,
+    /// The bits per entry of the smallest indirect palette
+    pub min_bits: u8,
+    /// The bits per entry of the largest indirect palette. Above this, the
+    /// direct palette is used.
+    pub max_indirect_bits: u8,
+    /// The bits per entry of the direct palette, i.e. enough to store any
+    /// id of the global registry
+    pub direct_bits: u8,
+}
+
+impl PaletteKind {
+    /// Block states of a 16x16x16 section. 1.19.4 has 24135 block states.
+    pub const BLOCKS: PaletteKind = PaletteKind {
+        dimension: 16,
+        min_bits: 4,
+        max_indirect_bits: 8,
+        direct_bits: 15,
+    };
+
+    /// Biomes of a section, one per 4x4x4 cell. 1.19.4 has 64 biomes.
+    pub const BIOMES: PaletteKind = PaletteKind {
+        dimension: 4,
+        min_bits: 1,
+        max_indirect_bits: 3,
+        direct_bits: 6,
+    };
+
+    /// Biomes of a section when the biome registry has `registry_size`
+    /// entries. Clients size direct palettes after their registry.
+    pub fn biomes(registry_size: usize) -> PaletteKind {
+        PaletteKind {
+            direct_bits: ceil_log2(registry_size),
+            ..PaletteKind::BIOMES
+        }
+    }
+
+    /// The number of entries in the palette
+    pub fn size(&self) -> usize {
+        self.dimension.pow(3) as usize
+    }
+
+    /// The index of the entry at the given coordinates, in YZX order
+    pub fn index(&self, x: i32, y: i32, z: i32) -> usize {
+        let dimension = self.dimension;
+        (((y & (dimension - 1)) * dimension + (z & (dimension - 1))) * dimension + (x & (dimension - 1))) as usize
+    }
+
+    /// The bits per entry used in Anvil files for a palette of the given length.
+    /// Anvil never uses a direct palette, and block states use at least 4 bits.
+    pub fn anvil_bits(&self, palette_length: usize) -> u8 {
+        let bits = ceil_log2(palette_length);
+        if *self == PaletteKind::BLOCKS {
+            bits.max(self.min_bits)
+        } else {
+            bits
+        }
+    }
+}
+
+/// The number of bits needed to store `count` distinct values
+pub fn ceil_log2(count: usize) -> u8 {
+    if count <= 1 {
+        return 0;
+    }
+    (usize::BITS - (count - 1).leading_zeros()) as u8
+}
@@ -1,6 +1,8 @@
-use std::fs::File;
-use std::io::{Result, Seek, SeekFrom};
-use std::os::windows::fs::FileExt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bytes::Buf;
@@ -9,44 +11,109 @@ use nbt::Blob;
 use coordinates::{sector_offset, size_in_sectors};
 
 use crate::region::coordinates;
-use crate::region::coordinates::{chunk_inside_region, get_index, to_region};
+use crate::region::coordinates::{chunk_inside_region, get_index, to_chunk, to_region};
 
 const GZIP_COMPRESSION: u8 = 1;
 const ZLIB_COMPRESSION: u8 = 2;
 const NO_COMPRESSION: u8 = 3;
+/// Set on the compression byte when the chunk data is stored in an external `.mcc` file
+const EXTERNAL_FLAG: u8 = 128;
 const MAX_ENTRY_COUNT: u64 = 1024;
 const SECTOR_SIZE: usize = 4096;
 const SECTOR_1_MB: u64 = 256;
 const HEADER_LENGTH: u64 = MAX_ENTRY_COUNT * 2 * 4;
 
+/// The compression applied to chunk data stored in a region file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkCompression {
+    Gzip,
+    Zlib,
+    None,
+}
+
+impl ChunkCompression {
+    pub fn id(&self) -> u8 {
+        match self {
+            ChunkCompression::Gzip => GZIP_COMPRESSION,
+            ChunkCompression::Zlib => ZLIB_COMPRESSION,
+            ChunkCompression::None => NO_COMPRESSION,
+        }
+    }
+
+    fn compress(&self, blob: &Blob) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let result = match self {
+            ChunkCompression::Gzip => blob.to_gzip_writer(&mut data),
+            ChunkCompression::Zlib => blob.to_zlib_writer(&mut data),
+            ChunkCompression::None => blob.to_writer(&mut data),
+        };
+        result.map_err(|why| Error::new(ErrorKind::InvalidData, why.to_string()))?;
+        Ok(data)
+    }
+}
+
 pub struct Region {
     region_file: File,
+    directory: PathBuf,
     region_x: i32,
     region_z: i32,
     locations: [i32; 1024],
     timestamps: [i32; 1024],
     free_sectors: Vec<bool>,
+    // Regions opened read-only refuse to write chunks
+    writable: bool,
 }
 
 impl Region {
-    pub fn new(mut region_file: File, region_x: i32, region_z: i32) -> Result<Self> {
+    /// Opens the region file at `path`, creating it if it does not exist.
+    pub fn open(path: &Path, region_x: i32, region_z: i32) -> Result<Self> {
+        let region_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Self::new(region_file, directory_of(path), region_x, region_z)
+    }
+
+    /// Opens the existing region file at `path` without ever writing to it.
+    /// Writing chunks to the region fails.
+    pub fn open_read_only(path: &Path, region_x: i32, region_z: i32) -> Result<Self> {
+        let region_file = File::open(path)?;
+        Self::read_header(region_file, directory_of(path), region_x, region_z, false)
+    }
+
+    /// Reads the header of the region file, writing an empty one if the file
+    /// is too short and padding it to a whole number of sectors. `directory`
+    /// is where external `.mcc` chunk files are looked up.
+    pub fn new(region_file: File, directory: PathBuf, region_x: i32, region_z: i32) -> Result<Self> {
+        Self::read_header(region_file, directory, region_x, region_z, true)
+    }
+
+    fn read_header(mut region_file: File, directory: PathBuf, region_x: i32, region_z: i32, writable: bool) -> Result<Self> {
         let mut locations: [i32; 1024] = [0; 1024];
         let mut timestamps: [i32; 1024] = [0; 1024];
 
         region_file.seek(SeekFrom::Start(0))?;
-        let length = region_file.metadata().unwrap().len();
+        let length = region_file.metadata()?.len();
         if length < HEADER_LENGTH as u64 {
+            if !writable {
+                return Err(corrupt(format!("the region file is {} bytes, shorter than its header", length)));
+            }
             for _ in 0..HEADER_LENGTH {
                 region_file.write_u8(0)?;
             }
         }
 
-        Self::add_padding(&mut region_file)?;
+        if writable {
+            Self::add_padding(&mut region_file)?;
+        }
 
-        let length = region_file.metadata().unwrap().len();
+        let length = region_file.metadata()?.len();
 
-        let available_sectors = length / (SECTOR_SIZE as u64);
-        let mut free_sectors: Vec<bool> = vec![true; available_sectors as usize];
+        // The last sector of read-only files may be cut short
+        let available_sectors = sectors_for(length as usize);
+        let mut free_sectors: Vec<bool> = vec![true; available_sectors];
 
         free_sectors[0] = false;
         free_sectors[1] = false;
@@ -71,18 +138,17 @@ impl Region {
         }
         Ok(Self {
             region_file,
+            directory,
             region_x,
             region_z,
             locations,
             timestamps,
             free_sectors,
+            writable,
         })
     }
 
     pub fn get_chunk_data(&self, chunk_x: i32, chunk_z: i32) -> Result<Option<Blob>> {
-        // if self.is_out(chunk_x, chunk_z) {
-        //     panic!("Out of region. X: {}. Z: {} (This region -> X: {}, Z: {})", chunk_x, chunk_z, self.region_x, self.region_z);
-        // }
         if !self.has_chunk(chunk_x, chunk_z) {
             return Ok(None);
         }
@@ -90,49 +156,238 @@ impl Region {
             .map(|v| Some(v));
     }
 
-    fn has_chunk(&self, x: i32, z: i32) -> bool {
+    pub fn has_chunk(&self, x: i32, z: i32) -> bool {
         self.locations[get_index(chunk_inside_region(x), chunk_inside_region(z))] != 0
     }
 
+    /// The last modification time of the chunk, in seconds since the unix epoch
+    pub fn timestamp(&self, x: i32, z: i32) -> i32 {
+        self.timestamps[get_index(chunk_inside_region(x), chunk_inside_region(z))]
+    }
+
+    pub fn region_x(&self) -> i32 {
+        self.region_x
+    }
+
+    pub fn region_z(&self) -> i32 {
+        self.region_z
+    }
+
     fn read_column_data(&self, x: i32, z: i32) -> Result<Blob> {
-        let offset = self.file_offset(x, z);
-        let length = {
-            let mut v = vec![0_u8; 4];
-            self.region_file.seek_read(&mut v, offset as u64)?;
-            ((v[0] as u32) << 24) + ((v[1] as u32) << 16) + ((v[2] as u32) << 8) + ((v[3] as u32) << 0)
-        } as usize;
-        let raw_data = {
-            let mut v = vec![0; length - 1];
-            self.region_file.seek_read(&mut v, (offset + 5) as u64)?;
-            v
-        };
-        let compressed_type = {
-            let mut v = [0; 1];
-            self.region_file.seek_read(&mut v, (offset + 4) as u64)?;
-            v[0]
+        let location = self.locations[get_index(x, z)];
+        let offset = sector_offset(location) * SECTOR_SIZE;
+        let allocated = size_in_sectors(location) * SECTOR_SIZE;
+        if sector_offset(location) < 2 || sector_offset(location) + size_in_sectors(location) > self.free_sectors.len() {
+            return Err(corrupt(format!("chunk [{}, {}] points outside of the region file", x, z)));
+        }
+
+        let mut header = [0_u8; 5];
+        read_exact_at(&self.region_file, &mut header, offset as u64)?;
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let compression_type = header[4];
+        // The length includes the compression byte
+        if length == 0 || length + 4 > allocated {
+            return Err(corrupt(format!(
+                "chunk [{}, {}] has an invalid length of {} bytes ({} bytes allocated)", x, z, length, allocated
+            )));
+        }
+
+        let raw_data = if compression_type & EXTERNAL_FLAG != 0 {
+            let mut data = Vec::new();
+            File::open(self.external_file(x, z))?.read_to_end(&mut data)?;
+            data
+        } else {
+            let mut data = vec![0; length - 1];
+            read_exact_at(&self.region_file, &mut data, (offset + 5) as u64)?;
+            data
         };
         let mut reader = raw_data.reader();
-        let data = match compressed_type {
+        let data = match compression_type & !EXTERNAL_FLAG {
             GZIP_COMPRESSION => Blob::from_gzip_reader(&mut reader),
             ZLIB_COMPRESSION => Blob::from_zlib_reader(&mut reader),
             NO_COMPRESSION => Blob::from_reader(&mut reader),
-            compression => panic!("invalid compression: {}", compression)
-        }?;
-        Ok(data)
+            compression => return Err(corrupt(format!(
+                "chunk [{}, {}] uses an unknown compression type: {}", x, z, compression
+            )))
+        };
+        data.map_err(|why| Error::new(ErrorKind::InvalidData, why.to_string()))
+    }
+
+    /// Writes the chunk to the region file, replacing any previous data.
+    ///
+    /// Chunks that need 1 MiB or more are stored in an external `.mcc` file.
+    pub fn write_chunk_data(&mut self, chunk_x: i32, chunk_z: i32, blob: &Blob, compression: ChunkCompression) -> Result<()> {
+        self.check_writable()?;
+        let x = chunk_inside_region(chunk_x);
+        let z = chunk_inside_region(chunk_z);
+        let data = compression.compress(blob)?;
+        let external_file = self.external_file(x, z);
+
+        // External data is written to a temporary file first, and renamed over
+        // the `.mcc` file once whole, so a crash never leaves half of it
+        let external = sectors_for(data.len() + 5) >= SECTOR_1_MB as usize;
+        let temporary_file = external_file.with_extension("mcc.tmp");
+        let (payload, compression_type) = if external {
+            write_synced(&temporary_file, &data)?;
+            (Vec::new(), compression.id() | EXTERNAL_FLAG)
+        } else {
+            (data, compression.id())
+        };
+
+        let mut buf = Vec::with_capacity(payload.len() + 5);
+        buf.write_u32::<BigEndian>(payload.len() as u32 + 1)?;
+        buf.write_u8(compression_type)?;
+        buf.extend_from_slice(&payload);
+        let sectors = sectors_for(buf.len());
+        buf.resize(sectors * SECTOR_SIZE, 0);
+
+        // Allocate before freeing the old sectors, so the old data stays
+        // intact until the header points to the new one.
+        let old_location = self.locations[get_index(x, z)];
+        let offset = self.allocate(sectors);
+        let location = ((offset as i32) << 8) | sectors as i32;
+        let written = write_all_at(&self.region_file, &buf, (offset * SECTOR_SIZE) as u64)
+            .and_then(|_| if external { fs::rename(&temporary_file, &external_file) } else { Ok(()) })
+            .and_then(|_| self.set_location(x, z, location));
+        if let Err(why) = written {
+            self.release(location);
+            if external {
+                let _ = remove_if_exists(&temporary_file);
+            }
+            return Err(why);
+        }
+        self.set_timestamp(x, z, now())?;
+        self.release(old_location);
+        // The header no longer points to the external data of a chunk that
+        // is now stored in the region file
+        if !external {
+            remove_if_exists(&external_file)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the chunk from the region. Its sectors become available for other chunks.
+    pub fn delete_chunk(&mut self, chunk_x: i32, chunk_z: i32) -> Result<()> {
+        self.check_writable()?;
+        let x = chunk_inside_region(chunk_x);
+        let z = chunk_inside_region(chunk_z);
+        let old_location = self.locations[get_index(x, z)];
+        if old_location == 0 {
+            return Ok(());
+        }
+        self.set_location(x, z, 0)?;
+        self.set_timestamp(x, z, 0)?;
+        self.release(old_location);
+        remove_if_exists(&self.external_file(x, z))
+    }
+
+    /// Defragments the region file by moving every chunk towards the start
+    /// of the file and truncating the unused sectors at the end.
+    ///
+    /// This is meant to run offline, as it rewrites most of the file.
+    pub fn compact(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let mut chunks: Vec<usize> = (0..MAX_ENTRY_COUNT as usize)
+            .filter(|index| self.locations[*index] != 0)
+            .collect();
+        chunks.sort_by_key(|index| sector_offset(self.locations[*index]));
+
+        // Chunks only ever move towards the start, so a chunk is always
+        // fully read before its sectors can be overwritten.
+        let mut next_sector = 2;
+        for index in chunks {
+            let location = self.locations[index];
+            let offset = sector_offset(location);
+            let sectors = size_in_sectors(location);
+            if offset != next_sector {
+                let mut buf = vec![0; sectors * SECTOR_SIZE];
+                read_exact_at(&self.region_file, &mut buf, (offset * SECTOR_SIZE) as u64)?;
+                write_all_at(&self.region_file, &buf, (next_sector * SECTOR_SIZE) as u64)?;
+                let (x, z) = ((index % 32) as i32, (index / 32) as i32);
+                self.set_location(x, z, ((next_sector as i32) << 8) | sectors as i32)?;
+            }
+            next_sector += sectors;
+        }
+
+        self.region_file.set_len((next_sector * SECTOR_SIZE) as u64)?;
+        self.free_sectors = vec![false; next_sector];
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if !self.writable {
+            return Err(Error::new(ErrorKind::PermissionDenied, "the region file is opened read-only"));
+        }
+        Ok(())
+    }
+
+    /// Finds `count` contiguous free sectors, growing the file if there is
+    /// no such run, and marks them as used. Returns the first sector.
+    fn allocate(&mut self, count: usize) -> usize {
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for (index, free) in self.free_sectors.iter().enumerate() {
+            if !*free {
+                run_length = 0;
+                continue;
+            }
+            if run_length == 0 {
+                run_start = index;
+            }
+            run_length += 1;
+            if run_length == count {
+                break;
+            }
+        }
+        // A run touching the end of the file can be extended
+        if run_length < count {
+            if run_length == 0 || run_start + run_length != self.free_sectors.len() {
+                run_start = self.free_sectors.len();
+            }
+            self.free_sectors.resize(run_start + count, true);
+        }
+        for sector in run_start..run_start + count {
+            self.free_sectors[sector] = false;
+        }
+        run_start
+    }
+
+    /// Marks the sectors of the location as free
+    fn release(&mut self, location: i32) {
+        if location == 0 {
+            return;
+        }
+        let end = (sector_offset(location) + size_in_sectors(location)).min(self.free_sectors.len());
+        for sector in sector_offset(location).max(2)..end {
+            self.free_sectors[sector] = true;
+        }
+    }
+
+    fn set_location(&mut self, x: i32, z: i32, location: i32) -> Result<()> {
+        let index = get_index(x, z);
+        self.locations[index] = location;
+        write_all_at(&self.region_file, &location.to_be_bytes(), (index * 4) as u64)
     }
 
-    fn file_offset(&self, chunk_x: i32, chunk_z: i32) -> usize {
-        sector_offset(self.locations[get_index(chunk_x, chunk_z)]) * SECTOR_SIZE
+    fn set_timestamp(&mut self, x: i32, z: i32, timestamp: i32) -> Result<()> {
+        let index = get_index(x, z);
+        self.timestamps[index] = timestamp;
+        write_all_at(&self.region_file, &timestamp.to_be_bytes(), (SECTOR_SIZE + index * 4) as u64)
+    }
+
+    /// The path of the external file storing the chunk at the given region-local coordinates
+    fn external_file(&self, x: i32, z: i32) -> PathBuf {
+        let chunk_x = to_chunk(self.region_x) + x;
+        let chunk_z = to_chunk(self.region_z) + z;
+        self.directory.join(format!("c.{}.{}.mcc", chunk_x, chunk_z))
     }
 
     fn is_out(&self, chunk_x: i32, chunk_z: i32) -> bool {
-        println!("{}", to_region(chunk_x));
-        println!("{}", to_region(chunk_z));
         to_region(chunk_x) != self.region_x || to_region(chunk_z) != self.region_z
     }
 
     fn add_padding(file: &mut File) -> Result<()> {
-        let length = file.metadata().unwrap().len();
+        let length = file.metadata()?.len();
         let missing_padding = length % (SECTOR_SIZE as u64);
         // file is not a multiple of 4kib, add padding
         if missing_padding > 0 {
@@ -140,4 +395,235 @@ impl Region {
         }
         return Ok(());
     }
-}
\ No newline at end of file
+}
+
+/// The directory of the region file, where its external chunks are stored
+fn directory_of(path: &Path) -> PathBuf {
+    path.parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+/// The number of sectors needed to store `length` bytes
+fn sectors_for(length: usize) -> usize {
+    (length + SECTOR_SIZE - 1) / SECTOR_SIZE
+}
+
+fn now() -> i32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i32)
+        .unwrap_or(0)
+}
+
+/// Writes the file and waits for its data to reach the disk
+fn write_synced(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(())
+    }
+}
+
+fn corrupt(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Fills `buf` with the bytes at `offset`, without moving the file cursor.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Fills `buf` with the bytes at `offset`. Moves the file cursor.
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Writes all of `buf` at `offset`, without moving the file cursor.
+#[cfg(unix)]
+fn write_all_at(file: &File, buf: &[u8], offset: u64) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+/// Writes all of `buf` at `offset`. Moves the file cursor.
+#[cfg(windows)]
+fn write_all_at(file: &File, mut buf: &[u8], mut offset: u64) -> Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    use nbt::{Blob, Value};
+
+    use crate::region::region::{ChunkCompression, Region};
+
+    fn fixture(name: &str) -> PathBuf {
+        crate::region::fixture(name).join("r.0.0.mca")
+    }
+
+    fn x_pos(region: &Region, x: i32, z: i32) -> Value {
+        region.get_chunk_data(x, z).unwrap().unwrap().get("xPos").unwrap().clone()
+    }
+
+    #[test]
+    fn test_compression_types() {
+        let region = Region::open(&fixture("compression"), 0, 0).unwrap();
+        assert_eq!(x_pos(&region, 0, 0), Value::Int(0));
+        assert_eq!(x_pos(&region, 1, 0), Value::Int(1));
+        assert_eq!(x_pos(&region, 2, 0), Value::Int(2));
+    }
+
+    #[test]
+    fn test_missing_chunk() {
+        let region = Region::open(&fixture("missing"), 0, 0).unwrap();
+        assert!(region.get_chunk_data(10, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_external_chunk() {
+        let region = Region::open(&fixture("external"), 0, 0).unwrap();
+        assert_eq!(x_pos(&region, 3, 0), Value::Int(3));
+    }
+
+    #[test]
+    fn test_unknown_compression() {
+        let region = Region::open(&fixture("unknown"), 0, 0).unwrap();
+        let error = region.get_chunk_data(4, 0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let region = Region::open(&fixture("corrupt"), 0, 0).unwrap();
+        let error = region.get_chunk_data(5, 0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    fn chunk(x: i32, padding: usize) -> Blob {
+        let mut blob = Blob::new();
+        blob.insert("xPos", Value::Int(x)).unwrap();
+        blob.insert("padding", Value::ByteArray(vec![1; padding])).unwrap();
+        blob
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let path = fixture("write");
+        let mut region = Region::open(&path, 0, 0).unwrap();
+        region.write_chunk_data(20, 20, &chunk(20, 10_000), ChunkCompression::Zlib).unwrap();
+        region.write_chunk_data(0, 0, &chunk(100, 0), ChunkCompression::Gzip).unwrap();
+        drop(region);
+
+        let region = Region::open(&path, 0, 0).unwrap();
+        assert_eq!(x_pos(&region, 20, 20), Value::Int(20));
+        assert_eq!(x_pos(&region, 0, 0), Value::Int(100));
+        assert_eq!(x_pos(&region, 1, 0), Value::Int(1));
+    }
+
+    #[test]
+    fn test_oversized_chunk() {
+        let path = fixture("oversized");
+        let mut region = Region::open(&path, 0, 0).unwrap();
+        region.write_chunk_data(7, 7, &chunk(7, 2 * 1024 * 1024), ChunkCompression::None).unwrap();
+        assert!(path.with_file_name("c.7.7.mcc").exists());
+        assert_eq!(x_pos(&region, 7, 7), Value::Int(7));
+
+        // Rewritten through a temporary file
+        region.write_chunk_data(7, 7, &chunk(9, 2 * 1024 * 1024), ChunkCompression::None).unwrap();
+        assert!(!path.with_file_name("c.7.7.mcc.tmp").exists());
+        assert_eq!(x_pos(&region, 7, 7), Value::Int(9));
+
+        region.write_chunk_data(7, 7, &chunk(8, 0), ChunkCompression::None).unwrap();
+        assert!(!path.with_file_name("c.7.7.mcc").exists());
+        assert_eq!(x_pos(&region, 7, 7), Value::Int(8));
+    }
+
+    #[test]
+    fn test_delete_and_compact() {
+        let path = fixture("compact");
+        let mut region = Region::open(&path, 0, 0).unwrap();
+        region.delete_chunk(4, 0).unwrap();
+        region.delete_chunk(5, 0).unwrap();
+        region.delete_chunk(0, 0).unwrap();
+        assert!(region.get_chunk_data(0, 0).unwrap().is_none());
+
+        let length = fs::metadata(&path).unwrap().len();
+        region.compact().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < length);
+        drop(region);
+
+        let region = Region::open(&path, 0, 0).unwrap();
+        assert_eq!(x_pos(&region, 1, 0), Value::Int(1));
+        assert_eq!(x_pos(&region, 2, 0), Value::Int(2));
+        assert_eq!(x_pos(&region, 3, 0), Value::Int(3));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let path = fixture("read-only");
+        // Files cut short are not padded
+        let mut contents = fs::read(&path).unwrap();
+        contents.truncate(contents.len() - 100);
+        fs::write(&path, &contents).unwrap();
+
+        let external = fs::read(path.with_file_name("c.3.0.mcc")).unwrap();
+
+        let mut region = Region::open_read_only(&path, 0, 0).unwrap();
+        assert_eq!(x_pos(&region, 1, 0), Value::Int(1));
+        let denied = |result: std::io::Result<()>| result.err().map(|why| why.kind()) == Some(ErrorKind::PermissionDenied);
+        assert!(denied(region.write_chunk_data(7, 0, &chunk(7, 0), ChunkCompression::Zlib)));
+        // Neither moving the external chunk into the region nor rewriting it
+        // touches its file
+        assert!(denied(region.write_chunk_data(3, 0, &chunk(3, 0), ChunkCompression::Zlib)));
+        assert!(denied(region.write_chunk_data(3, 0, &chunk(3, 2 * 1024 * 1024), ChunkCompression::None)));
+        assert!(denied(region.delete_chunk(3, 0)));
+        assert!(denied(region.compact()));
+        assert_eq!(x_pos(&region, 3, 0), Value::Int(3));
+        drop(region);
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        assert_eq!(fs::read(path.with_file_name("c.3.0.mcc")).unwrap(), external);
+        assert!(!path.with_file_name("c.3.0.mcc.tmp").exists());
+
+        // Missing files are not created
+        let missing = path.with_file_name("r.1.0.mca");
+        assert_eq!(Region::open_read_only(&missing, 1, 0).err().unwrap().kind(), ErrorKind::NotFound);
+        assert!(!missing.exists());
+    }
+}
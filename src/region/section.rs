@@ -2,14 +2,17 @@ use std::path::Path;
 
 const ZERO: ChunkPos = ChunkPos { x: 0, z: 0 };
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct ChunkPos {
     x: i32,
     z: i32,
 }
 
 impl ChunkPos {
-    fn get_from_file(file: &Path) -> Option<ChunkPos> {
-        let name = file.file_name().unwrap().to_str().unwrap();
+    /// Returns the position of the first chunk in the region file, if
+    /// `file` is named like a region file (`r.<x>.<z>.mca`).
+    pub fn get_from_file(file: &Path) -> Option<ChunkPos> {
+        let name = file.file_name()?.to_str()?;
         if !name.starts_with("r.") || !name.ends_with(".mca") {
             return None;
         }
@@ -17,8 +20,8 @@ impl ChunkPos {
         if split.len() != 4 {
             return None;
         }
-        let x = split[0].parse::<i32>().unwrap();
-        let z = split[1].parse::<i32>().unwrap();
+        let x = split[1].parse::<i32>().ok()?;
+        let z = split[2].parse::<i32>().ok()?;
         Some(ChunkPos { x: x << 5, z: z << 5 })
     }
 
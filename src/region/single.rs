@@ -1,17 +1,37 @@
-use crate::region::palette::Palette;
+use crate::region::palette::{Palette, PaletteKind};
 
+/// A palette where every entry has the same value. Uses 0 bits per entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SingleValuePalette {
-    dimension: u8,
+    kind: PaletteKind,
     value: i32,
 }
 
+impl SingleValuePalette {
+    pub fn new(kind: PaletteKind, value: i32) -> Self {
+        Self { kind, value }
+    }
+
+    pub fn kind(&self) -> PaletteKind {
+        self.kind
+    }
+
+    pub(crate) fn set_kind(&mut self, kind: PaletteKind) {
+        self.kind = kind;
+    }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+}
+
 impl Palette for SingleValuePalette {
     fn get(&self, _x: i32, _y: i32, _z: i32) -> i32 {
         self.value
     }
 
     fn dimension(&self) -> i32 {
-        self.dimension as i32
+        self.kind.dimension
     }
 
     fn max_bits_per_entry(&self) -> Option<i32> {
@@ -22,4 +42,3 @@ impl Palette for SingleValuePalette {
         None
     }
 }
-
@@ -0,0 +1,89 @@
+use std::io::{Error, ErrorKind, Result};
+
+/// Fixed-size entries packed into longs, `bits` at a time.
+///
+/// Since 1.16 an entry never spans two longs: the unused high bits of each
+/// long are left empty. Both the network protocol and Anvil use this layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedArray {
+    bits: u8,
+    size: usize,
+    data: Vec<i64>,
+}
+
+impl PackedArray {
+    pub fn new(bits: u8, size: usize) -> Self {
+        Self {
+            bits,
+            size,
+            data: vec![0; Self::longs_needed(bits, size)],
+        }
+    }
+
+    /// Wraps already packed data, failing if it has the wrong length.
+    pub fn from_data(bits: u8, size: usize, data: Vec<i64>) -> Result<Self> {
+        let expected = Self::longs_needed(bits, size);
+        if data.len() != expected {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "expected {} longs to store {} entries of {} bits, found {}", expected, size, bits, data.len()
+            )));
+        }
+        Ok(Self { bits, size, data })
+    }
+
+    /// The number of longs needed to store `size` entries of `bits` bits
+    pub fn longs_needed(bits: u8, size: usize) -> usize {
+        if bits == 0 {
+            return 0;
+        }
+        let per_long = 64 / bits as usize;
+        size.div_ceil(per_long)
+    }
+
+    pub fn get(&self, index: usize) -> u32 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_long = 64 / self.bits as usize;
+        let long = self.data[index / per_long] as u64;
+        let shift = (index % per_long) * self.bits as usize;
+        ((long >> shift) & self.mask()) as u32
+    }
+
+    pub fn set(&mut self, index: usize, value: u32) {
+        if self.bits == 0 {
+            return;
+        }
+        let per_long = 64 / self.bits as usize;
+        let shift = (index % per_long) * self.bits as usize;
+        let mask = self.mask();
+        let long = &mut self.data[index / per_long];
+        let cleared = (*long as u64) & !(mask << shift);
+        *long = (cleared | ((value as u64 & mask) << shift)) as i64;
+    }
+
+    /// Copies every entry into an array using `bits` bits per entry
+    pub fn resize(&self, bits: u8) -> PackedArray {
+        let mut resized = PackedArray::new(bits, self.size);
+        for index in 0..self.size {
+            resized.set(index, self.get(index));
+        }
+        resized
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn data(&self) -> &Vec<i64> {
+        &self.data
+    }
+
+    fn mask(&self) -> u64 {
+        (1_u64 << self.bits) - 1
+    }
+}
@@ -0,0 +1,453 @@
+use std::fs;
+use std::io::Result;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+use crate::client::client::ClientMessage;
+use crate::packets::handler::login::error::LoginError;
+use crate::server::player_count::{ConnectedPlayer, Players};
+
+pub const BANNED_PLAYERS_FILE: &str = "banned-players.json";
+pub const BANNED_IPS_FILE: &str = "banned-ips.json";
+pub const WHITELIST_FILE: &str = "whitelist.json";
+
+/// An entry of an [AccessList], identified by a key
+pub trait ListEntry {
+    type Key: PartialEq;
+
+    fn key(&self) -> Self::Key;
+
+    /// Whether the entry no longer applies and can be dropped
+    fn is_expired(&self, _now: u64) -> bool {
+        false
+    }
+}
+
+/// Why, by whom and for how long something was banned
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BanDetails {
+    pub reason: String,
+    pub source: String,
+    /// Seconds since the unix epoch at which the ban was created
+    pub created: u64,
+    /// Seconds since the unix epoch at which the ban is lifted. `None` bans forever.
+    #[serde(default)]
+    pub expires: Option<u64>,
+}
+
+impl BanDetails {
+    pub fn new(reason: &str, source: &str, expires: Option<u64>) -> Self {
+        Self {
+            reason: reason.to_string(),
+            source: source.to_string(),
+            created: now(),
+            expires,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.map_or(false, |expires| expires <= now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerBan {
+    pub uuid: Uuid,
+    pub name: String,
+    #[serde(flatten)]
+    pub details: BanDetails,
+}
+
+impl ListEntry for PlayerBan {
+    type Key = Uuid;
+
+    fn key(&self) -> Uuid {
+        self.uuid
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.details.is_expired(now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IpBan {
+    pub ip: IpAddr,
+    #[serde(flatten)]
+    pub details: BanDetails,
+}
+
+impl ListEntry for IpBan {
+    type Key = IpAddr;
+
+    fn key(&self) -> IpAddr {
+        self.ip
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.details.is_expired(now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WhitelistEntry {
+    pub uuid: Uuid,
+    pub name: String,
+}
+
+impl ListEntry for WhitelistEntry {
+    type Key = Uuid;
+
+    fn key(&self) -> Uuid {
+        self.uuid
+    }
+}
+
+/// A list of entries stored as a JSON array in a file. Every change is
+/// written back to the file right away, on a blocking thread when called
+/// from the tokio runtime.
+pub struct AccessList<T> {
+    path: PathBuf,
+    entries: Arc<RwLock<Vec<T>>>,
+    // Held while writing, so writes never overlap and the last one always
+    // writes the latest entries
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl<T: ListEntry + Serialize + DeserializeOwned + Clone + Send + Sync + 'static> AccessList<T> {
+    /// Loads the list stored at `path`, creating an empty file if it does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let list = Self {
+            path: path.to_path_buf(),
+            entries: Arc::new(RwLock::new(Vec::new())),
+            write_lock: Arc::new(Mutex::new(())),
+        };
+        if path.exists() {
+            list.reload()?;
+        } else {
+            list.save()?;
+        }
+        Ok(list)
+    }
+
+    /// Replaces the entries in memory with the contents of the file
+    pub fn reload(&self) -> Result<()> {
+        let json = fs::read_to_string(&self.path)?;
+        let entries: Vec<T> = serde_json::from_str(&json)?;
+        *self.entries.write().unwrap() = entries;
+        Ok(())
+    }
+
+    /// Writes the entries to the file, blocking until they are written
+    pub fn save(&self) -> Result<()> {
+        write_entries(&self.path, &self.entries, &self.write_lock)
+    }
+
+    /// Writes the entries to the file without blocking the runtime.
+    /// Failures are logged.
+    fn save_changes(&self) {
+        let (path, entries, write_lock) = (self.path.clone(), Arc::clone(&self.entries), Arc::clone(&self.write_lock));
+        let write = move || {
+            if let Err(why) = write_entries(&path, &entries, &write_lock) {
+                eprintln!("failed to save {:?}: {}", path, why);
+            }
+        };
+        match Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(write)),
+            Err(_) => write(),
+        }
+    }
+
+    /// Returns the entry with the given key, unless it has expired
+    pub fn get(&self, key: &T::Key) -> Option<T> {
+        let now = now();
+        self.entries.read().unwrap()
+            .iter()
+            .find(|entry| entry.key() == *key && !entry.is_expired(now))
+            .cloned()
+    }
+
+    pub fn contains(&self, key: &T::Key) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn entries(&self) -> Vec<T> {
+        self.entries.read().unwrap().clone()
+    }
+
+    /// Adds the entry, replacing any entry with the same key
+    pub fn add(&self, entry: T) {
+        {
+            let mut entries = self.entries.write().unwrap();
+            let key = entry.key();
+            entries.retain(|existing| existing.key() != key);
+            entries.push(entry);
+        }
+        self.save_changes();
+    }
+
+    /// Removes the entry with the given key. Returns whether it existed.
+    pub fn remove(&self, key: &T::Key) -> bool {
+        let removed = {
+            let mut entries = self.entries.write().unwrap();
+            let length = entries.len();
+            entries.retain(|existing| existing.key() != *key);
+            entries.len() != length
+        };
+        if removed {
+            self.save_changes();
+        }
+        removed
+    }
+
+    /// Drops every expired entry
+    pub fn prune(&self) {
+        let now = now();
+        self.entries.write().unwrap().retain(|entry| !entry.is_expired(now));
+        self.save_changes();
+    }
+}
+
+fn write_entries<T: Serialize>(path: &Path, entries: &RwLock<Vec<T>>, write_lock: &Mutex<()>) -> Result<()> {
+    let _writing = write_lock.lock().unwrap();
+    let json = serde_json::to_string_pretty(&*entries.read().unwrap())?;
+    fs::write(path, json)
+}
+
+/// The reason a connection was refused by [AccessControl]
+#[derive(Debug, Clone)]
+pub enum AccessDenied {
+    Banned(BanDetails),
+    IpBanned(BanDetails),
+    NotWhitelisted,
+}
+
+/// The ban lists and the whitelist of the server, stored in the run directory
+pub struct AccessControl {
+    banned_players: AccessList<PlayerBan>,
+    banned_ips: AccessList<IpBan>,
+    whitelist: AccessList<WhitelistEntry>,
+    whitelist_enabled: AtomicBool,
+}
+
+impl AccessControl {
+    pub fn load(run_directory: &Path, whitelist_enabled: bool) -> Result<Self> {
+        Ok(Self {
+            banned_players: AccessList::load(&run_directory.join(BANNED_PLAYERS_FILE))?,
+            banned_ips: AccessList::load(&run_directory.join(BANNED_IPS_FILE))?,
+            whitelist: AccessList::load(&run_directory.join(WHITELIST_FILE))?,
+            whitelist_enabled: AtomicBool::new(whitelist_enabled),
+        })
+    }
+
+    /// Re-reads every list from disk
+    pub fn reload(&self) -> Result<()> {
+        self.banned_players.reload()?;
+        self.banned_ips.reload()?;
+        self.whitelist.reload()
+    }
+
+    /// Checks whether connections from the address are allowed
+    pub fn check_ip(&self, ip: &IpAddr) -> std::result::Result<(), AccessDenied> {
+        match self.banned_ips.get(ip) {
+            Some(ban) => Err(AccessDenied::IpBanned(ban.details)),
+            None => Ok(())
+        }
+    }
+
+    /// Checks whether the player is allowed to join
+    pub fn check_player(&self, id: &Uuid) -> std::result::Result<(), AccessDenied> {
+        if let Some(ban) = self.banned_players.get(id) {
+            return Err(AccessDenied::Banned(ban.details));
+        }
+        if self.is_whitelist_enabled() && !self.whitelist.contains(id) {
+            return Err(AccessDenied::NotWhitelisted);
+        }
+        Ok(())
+    }
+
+    pub fn banned_players(&self) -> &AccessList<PlayerBan> {
+        &self.banned_players
+    }
+
+    pub fn banned_ips(&self) -> &AccessList<IpBan> {
+        &self.banned_ips
+    }
+
+    pub fn whitelist(&self) -> &AccessList<WhitelistEntry> {
+        &self.whitelist
+    }
+
+    pub fn is_whitelist_enabled(&self) -> bool {
+        self.whitelist_enabled.load(Ordering::SeqCst)
+    }
+
+    /// Enables or disables the whitelist. Enabling it disconnects the
+    /// online players who are not on it.
+    pub fn set_whitelist_enabled(&self, enabled: bool, players: &Players) {
+        self.whitelist_enabled.store(enabled, Ordering::SeqCst);
+        if enabled {
+            kick(players, |id, _| !self.whitelist.contains(id), AccessDenied::NotWhitelisted);
+        }
+    }
+
+    /// Bans the player, disconnecting them if they are online
+    pub fn ban_player(&self, ban: PlayerBan, players: &Players) {
+        let (id, details) = (ban.uuid, ban.details.clone());
+        self.banned_players.add(ban);
+        kick(players, |player_id, _| *player_id == id, AccessDenied::Banned(details));
+    }
+
+    /// Bans the address, disconnecting the players connected from it
+    pub fn ban_ip(&self, ban: IpBan, players: &Players) {
+        let (ip, details) = (ban.ip, ban.details.clone());
+        self.banned_ips.add(ban);
+        kick(players, |_, player| player.address().ip() == ip, AccessDenied::IpBanned(details));
+    }
+
+    /// Removes the player from the whitelist, disconnecting them if the
+    /// whitelist is enabled. Returns whether they were on it.
+    pub fn remove_from_whitelist(&self, id: &Uuid, players: &Players) -> bool {
+        let removed = self.whitelist.remove(id);
+        if removed && self.is_whitelist_enabled() {
+            kick(players, |player_id, _| player_id == id, AccessDenied::NotWhitelisted);
+        }
+        removed
+    }
+}
+
+/// Disconnects the online players matching the predicate, telling them why
+fn kick(players: &Players, matches: impl Fn(&Uuid, &ConnectedPlayer) -> bool, denied: AccessDenied) {
+    for sessions in players.connected_clients().iter() {
+        for player in sessions.value().iter().filter(|player| matches(sessions.key(), player)) {
+            let reason = LoginError::AccessDenied(denied.clone()).reason();
+            let _ = player.messages().send(ClientMessage::Disconnect(reason));
+        }
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+    use uuid::Uuid;
+
+    use crate::client::client::ClientMessage;
+    use crate::server::access::{AccessControl, AccessDenied, AccessList, BanDetails, IpBan, now, PlayerBan, WhitelistEntry, BANNED_PLAYERS_FILE};
+    use crate::server::player_count::{ConnectedPlayer, DuplicateLoginPolicy, Players};
+    use crate::util::test_directory;
+
+    fn ban(id: u128, expires: Option<u64>) -> PlayerBan {
+        PlayerBan {
+            uuid: Uuid::from_u128(id),
+            name: "Steve".to_string(),
+            details: BanDetails::new("griefing", "Server", expires),
+        }
+    }
+
+    fn join(players: &Players, id: u128, port: u16) -> UnboundedReceiver<ClientMessage> {
+        let (sender, receiver) = unbounded_channel();
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        players.player_joined(Uuid::from_u128(id), ConnectedPlayer::new("Steve".to_string(), address, sender), DuplicateLoginPolicy::Allow);
+        receiver
+    }
+
+    fn was_kicked(receiver: &mut UnboundedReceiver<ClientMessage>) -> bool {
+        matches!(receiver.try_recv(), Ok(ClientMessage::Disconnect(_)))
+    }
+
+    #[test]
+    fn test_ban_expiry() {
+        let run_directory = test_directory("access-expiry");
+        let directory = run_directory.path();
+        let access = AccessControl::load(directory, false).unwrap();
+        access.banned_players().add(ban(1, None));
+        access.banned_players().add(ban(2, Some(now() - 1)));
+        access.banned_players().add(ban(3, Some(now() + 3600)));
+        assert!(matches!(access.check_player(&Uuid::from_u128(1)), Err(AccessDenied::Banned(_))));
+        assert!(access.check_player(&Uuid::from_u128(2)).is_ok());
+        assert!(matches!(access.check_player(&Uuid::from_u128(3)), Err(AccessDenied::Banned(_))));
+
+        access.banned_players().prune();
+        let reloaded = AccessList::<PlayerBan>::load(&directory.join(BANNED_PLAYERS_FILE)).unwrap();
+        let mut remaining: Vec<u128> = reloaded.entries().iter().map(|ban| ban.uuid.as_u128()).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_whitelist_check() {
+        let run_directory = test_directory("access-whitelist");
+        let directory = run_directory.path();
+        let players = Players::new();
+        let access = AccessControl::load(directory, false).unwrap();
+        let id = Uuid::from_u128(1);
+        assert!(access.check_player(&id).is_ok());
+
+        access.set_whitelist_enabled(true, &players);
+        assert!(matches!(access.check_player(&id), Err(AccessDenied::NotWhitelisted)));
+        access.whitelist().add(WhitelistEntry { uuid: id, name: "Steve".to_string() });
+        assert!(access.check_player(&id).is_ok());
+
+        // The list survives a restart
+        let reloaded = AccessControl::load(directory, true).unwrap();
+        assert!(reloaded.check_player(&id).is_ok());
+        assert!(reloaded.check_player(&Uuid::from_u128(2)).is_err());
+    }
+
+    #[test]
+    fn test_kicks() {
+        let run_directory = test_directory("access-kicks");
+        let directory = run_directory.path();
+        let players = Players::new();
+        let access = AccessControl::load(directory, false).unwrap();
+        let mut banned = join(&players, 1, 1000);
+        let mut other = join(&players, 2, 1001);
+        let mut banned_again = join(&players, 1, 1002);
+        access.ban_player(ban(1, None), &players);
+        assert!(was_kicked(&mut banned));
+        assert!(was_kicked(&mut banned_again), "every session of the player is kicked");
+        assert!(!was_kicked(&mut other));
+
+        let ip_ban = IpBan { ip: "127.0.0.1".parse().unwrap(), details: BanDetails::new("", "Server", None) };
+        access.ban_ip(ip_ban, &players);
+        assert!(was_kicked(&mut other));
+        assert!(access.check_ip(&"127.0.0.1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_whitelist_kicks() {
+        let run_directory = test_directory("access-whitelist-kicks");
+        let directory = run_directory.path();
+        let players = Players::new();
+        let access = AccessControl::load(directory, false).unwrap();
+        access.whitelist().add(WhitelistEntry { uuid: Uuid::from_u128(1), name: "Steve".to_string() });
+        let mut listed = join(&players, 1, 1000);
+        let mut unlisted = join(&players, 2, 1001);
+
+        access.set_whitelist_enabled(true, &players);
+        assert!(!was_kicked(&mut listed));
+        assert!(was_kicked(&mut unlisted));
+
+        assert!(access.remove_from_whitelist(&Uuid::from_u128(1), &players));
+        assert!(was_kicked(&mut listed));
+        assert!(!access.remove_from_whitelist(&Uuid::from_u128(1), &players));
+    }
+}
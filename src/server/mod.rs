@@ -1,2 +1,3 @@
 pub mod server;
 pub mod player_count;
+pub mod access;
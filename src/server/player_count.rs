@@ -2,10 +2,66 @@ use std::net::SocketAddr;
 use std::sync::atomic::AtomicUsize;
 
 use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::Deserialize;
 use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use crate::client::client::ClientMessage;
+
+/// What to do when a player logs in while a session with the same UUID
+/// is already connected.
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateLoginPolicy {
+    /// Disconnect the existing session and let the new one in
+    #[default]
+    KickOld,
+    /// Keep the existing session and refuse the new one
+    RejectNew,
+    /// Let both sessions play. Each has its own entity in the worlds.
+    Allow,
+}
+
+/// A logged-in player's connection
+pub struct ConnectedPlayer {
+    name: String,
+    address: SocketAddr,
+    messages: UnboundedSender<ClientMessage>,
+}
+
+impl ConnectedPlayer {
+    pub fn new(name: String, address: SocketAddr, messages: UnboundedSender<ClientMessage>) -> Self {
+        Self { name, address, messages }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
+    pub fn messages(&self) -> &UnboundedSender<ClientMessage> {
+        &self.messages
+    }
+}
+
+/// The result of [Players::player_joined]
+pub enum JoinOutcome {
+    /// No other session with the same UUID was connected
+    Joined,
+    /// The player joined, and the returned sessions should be disconnected
+    Replaced(Vec<ConnectedPlayer>),
+    /// A session with the same UUID is connected and the player must be refused
+    Rejected,
+}
 
 pub struct Players {
-    connected_clients: DashMap<SocketAddr, UnboundedSender<Vec<u8>>>,
+    // The sessions of each player, oldest first. Players only have several
+    // under the allow policy.
+    connected_clients: DashMap<Uuid, Vec<ConnectedPlayer>>,
 }
 
 impl Players {
@@ -13,20 +69,129 @@ impl Players {
         Self { connected_clients: DashMap::new() }
     }
 
-    pub fn connected_clients(&self) -> &DashMap<SocketAddr, UnboundedSender<Vec<u8>>> {
+    pub fn connected_clients(&self) -> &DashMap<Uuid, Vec<ConnectedPlayer>> {
         &self.connected_clients
     }
 
+    /// The number of sessions connected
     pub fn count(&self) -> usize {
         // Maybe consider keeping a track in a separate AtomicUsize?
-        self.connected_clients.len()
+        self.connected_clients.iter().map(|sessions| sessions.len()).sum()
+    }
+
+    pub fn is_online(&self, id: &Uuid) -> bool {
+        self.connected_clients.contains_key(id)
+    }
+
+    /// Registers a player that finished authenticating, applying `policy`
+    /// if a session with the same UUID is already connected.
+    pub fn player_joined(&self, id: Uuid, player: ConnectedPlayer, policy: DuplicateLoginPolicy) -> JoinOutcome {
+        match self.connected_clients.entry(id) {
+            Entry::Vacant(entry) => {
+                entry.insert(vec![player]);
+                JoinOutcome::Joined
+            }
+            Entry::Occupied(mut entry) => match policy {
+                DuplicateLoginPolicy::RejectNew => JoinOutcome::Rejected,
+                DuplicateLoginPolicy::KickOld => JoinOutcome::Replaced(entry.insert(vec![player])),
+                DuplicateLoginPolicy::Allow => {
+                    entry.get_mut().push(player);
+                    JoinOutcome::Joined
+                }
+            }
+        }
+    }
+
+    /// Removes the session of the player connected from `address`. Sessions
+    /// that were replaced are already gone.
+    pub fn player_left(&self, id: &Uuid, address: &SocketAddr) {
+        if let Entry::Occupied(mut entry) = self.connected_clients.entry(*id) {
+            entry.get_mut().retain(|player| player.address() != address);
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde::Deserialize;
+    use serde::de::IntoDeserializer;
+    use serde::de::value::Error;
+    use tokio::sync::mpsc::unbounded_channel;
+    use uuid::Uuid;
+
+    use crate::server::player_count::{ConnectedPlayer, DuplicateLoginPolicy, JoinOutcome, Players};
+
+    fn player(port: u16) -> ConnectedPlayer {
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        ConnectedPlayer::new("Steve".to_string(), address, unbounded_channel().0)
     }
 
-    pub fn player_joined(&self, addr: SocketAddr, packets: UnboundedSender<Vec<u8>>) {
-        self.connected_clients.insert(addr, packets);
+    fn ports(sessions: &[ConnectedPlayer]) -> Vec<u16> {
+        sessions.iter().map(|player| player.address().port()).collect()
     }
 
-    pub fn player_left(&self, addr: &SocketAddr) {
-        self.connected_clients.remove(addr);
+    #[test]
+    fn test_kick_old() {
+        let players = Players::new();
+        let id = Uuid::from_u128(1);
+        assert!(matches!(players.player_joined(id, player(1000), DuplicateLoginPolicy::KickOld), JoinOutcome::Joined));
+        match players.player_joined(id, player(1001), DuplicateLoginPolicy::KickOld) {
+            JoinOutcome::Replaced(old) => assert_eq!(ports(&old), vec![1000]),
+            _ => panic!("the old session was not replaced"),
+        }
+        assert_eq!(players.count(), 1);
+
+        // The kicked session leaving keeps the new one
+        players.player_left(&id, &player(1000).address);
+        assert!(players.is_online(&id));
+        players.player_left(&id, &player(1001).address);
+        assert!(!players.is_online(&id));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_reject_new() {
+        let players = Players::new();
+        let id = Uuid::from_u128(2);
+        assert!(matches!(players.player_joined(id, player(1000), DuplicateLoginPolicy::RejectNew), JoinOutcome::Joined));
+        assert!(matches!(players.player_joined(id, player(1001), DuplicateLoginPolicy::RejectNew), JoinOutcome::Rejected));
+        assert_eq!(ports(&players.connected_clients().get(&id).unwrap()), vec![1000]);
+
+        let other = Uuid::from_u128(3);
+        assert!(matches!(players.player_joined(other, player(1002), DuplicateLoginPolicy::RejectNew), JoinOutcome::Joined));
+        assert_eq!(players.count(), 2);
+    }
+
+    #[test]
+    fn test_allow() {
+        let players = Players::new();
+        let id = Uuid::from_u128(4);
+        for port in [1000, 1001, 1002] {
+            assert!(matches!(players.player_joined(id, player(port), DuplicateLoginPolicy::Allow), JoinOutcome::Joined));
+        }
+        assert_eq!(players.count(), 3);
+        assert_eq!(ports(&players.connected_clients().get(&id).unwrap()), vec![1000, 1001, 1002]);
+
+        // Each session leaves on its own
+        players.player_left(&id, &player(1001).address);
+        assert_eq!(ports(&players.connected_clients().get(&id).unwrap()), vec![1000, 1002]);
+        players.player_left(&id, &player(1000).address);
+        players.player_left(&id, &player(1002).address);
+        assert!(!players.is_online(&id));
+        assert_eq!(players.count(), 0);
+    }
+
+    #[test]
+    fn test_policy_names() {
+        let parse = |name: &str| DuplicateLoginPolicy::deserialize(IntoDeserializer::<Error>::into_deserializer(name));
+        assert_eq!(parse("kick-old").unwrap(), DuplicateLoginPolicy::KickOld);
+        assert_eq!(parse("reject-new").unwrap(), DuplicateLoginPolicy::RejectNew);
+        assert_eq!(parse("allow").unwrap(), DuplicateLoginPolicy::Allow);
+        assert!(parse("kick-new").is_err());
+        assert_eq!(DuplicateLoginPolicy::default(), DuplicateLoginPolicy::KickOld);
+    }
+}
@@ -3,33 +3,53 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU8, AtomicUsize};
+use std::time::Duration;
 use bevy::app::App;
+use bevy::log::error;
 
 use bytebuffer::ByteBuffer;
 use bytes::Buf;
 use futures::SinkExt;
+use nbt::Blob;
 use tokio::io::AsyncWriteExt;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
 use tokio::select;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::{Mutex, RwLock};
 use tokio_stream::StreamExt;
 use app::setup_netherite_app;
 
+use auth::cache::ProfileCache;
+use protocol::fields::key::Key;
 use protocol::Serverbound;
 
 use crate::client::client::{Client, ProtocolStage};
 use crate::config::ServerProperties;
+use crate::dimension::biome::BiomeProperties;
+use crate::region::level::LEVEL_FILE;
+use crate::dimension::registry::RegistryCodec;
 use crate::encryption::server::ServerEncryption;
 use crate::{app, packets};
 use crate::packets::handler;
 use crate::packets::handler::handshake::receive_handshake;
 use crate::packets::handler::login::receive_login;
-use crate::packets::handler::play::{join_player, receive_play};
+use crate::packets::handler::play::{join_player, login_play, receive_play};
 use crate::packets::handler::status::receive_status;
 use crate::packets::handshake::{self, Handshake};
+use crate::server::access::AccessControl;
 use crate::server::player_count::Players;
-use crate::world::worlds::Worlds;
+use crate::world::anvil::AnvilWorld;
+use crate::world::streaming::ChunkStreaming;
+use crate::world::player::{KnownDimensionTypes, PlayerPosition};
+use crate::world::world::{parse_seed, WorldInfo};
+use crate::world::worlds::{DEFAULT_WORLD, PlayerEvent, Worlds};
+
+/// The file, relative to the run directory, that cached profiles are stored in.
+pub const PROFILE_CACHE_FILE: &str = "usercache.json";
+
+/// Where players appear in the default world until it has a spawn point
+const DEFAULT_SPAWN: PlayerPosition = PlayerPosition { x: 0.5, y: 64.0, z: 0.5 };
 
 pub(crate) static ENTITY_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
@@ -38,67 +58,108 @@ pub struct Server {
     encryption: ServerEncryption,
     run_directory: PathBuf,
     players: Players,
-    worlds: Worlds,
+    profile_cache: ProfileCache,
+    access: AccessControl,
+    worlds: std::sync::Mutex<Worlds>,
+    player_events: flume::Sender<PlayerEvent>,
+    // Drained by the tick
+    pending_player_events: flume::Receiver<PlayerEvent>,
+    registry_codec: std::sync::RwLock<RegistryCodec>,
+    // The codec as sent to joining players, serialized when it changes
+    registry_blob: std::sync::RwLock<Blob>,
 }
 
 impl Server {
     pub fn new(
         properties: ServerProperties,
         run_directory: PathBuf,
+        registry_codec: RegistryCodec,
+        runtime: Handle,
     ) -> Self {
+        let encryption = ServerEncryption::load_or_create(
+            &run_directory,
+            *properties.server().key_size(),
+        ).expect("failed to load the server keypair");
+        let profile_cache = ProfileCache::load(
+            &run_directory.join(PROFILE_CACHE_FILE),
+            Duration::from_secs(properties.profiles().cache_ttl_days() * 24 * 60 * 60),
+        ).expect("failed to load the profile cache");
+        let access = AccessControl::load(&run_directory, *properties.server().whitelist())
+            .expect("failed to load the ban lists and whitelist");
+        let registry_blob = registry_codec.to_blob().expect("failed to serialize the registry codec");
+        let dimension_type = properties.game().dimension_type();
+        let default_type = registry_codec.dimension_type.get(dimension_type)
+            .unwrap_or_else(|| panic!("the dimension type {} is not registered", dimension_type))
+            .clone();
+        let streaming = ChunkStreaming {
+            view_distance: *properties.game().view_distance(),
+            chunks_per_tick: *properties.game().chunks_per_tick(),
+        };
+        let mut worlds = Worlds::new(streaming, registry_codec.biome.palette_kind());
+        let level_directory = run_directory.join(properties.game().level_name());
+        let anvil = level_directory.join(LEVEL_FILE).exists()
+            .then(|| AnvilWorld::load(&level_directory).expect("failed to read the level data"));
+        // Loaded worlds keep their seed
+        let seed = anvil.as_ref()
+            .map_or_else(|| parse_seed(properties.game().seed()), |anvil| anvil.level().seed());
+        let generator = properties.game().generator()
+            .create(properties.game().generator_settings(), seed, &registry_codec.biome)
+            .expect("invalid generator settings");
+        if let Some(anvil) = &anvil {
+            let read_only = *properties.game().read_only();
+            let biome_ids = registry_codec.biome.ids_by_name();
+            let overworld = Key::minecraft("overworld");
+            let info = WorldInfo::new(overworld.clone(), dimension_type.clone(), default_type, seed, anvil.spawn(&overworld));
+            worlds.load_world(info, anvil, &overworld, runtime.clone(), biome_ids.clone(), read_only)
+                .expect("failed to load the default world");
+            // The other vanilla dimensions, if the world has been to them
+            for name in ["the_nether", "the_end"] {
+                let dimension = Key::minecraft(name);
+                if !anvil.has_dimension(&dimension) {
+                    continue;
+                }
+                let Some(dimension_type) = registry_codec.dimension_type.get(&dimension) else {
+                    continue;
+                };
+                let info = WorldInfo::new(dimension.clone(), dimension.clone(), dimension_type.clone(), seed, anvil.spawn(&dimension));
+                worlds.load_world(info, anvil, &dimension, runtime.clone(), biome_ids.clone(), read_only)
+                    .unwrap_or_else(|why| panic!("failed to load the dimension {}: {:?}", dimension, why));
+            }
+        } else {
+            let mut spawn = DEFAULT_SPAWN;
+            if let Some(y) = generator.surface_y(spawn.x.floor() as i32, spawn.z.floor() as i32) {
+                spawn.y = y as f64;
+            }
+            worlds.new_world(WorldInfo::new(
+                Key::minecraft("overworld"),
+                dimension_type.clone(),
+                default_type,
+                seed,
+                spawn,
+            )).expect("failed to create the default world");
+        }
+        worlds.set_generator(DEFAULT_WORLD, generator, runtime);
+        let (player_events, pending_player_events) = flume::unbounded();
         Self {
             properties,
-            encryption: ServerEncryption::new(),
+            encryption,
             run_directory,
             players: Players::new(),
-            worlds: Worlds::new(),
+            profile_cache,
+            access,
+            worlds: std::sync::Mutex::new(worlds),
+            player_events,
+            pending_player_events,
+            registry_codec: std::sync::RwLock::new(registry_codec),
+            registry_blob: std::sync::RwLock::new(registry_blob),
         }
     }
 
     pub async fn finish_login(server: Arc<Self>, client: &mut Client) {
         client.set_stage(ProtocolStage::Play);
-        join_player(client, server).await;
+        join_player(client, &server);
     }
 
-    // let file = File::open("F:/Rust/netherite-rs/run/world/region/r.1.1.mca").unwrap();
-    // let region = Region::new(file, 1, 1).unwrap();
-    // let blob = region.get_chunk_data(30, 30).unwrap().expect("Expected data");
-    //
-    // let heightmap = blob.get("Heightmaps").expect("expected a heightmaps").clone();
-    //
-    // let sections = {
-    //     let v = blob.get("sections").unwrap();
-    //     let mut vec = Vec::new();
-    //     v.write_field(&mut vec).unwrap();
-    //     vec
-    // };
-    //
-    // let block_entities = {
-    //     let v = blob.get("block_entities").unwrap();
-    //     let mut vec = Vec::new();
-    //     v.write_field(&mut vec).unwrap();
-    //     vec
-    // };
-    //
-    //
-    // let packet = ChunkPacket {
-    //     chunk_x: 30,
-    //     chunk_z: 30,
-    //     heightmaps: heightmap,
-    //     data: sections,
-    //     block_entities,
-    //     trust_edges: false,
-    //     sky_light_mask: BitSet::new(),
-    //     block_light_mask: BitSet::new(),
-    //     empty_sky_light_mask: BitSet::new(),
-    //     empty_block_light_mask: BitSet::new(),
-    //     sky_light: [0; 2048],
-    //     block_light: [0; 2048],
-    // };
-    //
-    // client.write_packet(&packet).await.unwrap();
-    // }
-
     // pub async fn start(run_directory: PathBuf, properties: ServerProperties) -> Self {
     //     let address = properties.address();
     //     let listener = TcpListener::bind(address).await.expect(&*format!(
@@ -174,4 +235,121 @@ impl Server {
     pub fn players(&self) -> &Players {
         &self.players
     }
+
+    pub fn profile_cache(&self) -> &ProfileCache {
+        &self.profile_cache
+    }
+
+    /// Saves the profile cache on a blocking thread, unless a save is
+    /// already waiting to run
+    pub fn save_profile_cache(server: &Arc<Self>) {
+        if !server.profile_cache.request_save() {
+            return;
+        }
+        let server = Arc::clone(server);
+        tokio::task::spawn_blocking(move || {
+            if let Err(why) = server.profile_cache.save() {
+                error!("failed to save the profile cache: {}", why);
+            }
+        });
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    /// The worlds, which the app's systems lock. Connections send
+    /// [PlayerEvent]s instead of locking them.
+    pub fn worlds(&self) -> &std::sync::Mutex<Worlds> {
+        &self.worlds
+    }
+
+    /// Where connections send the changes to their player
+    pub fn player_events(&self) -> &flume::Sender<PlayerEvent> {
+        &self.player_events
+    }
+
+    /// Applies the player events sent since the previous tick, then runs
+    /// the systems of every world
+    pub fn tick_worlds(&self) {
+        let mut worlds = self.worlds.lock().unwrap();
+        for event in self.pending_player_events.try_iter() {
+            match event {
+                PlayerEvent::Join(connection) => {
+                    connection.send_packet(&login_play(self, &worlds));
+                    let dimension_types = self.registry_codec().dimension_type.entries().iter()
+                        .map(|entry| entry.name().clone())
+                        .collect();
+                    worlds.add_player(DEFAULT_WORLD, connection, KnownDimensionTypes(dimension_types));
+                }
+                PlayerEvent::Leave(session) => worlds.remove_player(&session),
+                PlayerEvent::ViewDistance(session, view_distance) => worlds.set_view_distance(&session, view_distance),
+                PlayerEvent::Move(session, position) => worlds.move_player(&session, position),
+                PlayerEvent::ConfirmTeleport(session, teleport_id) => worlds.confirm_teleport(&session, teleport_id),
+            }
+        }
+        worlds.tick();
+    }
+
+    pub fn registry_codec(&self) -> std::sync::RwLockReadGuard<RegistryCodec> {
+        self.registry_codec.read().unwrap()
+    }
+
+    /// The codec sent to joining players
+    pub fn registry_blob(&self) -> Blob {
+        self.registry_blob.read().unwrap().clone()
+    }
+
+    /// Registers a biome while the server runs, returning its id. Only
+    /// players joining afterwards know about it, so it should not be placed
+    /// in chunks sent to players already online. Fails if players are online
+    /// and the biome containers would need more bits, which their clients
+    /// would read the chunks with.
+    pub fn add_biome(&self, name: Key, biome: BiomeProperties) -> anyhow::Result<i32> {
+        // Locked first, like the tick does before reading the codec
+        let mut worlds = self.worlds.lock().unwrap();
+        let mut codec = self.registry_codec.write().unwrap();
+        let mut updated = codec.clone();
+        let id = updated.add_biome(name, biome);
+        let blob = updated.to_blob()?;
+        worlds.set_biome_kind(updated.biome.palette_kind())?;
+        *codec = updated;
+        *self.registry_blob.write().unwrap() = blob;
+        Ok(id)
+    }
+}
+
+/// The settings of [test_server]
+#[cfg(test)]
+const TEST_PROPERTIES: &str = r#"
+[server]
+address = "127.0.0.1"
+port = 25565
+compression-threshold = 256
+online-mode = false
+
+[status]
+motd = "test"
+max-players = 10
+icon = "server-icon.png"
+
+[game]
+default-gamemode = "survival"
+difficulty = "peaceful"
+view-distance = 2
+simulation-disance = 2
+reduce-debug-info = false
+enable-respawn-screen = true
+generator = "void"
+"#;
+
+/// Creates an offline-mode server with a void default world, run from the
+/// given directory. Must be called from a tokio runtime.
+#[cfg(test)]
+pub(crate) fn test_server(run_directory: &std::path::Path) -> Server {
+    let icon = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("run/server-icon.png");
+    std::fs::copy(icon, run_directory.join("server-icon.png")).unwrap();
+    std::fs::write(run_directory.join("server.toml"), TEST_PROPERTIES).unwrap();
+    let properties = ServerProperties::from_file(&run_directory.join("server.toml"));
+    Server::new(properties, run_directory.to_path_buf(), RegistryCodec::vanilla(), Handle::current())
 }
@@ -22,4 +22,15 @@ impl<'de> Visitor<'de> for StringVisitor {
     fn visit_string<E>(self, v: String) -> Result<Self::Value, E> where E: Error {
         Ok(v)
     }
-}
\ No newline at end of file
+}
+
+/// Creates an empty directory for a test, removed when the handle is
+/// dropped. The test's name starts the directory's, to find it when a test
+/// fails.
+#[cfg(test)]
+pub(crate) fn test_directory(name: &str) -> tempfile::TempDir {
+    tempfile::Builder::new()
+        .prefix(&format!("netherite-{}-", name))
+        .tempdir()
+        .unwrap()
+}
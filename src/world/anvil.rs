@@ -0,0 +1,106 @@
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use protocol::fields::key::Key;
+
+use crate::region::level::LevelData;
+use crate::region::manager::RegionManager;
+use crate::world::player::PlayerPosition;
+
+/// The maximum number of region files each dimension keeps open
+const MAX_OPEN_REGIONS: usize = 32;
+
+/// The number of I/O threads of each dimension
+const REGION_THREADS: usize = 2;
+
+/// Above the top layer of the bedrock roof of the nether
+const NETHER_ROOF_Y: f64 = 128.0;
+
+/// On the obsidian platform vanilla creates in the end
+const END_SPAWN: PlayerPosition = PlayerPosition { x: 100.5, y: 50.0, z: 0.5 };
+
+/// A vanilla world folder: its `level.dat`, and the region files of each of
+/// its dimensions
+pub struct AnvilWorld {
+    directory: PathBuf,
+    level: LevelData,
+}
+
+impl AnvilWorld {
+    /// Opens the world folder, reading its `level.dat`
+    pub fn load(directory: &Path) -> Result<Self> {
+        let level = LevelData::read(directory)?;
+        Ok(Self { directory: directory.to_path_buf(), level })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn level(&self) -> &LevelData {
+        &self.level
+    }
+
+    /// Where players appear in the dimension. The level data only stores
+    /// the spawn of the overworld, which other dimensions have no reason to
+    /// be safe at. Players appear on the obsidian platform of the end, like
+    /// vanilla, and on the bedrock roof of the nether, which is solid in
+    /// every vanilla nether, above the overworld spawn.
+    pub fn spawn(&self, dimension: &Key) -> PlayerPosition {
+        let x = self.level.spawn_x as f64 + 0.5;
+        let z = self.level.spawn_z as f64 + 0.5;
+        match (dimension.namespace(), dimension.value()) {
+            ("minecraft", "the_nether") => PlayerPosition::new((x / 8.0).floor() + 0.5, NETHER_ROOF_Y, (z / 8.0).floor() + 0.5),
+            ("minecraft", "the_end") => END_SPAWN,
+            _ => PlayerPosition::new(x, self.level.spawn_y as f64, z),
+        }
+    }
+
+    /// The folder storing the dimension: the world folder for the overworld,
+    /// `DIM-1` and `DIM1` for the nether and the end, and
+    /// `dimensions/<namespace>/<path>` for the others.
+    pub fn dimension_directory(&self, dimension: &Key) -> PathBuf {
+        match (dimension.namespace(), dimension.value()) {
+            ("minecraft", "overworld") => self.directory.clone(),
+            ("minecraft", "the_nether") => self.directory.join("DIM-1"),
+            ("minecraft", "the_end") => self.directory.join("DIM1"),
+            (namespace, path) => self.directory.join("dimensions").join(namespace).join(path),
+        }
+    }
+
+    /// Whether the dimension has any region files
+    pub fn has_dimension(&self, dimension: &Key) -> bool {
+        self.dimension_directory(dimension).join("region").is_dir()
+    }
+
+    /// Opens the region files of the dimension. Read-only regions never
+    /// create nor change any file of the folder.
+    pub fn regions(&self, dimension: &Key, read_only: bool) -> Result<RegionManager> {
+        let directory = self.dimension_directory(dimension).join("region");
+        if read_only {
+            RegionManager::read_only(&directory, MAX_OPEN_REGIONS, REGION_THREADS)
+        } else {
+            RegionManager::new(&directory, MAX_OPEN_REGIONS, REGION_THREADS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use protocol::fields::key::Key;
+
+    use crate::world::anvil::AnvilWorld;
+    use crate::world::player::PlayerPosition;
+
+    #[test]
+    fn test_dimension_spawns() {
+        let anvil = AnvilWorld::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")).unwrap();
+        assert_eq!(anvil.spawn(&Key::minecraft("overworld")), PlayerPosition::new(136.5, 64.0, 136.5));
+        assert_eq!(anvil.spawn(&Key::minecraft("the_nether")), PlayerPosition::new(17.5, 128.0, 17.5));
+        assert_eq!(anvil.spawn(&Key::minecraft("the_end")), PlayerPosition::new(100.5, 50.0, 0.5));
+        assert!(anvil.has_dimension(&Key::minecraft("overworld")));
+        assert!(!anvil.has_dimension(&Key::minecraft("the_nether")));
+    }
+}
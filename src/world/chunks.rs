@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+
+use bevy::prelude::Resource;
+
+use crate::block::BlockState;
+use crate::dimension::dimension::DimensionType;
+use crate::region::column::ChunkColumn;
+use crate::region::palette::PaletteKind;
+use crate::region::section::ChunkPos;
+use crate::world::light;
+
+/// The columns loaded in a world.
+///
+/// Columns can be shared with the instances of the world, in which case
+/// they are copied the first time one of the worlds changes them.
+///
+/// Columns are lit when they are added, and their light is kept up to date
+/// as blocks change, see [light].
+#[derive(Resource)]
+pub struct WorldChunks {
+    min_y: i32,
+    height: i32,
+    has_skylight: bool,
+    // The kind of the biome containers of every column
+    biomes: PaletteKind,
+    columns: HashMap<ChunkPos, Arc<ChunkColumn>>,
+    // The columns whose light changed since it was last sent
+    light_changed: HashSet<ChunkPos>,
+}
+
+impl WorldChunks {
+    /// Creates an empty world sized like the dimension type, whose biome
+    /// containers are of the given kind
+    pub fn new(dimension_type: &DimensionType, biomes: PaletteKind) -> Self {
+        Self {
+            min_y: dimension_type.min_y,
+            height: dimension_type.height,
+            has_skylight: dimension_type.has_skylight,
+            biomes,
+            columns: HashMap::new(),
+            light_changed: HashSet::new(),
+        }
+    }
+
+    pub fn min_y(&self) -> i32 {
+        self.min_y
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn has_skylight(&self) -> bool {
+        self.has_skylight
+    }
+
+    pub fn get(&self, pos: &ChunkPos) -> Option<&ChunkColumn> {
+        self.columns.get(pos).map(Arc::as_ref)
+    }
+
+    /// Returns the column to change, copying it first if it is shared with
+    /// another world
+    pub fn get_mut(&mut self, pos: &ChunkPos) -> Option<&mut ChunkColumn> {
+        self.columns.get_mut(pos).map(Arc::make_mut)
+    }
+
+    /// Returns the column without copying it, e.g. to save it in the
+    /// background while the world keeps changing
+    pub fn get_shared(&self, pos: &ChunkPos) -> Option<Arc<ChunkColumn>> {
+        self.columns.get(pos).cloned()
+    }
+
+    /// Creates chunks holding the same columns as this one, which are only
+    /// copied once either side changes them
+    pub fn share(&self) -> WorldChunks {
+        Self {
+            min_y: self.min_y,
+            height: self.height,
+            has_skylight: self.has_skylight,
+            biomes: self.biomes,
+            columns: self.columns.clone(),
+            light_changed: HashSet::new(),
+        }
+    }
+
+    /// Returns the column at the given position, creating an empty one if
+    /// it is not loaded
+    pub fn get_or_create(&mut self, pos: ChunkPos) -> &ChunkColumn {
+        if !self.columns.contains_key(&pos) {
+            let column = self.empty_column(pos);
+            self.columns.insert(pos, Arc::new(column));
+            self.relight(pos);
+        }
+        &self.columns[&pos]
+    }
+
+    /// Creates an empty column sized for this world, without storing it.
+    /// Empty columns are fully lit by the sky, if the dimension has one, and
+    /// are not saved until they change.
+    pub fn empty_column(&self, pos: ChunkPos) -> ChunkColumn {
+        let mut column = ChunkColumn::new(pos.x(), pos.z(), self.min_y, self.height, 0);
+        column.set_biome_kind(self.biomes);
+        if self.has_skylight {
+            column.fill_sky_light();
+        }
+        column
+    }
+
+    /// Adds the column, lighting it and the loaded columns around it
+    pub fn insert(&mut self, mut column: ChunkColumn) {
+        column.set_biome_kind(self.biomes);
+        let pos = ChunkPos::new(column.x(), column.z());
+        self.columns.insert(pos, Arc::new(column));
+        self.relight(pos);
+    }
+
+    fn relight(&mut self, pos: ChunkPos) {
+        let mut changed = mem::take(&mut self.light_changed);
+        light::light_column(self, pos, &mut changed);
+        // The column itself is sent along with its light
+        changed.remove(&pos);
+        self.light_changed = changed;
+    }
+
+    /// Returns the block state at the given world coordinates, or `None` if
+    /// its column is not loaded or y is outside of the world
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Option<BlockState> {
+        let column = self.get(&ChunkPos::new(x >> 4, z >> 4))?;
+        if y < column.min_y() || y >= column.max_y() {
+            return None;
+        }
+        Some(column.get_block(x & 15, y, z & 15))
+    }
+
+    /// Sets the block state at the given world coordinates and updates the
+    /// light around it, returning the previous state, or `None` if its
+    /// column is not loaded or y is outside of the world
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, state: BlockState) -> Option<BlockState> {
+        let previous = self.get_mut(&ChunkPos::new(x >> 4, z >> 4))?.set_block(x & 15, y, z & 15, state)?;
+        if previous != state {
+            let mut changed = mem::take(&mut self.light_changed);
+            light::update_block(self, (x, y, z), previous, &mut changed);
+            self.light_changed = changed;
+        }
+        Some(previous)
+    }
+
+    /// The columns whose light changed since the last call, e.g. because of
+    /// a block change or a column loaded next to them
+    pub fn take_light_changed(&mut self) -> HashSet<ChunkPos> {
+        mem::take(&mut self.light_changed)
+    }
+
+    pub fn remove(&mut self, pos: &ChunkPos) -> Option<ChunkColumn> {
+        self.light_changed.remove(pos);
+        self.columns.remove(pos).map(Arc::unwrap_or_clone)
+    }
+
+    /// Drops the column, without copying it if it is shared with another
+    /// world. Returns `false` if it was not loaded.
+    pub fn unload(&mut self, pos: &ChunkPos) -> bool {
+        self.light_changed.remove(pos);
+        self.columns.remove(pos).is_some()
+    }
+
+    pub fn biome_kind(&self) -> PaletteKind {
+        self.biomes
+    }
+
+    /// Resizes the biome containers of every column, when the biome
+    /// registry grows
+    pub fn set_biome_kind(&mut self, biomes: PaletteKind) {
+        if self.biomes == biomes {
+            return;
+        }
+        self.biomes = biomes;
+        for column in self.columns.values_mut() {
+            Arc::make_mut(column).set_biome_kind(biomes);
+        }
+    }
+
+    pub fn columns(&self) -> impl Iterator<Item=&ChunkColumn> {
+        self.columns.values().map(Arc::as_ref)
+    }
+}
+
+/// The chunks within `radius` of `center`, starting at the center and going
+/// outwards ring by ring
+pub fn spiral(center: ChunkPos, radius: i32) -> Vec<ChunkPos> {
+    let mut chunks = Vec::with_capacity(((radius * 2 + 1) * (radius * 2 + 1)) as usize);
+    chunks.push(center);
+    for ring in 1..=radius {
+        let (mut x, mut z) = (center.x() - ring, center.z() - ring);
+        // Walk each side of the ring, stopping before its last corner
+        for (dx, dz) in [(1, 0), (0, 1), (-1, 0), (0, -1)] {
+            for _ in 0..ring * 2 {
+                chunks.push(ChunkPos::new(x, z));
+                x += dx;
+                z += dz;
+            }
+        }
+    }
+    chunks
+}
+
+/// Whether `pos` is within `radius` chunks of `center`, on both axes
+pub fn is_within(center: ChunkPos, pos: ChunkPos, radius: i32) -> bool {
+    (pos.x() - center.x()).abs() <= radius && (pos.z() - center.z()).abs() <= radius
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::region::section::ChunkPos;
+    use crate::world::chunks::{is_within, spiral};
+
+    fn distance(center: ChunkPos, pos: ChunkPos) -> i32 {
+        (pos.x() - center.x()).abs().max((pos.z() - center.z()).abs())
+    }
+
+    #[test]
+    fn test_spiral() {
+        let center = ChunkPos::new(-3, 7);
+        assert_eq!(spiral(center, 0), vec![center]);
+        for radius in 1..=4 {
+            let chunks = spiral(center, radius);
+            assert_eq!(chunks.len(), ((radius * 2 + 1) * (radius * 2 + 1)) as usize);
+            assert_eq!(chunks.iter().collect::<HashSet<_>>().len(), chunks.len(), "a chunk is repeated");
+            assert!(chunks.iter().all(|pos| is_within(center, *pos, radius)));
+            // Nearest rings first
+            assert!(chunks.windows(2).all(|pair| distance(center, pair[0]) <= distance(center, pair[1])));
+        }
+        assert_eq!(&spiral(ChunkPos::new(0, 0), 1)[..3], &[ChunkPos::new(0, 0), ChunkPos::new(-1, -1), ChunkPos::new(0, -1)]);
+    }
+
+    #[test]
+    fn test_is_within() {
+        let center = ChunkPos::new(0, 0);
+        assert!(is_within(center, ChunkPos::new(2, -2), 2));
+        assert!(!is_within(center, ChunkPos::new(3, 0), 2));
+        assert!(!is_within(center, ChunkPos::new(0, -3), 2));
+    }
+}
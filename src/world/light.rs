@@ -0,0 +1,416 @@
+//! Sky and block light propagation.
+//!
+//! Light spreads to the six neighbors of a block, losing the opacity of
+//! the block it enters, and at least one level: `level - max(1, opacity)`.
+//! Sky light also comes straight down from above the world without losing
+//! any level, until it reaches a block that dims it. Light crosses the
+//! borders of loaded columns, and columns that are loaded later pull it in
+//! from their neighbors.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::block::BlockState;
+use crate::block::light::MAX_LIGHT;
+use crate::region::section::ChunkPos;
+use crate::world::chunks::WorldChunks;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum LightKind {
+    Sky,
+    Block,
+}
+
+const DOWN: (i32, i32, i32) = (0, -1, 0);
+
+const DIRECTIONS: [(i32, i32, i32); 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), DOWN, (0, 0, 1), (0, 0, -1)];
+
+/// Computes the light of the column at `pos` from its blocks and the light
+/// of its loaded neighbors, spreading its own light into them. The neighbors
+/// whose light changed are added to `changed`.
+pub fn light_column(chunks: &mut WorldChunks, pos: ChunkPos, changed: &mut HashSet<ChunkPos>) {
+    let has_skylight = chunks.has_skylight();
+    let Some(column) = chunks.get_mut(&pos) else {
+        return;
+    };
+    column.reset_light(has_skylight);
+
+    let mut block = Propagation::new(chunks, LightKind::Block, changed);
+    block.add_emitters(pos);
+    block.pull_from_neighbors(pos);
+    block.propagate();
+
+    if has_skylight {
+        let mut sky = Propagation::new(chunks, LightKind::Sky, changed);
+        sky.add_sky(pos);
+        sky.pull_from_neighbors(pos);
+        sky.propagate();
+    }
+}
+
+/// Updates the light around a block that changed from `previous`. The
+/// columns whose light changed are added to `changed`.
+pub fn update_block(
+    chunks: &mut WorldChunks,
+    (x, y, z): (i32, i32, i32),
+    previous: BlockState,
+    changed: &mut HashSet<ChunkPos>,
+) {
+    let Some(state) = chunks.get_block(x, y, z) else {
+        return;
+    };
+    let same_light = previous.light_opacity() == state.light_opacity()
+        && previous.light_emission() == state.light_emission();
+    if same_light {
+        return;
+    }
+    let mut kinds = vec![LightKind::Block];
+    if chunks.has_skylight() {
+        kinds.push(LightKind::Sky);
+    }
+    for kind in kinds {
+        let mut propagation = Propagation::new(chunks, kind, changed);
+        propagation.remove(x, y, z);
+        propagation.propagate();
+    }
+}
+
+/// A breadth-first propagation of one kind of light. Levels that drop are
+/// removed first, then the remaining light spreads back into the darkened
+/// blocks.
+struct Propagation<'a> {
+    chunks: &'a mut WorldChunks,
+    kind: LightKind,
+    changed: &'a mut HashSet<ChunkPos>,
+    // Blocks whose light was removed, with their previous level
+    decrease: VecDeque<(i32, i32, i32, u8)>,
+    // Blocks whose light spreads to their neighbors, with their level
+    increase: VecDeque<(i32, i32, i32, u8)>,
+}
+
+impl<'a> Propagation<'a> {
+    fn new(chunks: &'a mut WorldChunks, kind: LightKind, changed: &'a mut HashSet<ChunkPos>) -> Self {
+        Self {
+            chunks,
+            kind,
+            changed,
+            decrease: VecDeque::new(),
+            increase: VecDeque::new(),
+        }
+    }
+
+    /// The light at the block, or `None` if it is not loaded
+    fn light(&self, x: i32, y: i32, z: i32) -> Option<u8> {
+        let column = self.chunks.get(&ChunkPos::new(x >> 4, z >> 4))?;
+        if y < column.min_y() || y >= column.max_y() {
+            return None;
+        }
+        Some(match self.kind {
+            LightKind::Sky => column.get_sky_light(x & 15, y, z & 15),
+            LightKind::Block => column.get_block_light(x & 15, y, z & 15),
+        })
+    }
+
+    fn set_light(&mut self, x: i32, y: i32, z: i32, level: u8) {
+        // Columns may be shared with other worlds, and are only copied when
+        // their light really changes
+        if self.light(x, y, z).map_or(true, |current| current == level) {
+            return;
+        }
+        let pos = ChunkPos::new(x >> 4, z >> 4);
+        let Some(column) = self.chunks.get_mut(&pos) else {
+            return;
+        };
+        match self.kind {
+            LightKind::Sky => column.set_sky_light_at(x & 15, y, z & 15, level),
+            LightKind::Block => column.set_block_light_at(x & 15, y, z & 15, level),
+        }
+        self.changed.insert(pos);
+    }
+
+    fn state(&self, x: i32, y: i32, z: i32) -> BlockState {
+        self.chunks.get_block(x, y, z).unwrap_or(BlockState::AIR)
+    }
+
+    /// The level light at `level` has after moving in `direction` into a
+    /// block of the given opacity
+    fn spread(&self, level: u8, direction: (i32, i32, i32), opacity: u8) -> u8 {
+        if self.kind == LightKind::Sky && direction == DOWN && level == MAX_LIGHT && opacity == 0 {
+            return MAX_LIGHT;
+        }
+        level.saturating_sub(opacity.max(1))
+    }
+
+    /// The light a block gets without any neighbor: its emission for block
+    /// light, and the sky above the world for the highest blocks
+    fn source(&self, x: i32, y: i32, z: i32) -> u8 {
+        let state = self.state(x, y, z);
+        match self.kind {
+            LightKind::Block => state.light_emission(),
+            LightKind::Sky if y == self.chunks.min_y() + self.chunks.height() - 1 => {
+                self.spread(MAX_LIGHT, DOWN, state.light_opacity())
+            }
+            LightKind::Sky => 0,
+        }
+    }
+
+    /// Darkens the block and relights it from its sources and neighbors
+    fn remove(&mut self, x: i32, y: i32, z: i32) {
+        let Some(level) = self.light(x, y, z) else {
+            return;
+        };
+        self.set_light(x, y, z, 0);
+        self.decrease.push_back((x, y, z, level));
+        self.relight_source(x, y, z);
+    }
+
+    fn relight_source(&mut self, x: i32, y: i32, z: i32) {
+        let source = self.source(x, y, z);
+        if source > 0 {
+            self.set_light(x, y, z, source);
+            self.increase.push_back((x, y, z, source));
+        }
+    }
+
+    /// Adds the blocks emitting light in the column
+    fn add_emitters(&mut self, pos: ChunkPos) {
+        let Some(column) = self.chunks.get(&pos) else {
+            return;
+        };
+        let mut emitters = Vec::new();
+        for section in column.sections() {
+            if section.blocks().count(|id| BlockState::from_id(id).map_or(false, |state| state.light_emission() > 0)) == 0 {
+                continue;
+            }
+            for y in 0..16 {
+                for z in 0..16 {
+                    for x in 0..16 {
+                        let emission = section.get_block(x, y, z).light_emission();
+                        if emission > 0 {
+                            emitters.push((pos.x() * 16 + x, section.y() * 16 + y, pos.z() * 16 + z, emission));
+                        }
+                    }
+                }
+            }
+        }
+        for (x, y, z, emission) in emitters {
+            self.set_light(x, y, z, emission);
+            self.increase.push_back((x, y, z, emission));
+        }
+    }
+
+    /// Lights the blocks of the column the sky reaches directly, and adds
+    /// those next to blocks it does not reach
+    fn add_sky(&mut self, pos: ChunkPos) {
+        let (origin_x, origin_z) = (pos.x() * 16, pos.z() * 16);
+        let mut tops = [[None; 18]; 18];
+        for (dz, row) in tops.iter_mut().enumerate() {
+            for (dx, top) in row.iter_mut().enumerate() {
+                *top = self.sky_top(origin_x + dx as i32 - 1, origin_z + dz as i32 - 1);
+            }
+        }
+        for z in 0..16 {
+            for x in 0..16 {
+                let Some(top) = tops[z + 1][x + 1] else {
+                    continue;
+                };
+                let (world_x, world_z) = (origin_x + x as i32, origin_z + z as i32);
+                let max_y = self.chunks.min_y() + self.chunks.height();
+                for y in top..max_y {
+                    self.set_light(world_x, y, world_z, MAX_LIGHT);
+                }
+                // Blocks next to lower tops light the darker blocks beside them
+                let highest_neighbor = [tops[z][x + 1], tops[z + 2][x + 1], tops[z + 1][x], tops[z + 1][x + 2]]
+                    .into_iter()
+                    .map(|neighbor| neighbor.unwrap_or(top))
+                    .max()
+                    .unwrap_or(top);
+                for y in top..highest_neighbor.max(top + 1).min(max_y) {
+                    self.increase.push_back((world_x, y, world_z, MAX_LIGHT));
+                }
+            }
+        }
+    }
+
+    /// The lowest y the sky reaches directly above the given block column,
+    /// or `None` if the column is not loaded
+    fn sky_top(&self, x: i32, z: i32) -> Option<i32> {
+        let column = self.chunks.get(&ChunkPos::new(x >> 4, z >> 4))?;
+        let mut top = column.max_y();
+        while top > column.min_y() && column.get_block(x & 15, top - 1, z & 15).light_opacity() == 0 {
+            top -= 1;
+        }
+        Some(top)
+    }
+
+    /// Spreads the light of the neighboring columns into the column
+    fn pull_from_neighbors(&mut self, pos: ChunkPos) {
+        let (min_x, min_z) = (pos.x() * 16, pos.z() * 16);
+        let mut borders = Vec::with_capacity(64);
+        for offset in 0..16 {
+            borders.push((min_x - 1, min_z + offset));
+            borders.push((min_x + 16, min_z + offset));
+            borders.push((min_x + offset, min_z - 1));
+            borders.push((min_x + offset, min_z + 16));
+        }
+        let (min_y, max_y) = (self.chunks.min_y(), self.chunks.min_y() + self.chunks.height());
+        for (x, z) in borders {
+            if self.chunks.get(&ChunkPos::new(x >> 4, z >> 4)).is_none() {
+                continue;
+            }
+            for y in min_y..max_y {
+                match self.light(x, y, z) {
+                    Some(level) if level > 1 => self.increase.push_back((x, y, z, level)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn propagate(&mut self) {
+        while let Some((x, y, z, level)) = self.decrease.pop_front() {
+            for direction in DIRECTIONS {
+                let (nx, ny, nz) = (x + direction.0, y + direction.1, z + direction.2);
+                let Some(current) = self.light(nx, ny, nz) else {
+                    continue;
+                };
+                if current == 0 {
+                    continue;
+                }
+                let opacity = self.state(nx, ny, nz).light_opacity();
+                if current < level || current <= self.spread(level, direction, opacity) {
+                    // The neighbor may have been lit through this block
+                    self.set_light(nx, ny, nz, 0);
+                    self.decrease.push_back((nx, ny, nz, current));
+                    self.relight_source(nx, ny, nz);
+                } else {
+                    self.increase.push_back((nx, ny, nz, current));
+                }
+            }
+        }
+        while let Some((x, y, z, level)) = self.increase.pop_front() {
+            if self.light(x, y, z) != Some(level) {
+                // Overwritten by a brighter level since it was queued
+                continue;
+            }
+            for direction in DIRECTIONS {
+                let (nx, ny, nz) = (x + direction.0, y + direction.1, z + direction.2);
+                let Some(current) = self.light(nx, ny, nz) else {
+                    continue;
+                };
+                let opacity = self.state(nx, ny, nz).light_opacity();
+                let spread = self.spread(level, direction, opacity);
+                if spread > current {
+                    self.set_light(nx, ny, nz, spread);
+                    self.increase.push_back((nx, ny, nz, spread));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use protocol::fields::key::Key;
+
+    use crate::block::{Block, BlockState};
+    use crate::dimension::registry::RegistryCodec;
+    use crate::region::section::ChunkPos;
+    use crate::world::chunks::WorldChunks;
+
+    /// Two empty columns side by side, at chunk x 0 and 1
+    fn chunks() -> WorldChunks {
+        let codec = RegistryCodec::vanilla();
+        let overworld = codec.dimension_type.get(&Key::minecraft("overworld")).unwrap();
+        let mut chunks = WorldChunks::new(overworld, codec.biome.palette_kind());
+        chunks.get_or_create(ChunkPos::new(0, 0));
+        chunks.get_or_create(ChunkPos::new(1, 0));
+        chunks.take_light_changed();
+        chunks
+    }
+
+    fn block_light(chunks: &WorldChunks, x: i32, y: i32, z: i32) -> u8 {
+        chunks.get(&ChunkPos::new(x >> 4, z >> 4)).unwrap().get_block_light(x & 15, y, z & 15)
+    }
+
+    fn sky_light(chunks: &WorldChunks, x: i32, y: i32, z: i32) -> u8 {
+        chunks.get(&ChunkPos::new(x >> 4, z >> 4)).unwrap().get_sky_light(x & 15, y, z & 15)
+    }
+
+    #[test]
+    fn test_torch_at_border() {
+        let mut chunks = chunks();
+        chunks.set_block(15, 64, 5, Block::Torch.default_state());
+        assert_eq!(block_light(&chunks, 15, 64, 5), 14);
+        assert_eq!(block_light(&chunks, 14, 64, 5), 13);
+        assert_eq!(block_light(&chunks, 16, 64, 5), 13);
+        assert_eq!(block_light(&chunks, 18, 66, 5), 9);
+        assert_eq!(block_light(&chunks, 29, 64, 5), 0);
+        assert_eq!(chunks.take_light_changed(), [ChunkPos::new(0, 0), ChunkPos::new(1, 0)].into());
+
+        chunks.set_block(15, 64, 5, BlockState::AIR);
+        for (x, y, z) in [(15, 64, 5), (14, 64, 5), (16, 64, 5), (18, 66, 5)] {
+            assert_eq!(block_light(&chunks, x, y, z), 0);
+        }
+        assert_eq!(chunks.take_light_changed(), [ChunkPos::new(0, 0), ChunkPos::new(1, 0)].into());
+    }
+
+    #[test]
+    fn test_torch_behind_wall() {
+        let mut chunks = chunks();
+        for y in 62..=66 {
+            for z in 0..16 {
+                chunks.set_block(16, y, z, Block::Stone.default_state());
+            }
+        }
+        chunks.set_block(15, 64, 5, Block::Torch.default_state());
+        assert_eq!(block_light(&chunks, 16, 64, 5), 0);
+        // Over the top of the wall, 8 blocks away
+        assert_eq!(block_light(&chunks, 17, 64, 5), 6);
+    }
+
+    #[test]
+    fn test_overhang() {
+        let mut chunks = chunks();
+        for x in 14..=18 {
+            for z in 2..=6 {
+                chunks.set_block(x, 80, z, Block::Stone.default_state());
+            }
+        }
+        assert_eq!(sky_light(&chunks, 16, 81, 4), 15);
+        assert_eq!(sky_light(&chunks, 16, 80, 4), 0);
+        // The sky reaches under the overhang from its sides
+        assert_eq!(sky_light(&chunks, 16, 79, 4), 12);
+        assert_eq!(sky_light(&chunks, 14, 79, 2), 14);
+        assert_eq!(sky_light(&chunks, 16, -64, 4), 12);
+        assert_eq!(sky_light(&chunks, 13, 79, 4), 15);
+
+        chunks.set_block(16, 80, 4, BlockState::AIR);
+        assert_eq!(sky_light(&chunks, 16, 79, 4), 15);
+        assert_eq!(sky_light(&chunks, 16, -64, 4), 15);
+        assert_eq!(sky_light(&chunks, 15, 79, 4), 14);
+
+        chunks.set_block(16, 80, 4, Block::Stone.default_state());
+        assert_eq!(sky_light(&chunks, 16, 79, 4), 12);
+        assert_eq!(sky_light(&chunks, 16, -64, 4), 12);
+        assert_eq!(sky_light(&chunks, 15, 79, 4), 13);
+    }
+
+    #[test]
+    fn test_light_of_instances() {
+        let mut chunks = chunks();
+        let mut instance = chunks.share();
+        // The torch lights its own column only
+        instance.set_block(2, 64, 5, Block::Torch.default_state());
+        assert_eq!(block_light(&instance, 2, 64, 5), 14);
+        assert_eq!(block_light(&instance, 15, 64, 5), 1);
+        assert_eq!(instance.take_light_changed(), [ChunkPos::new(0, 0)].into());
+
+        let (pos, neighbor) = (ChunkPos::new(0, 0), ChunkPos::new(1, 0));
+        assert!(!Arc::ptr_eq(&chunks.get_shared(&pos).unwrap(), &instance.get_shared(&pos).unwrap()));
+        assert!(Arc::ptr_eq(&chunks.get_shared(&neighbor).unwrap(), &instance.get_shared(&neighbor).unwrap()));
+        assert_eq!(block_light(&chunks, 2, 64, 5), 0);
+        assert!(chunks.take_light_changed().is_empty());
+    }
+}
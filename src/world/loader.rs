@@ -0,0 +1,551 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Result;
+use std::sync::Arc;
+
+use bevy::log::{error, warn};
+use bevy::prelude::{ResMut, Resource, World};
+use flume::{Receiver, Sender};
+use futures::future::join_all;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::generation::WorldGenerator;
+use crate::region::column::{ChunkColumn, unknown_block_states};
+use crate::region::data::LevelChunk;
+use crate::region::level::LevelData;
+use crate::region::manager::RegionManager;
+use crate::region::section::ChunkPos;
+use crate::world::chunks::WorldChunks;
+use crate::world::world::WorldInfo;
+
+/// A column read from its region file, and the names of its block states
+/// that are unknown to the registry
+type Loaded = (ChunkPos, Result<Option<(ChunkColumn, Vec<String>)>>);
+
+/// Loads and saves the columns of a world from its region files. Loads and
+/// saves run on the tokio runtime, and their results are picked up by
+/// [receive_chunks] on the next tick.
+///
+/// Read-only worlds never write to their folder, so they are back to their
+/// saved state the next time they are loaded.
+#[derive(Resource)]
+pub struct ChunkLoader {
+    regions: Arc<RegionManager>,
+    runtime: Handle,
+    // The biome id of each biome name found in region files
+    biome_ids: Arc<HashMap<String, i32>>,
+    biome_names: Arc<HashMap<i32, String>>,
+    read_only: bool,
+    min_y: i32,
+    height: i32,
+    pending: HashSet<ChunkPos>,
+    // Chunks that could not be read. They are never saved over.
+    failed: HashSet<ChunkPos>,
+    // Chunks with block states unknown to the registry, which were loaded
+    // as air. They are never saved over either, so the states are kept.
+    unknown_states: HashSet<ChunkPos>,
+    // The changes count of the last copy of each column written. Columns
+    // that are missing were never changed since they were loaded.
+    saved: HashMap<ChunkPos, u64>,
+    // The changes count of the copies saved to unload them
+    unloading: HashMap<ChunkPos, u64>,
+    // Held by the running save, so saves of the world write one at a time
+    saving: Arc<tokio::sync::Mutex<()>>,
+    sender: Sender<Loaded>,
+    receiver: Receiver<Loaded>,
+    saved_sender: Sender<(ChunkPos, u64)>,
+    saved_receiver: Receiver<(ChunkPos, u64)>,
+}
+
+impl ChunkLoader {
+    pub fn new(
+        regions: RegionManager,
+        runtime: Handle,
+        biome_ids: HashMap<String, i32>,
+        min_y: i32,
+        height: i32,
+        read_only: bool,
+    ) -> Self {
+        let (sender, receiver) = flume::unbounded();
+        let (saved_sender, saved_receiver) = flume::unbounded();
+        let biome_names = biome_ids.iter().map(|(name, id)| (*id, name.clone())).collect();
+        Self {
+            regions: Arc::new(regions),
+            runtime,
+            biome_ids: Arc::new(biome_ids),
+            biome_names: Arc::new(biome_names),
+            read_only,
+            min_y,
+            height,
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+            unknown_states: HashSet::new(),
+            saved: HashMap::new(),
+            unloading: HashMap::new(),
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+            sender,
+            receiver,
+            saved_sender,
+            saved_receiver,
+        }
+    }
+
+    pub fn regions(&self) -> &Arc<RegionManager> {
+        &self.regions
+    }
+
+    /// Starts loading the column, unless it is already loading
+    pub fn request(&mut self, pos: ChunkPos) {
+        if !self.pending.insert(pos) {
+            return;
+        }
+        let regions = Arc::clone(&self.regions);
+        let biome_ids = Arc::clone(&self.biome_ids);
+        let (min_y, height) = (self.min_y, self.height);
+        let sender = self.sender.clone();
+        self.runtime.spawn(async move {
+            let column = match regions.load_chunk(pos).await {
+                Ok(Some(blob)) => LevelChunk::from_blob(&blob)
+                    .and_then(|chunk| {
+                        let column = ChunkColumn::from_level_chunk(&chunk, min_y, height, |name| biome_ids.get(name).copied())?;
+                        Ok(Some((column, unknown_block_states(&chunk))))
+                    }),
+                Ok(None) => Ok(None),
+                Err(why) => Err(why),
+            };
+            let _ = sender.send((pos, column));
+        });
+    }
+
+    pub fn is_pending(&self, pos: &ChunkPos) -> bool {
+        self.pending.contains(pos)
+    }
+
+    pub fn is_failed(&self, pos: &ChunkPos) -> bool {
+        self.failed.contains(pos)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// A read-only loader for an instance of the world, reading the chunks
+    /// it has not loaded from the same region files
+    pub fn instance(&self) -> ChunkLoader {
+        let (sender, receiver) = flume::unbounded();
+        let (saved_sender, saved_receiver) = flume::unbounded();
+        Self {
+            regions: Arc::clone(&self.regions),
+            runtime: self.runtime.clone(),
+            biome_ids: Arc::clone(&self.biome_ids),
+            biome_names: Arc::clone(&self.biome_names),
+            read_only: true,
+            min_y: self.min_y,
+            height: self.height,
+            pending: HashSet::new(),
+            failed: self.failed.clone(),
+            unknown_states: self.unknown_states.clone(),
+            saved: HashMap::new(),
+            unloading: HashMap::new(),
+            saving: Arc::new(tokio::sync::Mutex::new(())),
+            sender,
+            receiver,
+            saved_sender,
+            saved_receiver,
+        }
+    }
+
+    /// Returns the columns that can be dropped from the world without losing
+    /// changes, and forgets them. The others changed since they were last
+    /// written: writable worlds save them, once, and can drop them when the
+    /// save is received, while read-only worlds keep them. Columns that are
+    /// never saved, with block states unknown to the registry, are dropped
+    /// as they are.
+    pub fn unload(&mut self, columns: Vec<Arc<ChunkColumn>>) -> Vec<ChunkPos> {
+        let mut unloaded = Vec::new();
+        let mut unsaved = Vec::new();
+        for column in columns {
+            let pos = ChunkPos::new(column.x(), column.z());
+            let never_saved = self.failed.contains(&pos) || self.unknown_states.contains(&pos);
+            if !self.is_unsaved(&column) || (never_saved && !self.read_only) {
+                self.saved.remove(&pos);
+                self.unloading.remove(&pos);
+                unloaded.push(pos);
+            } else if !self.read_only && self.unloading.get(&pos) != Some(&column.changes()) {
+                self.unloading.insert(pos, column.changes());
+                unsaved.push(column);
+            }
+        }
+        if !unsaved.is_empty() {
+            self.save(unsaved, None);
+        }
+        unloaded
+    }
+
+    /// Whether the column changed since the last copy of it written
+    fn is_unsaved(&self, column: &ChunkColumn) -> bool {
+        let pos = ChunkPos::new(column.x(), column.z());
+        column.changes() != self.saved.get(&pos).copied().unwrap_or(0)
+    }
+
+    /// Writes the columns to their region files, and the level data to the
+    /// world folder, once the previous save is done. Columns are written
+    /// over the chunks already saved, so the data this server does not load
+    /// is kept. Resolves to the number of columns saved.
+    ///
+    /// Each column written is reported to [receive_chunks], which counts it
+    /// as saved from then on. The others are tried again by the next save.
+    fn save(&self, columns: Vec<Arc<ChunkColumn>>, level: Option<LevelData>) -> JoinHandle<usize> {
+        let regions = Arc::clone(&self.regions);
+        let biome_names = Arc::clone(&self.biome_names);
+        let saving = Arc::clone(&self.saving);
+        let saved_sender = self.saved_sender.clone();
+        self.runtime.spawn(async move {
+            let _saving = saving.lock().await;
+            let saves = columns.into_iter().map(|column| {
+                let regions = Arc::clone(&regions);
+                let biome_names = Arc::clone(&biome_names);
+                let saved_sender = saved_sender.clone();
+                async move {
+                    let pos = ChunkPos::new(column.x(), column.z());
+                    let biome_name = |id: i32| biome_names.get(&id).cloned();
+                    let chunk = match regions.load_chunk(pos).await {
+                        Ok(Some(blob)) => LevelChunk::from_blob(&blob).map(|mut chunk| {
+                            column.write_level_chunk(&mut chunk, biome_name);
+                            chunk
+                        }),
+                        Ok(None) => Ok(column.to_level_chunk(biome_name)),
+                        Err(why) => Err(why),
+                    };
+                    let result = match chunk.and_then(|chunk| chunk.to_blob()) {
+                        Ok(blob) => regions.save_chunk(pos, blob).await,
+                        Err(why) => Err(why),
+                    };
+                    match &result {
+                        Ok(()) => {
+                            let _ = saved_sender.send((pos, column.changes()));
+                        }
+                        Err(why) => error!("failed to save chunk [{}, {}]: {}", pos.x(), pos.z(), why),
+                    }
+                    result.is_ok()
+                }
+            });
+            let saved = join_all(saves).await.into_iter().filter(|saved| *saved).count();
+            if let Some(level) = level {
+                let Some(directory) = regions.directory().parent().map(|path| path.to_path_buf()) else {
+                    return saved;
+                };
+                let result = tokio::task::spawn_blocking(move || level.write(&directory)).await;
+                if let Ok(Err(why)) = result {
+                    error!("failed to save the level data: {}", why);
+                }
+            }
+            saved
+        })
+    }
+}
+
+/// Saves the columns of the world that changed since they were last saved,
+/// along with its level data. Returns `None` for worlds without a folder
+/// and read-only worlds. Saves of a world run one after the other, so a save
+/// started while the previous one runs, e.g. on shutdown, waits for it.
+///
+/// The columns are written as they are now, without copying them, while
+/// the world keeps changing.
+///
+/// Columns that failed to load are never saved, so a chunk that could not
+/// be read is not replaced by an empty one. Neither are columns with block
+/// states unknown to the registry, which would be saved as air.
+pub fn save_world(world: &mut World) -> Option<JoinHandle<usize>> {
+    let loader = world.get_resource::<ChunkLoader>()?;
+    if loader.read_only {
+        return None;
+    }
+    let chunks = world.resource::<WorldChunks>();
+    let columns = chunks.columns()
+        .filter(|column| loader.is_unsaved(column))
+        .map(|column| ChunkPos::new(column.x(), column.z()))
+        .filter(|pos| !loader.failed.contains(pos) && !loader.unknown_states.contains(pos))
+        .filter_map(|pos| chunks.get_shared(&pos))
+        .collect();
+    let spawn = world.resource::<WorldInfo>().spawn_block();
+    let level = world.get_resource_mut::<LevelData>().map(|mut level| {
+        level.spawn_x = spawn.x;
+        level.spawn_y = spawn.y as i32;
+        level.spawn_z = spawn.z;
+        level.clone()
+    });
+    Some(world.resource::<ChunkLoader>().save(columns, level))
+}
+
+/// Counts the columns written since the last tick as saved, and adds the
+/// columns loaded since to the world. Chunks that
+/// were never saved are generated, or created empty in worlds without a
+/// [WorldGenerator].
+pub fn receive_chunks(
+    loader: Option<ResMut<ChunkLoader>>,
+    mut generator: Option<ResMut<WorldGenerator>>,
+    mut chunks: ResMut<WorldChunks>,
+) {
+    let Some(mut loader) = loader else {
+        return;
+    };
+    while let Ok((pos, changes)) = loader.saved_receiver.try_recv() {
+        let saved = loader.saved.entry(pos).or_insert(0);
+        *saved = (*saved).max(changes);
+    }
+    while let Ok((pos, column)) = loader.receiver.try_recv() {
+        loader.pending.remove(&pos);
+        match column {
+            Ok(Some((column, unknown_states))) => {
+                if !unknown_states.is_empty() {
+                    warn!(
+                        "chunk [{}, {}] has block states unknown to this version, shown as air. It will not be saved: {}",
+                        pos.x(), pos.z(), unknown_states.join(", ")
+                    );
+                    loader.unknown_states.insert(pos);
+                }
+                chunks.insert(column);
+            }
+            Ok(None) => match generator.as_mut() {
+                Some(generator) => generator.request(chunks.empty_column(pos)),
+                None => {
+                    chunks.get_or_create(pos);
+                }
+            },
+            Err(why) => {
+                error!("failed to load chunk [{}, {}]: {}", pos.x(), pos.z(), why);
+                loader.failed.insert(pos);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bevy::prelude::{Schedule, World};
+    use tokio::runtime::Handle;
+
+    use protocol::fields::key::Key;
+
+    use crate::block::Block;
+    use crate::dimension::dimension::DimensionType;
+    use crate::dimension::registry::RegistryCodec;
+    use crate::region::{fixture, FIXTURE_CHUNK};
+    use crate::region::column::ChunkColumn;
+    use crate::region::data::LevelChunk;
+    use crate::region::manager::RegionManager;
+    use crate::region::region::{ChunkCompression, Region};
+    use crate::region::section::ChunkPos;
+    use crate::world::chunks::WorldChunks;
+    use crate::world::loader::{ChunkLoader, receive_chunks, save_world};
+    use crate::world::player::PlayerPosition;
+    use crate::world::world::WorldInfo;
+
+    fn world(directory: &Path) -> World {
+        let codec = RegistryCodec::vanilla();
+        let dimension_type = DimensionType::new(-64, 384);
+        let regions = RegionManager::new(directory, 4, 1).unwrap();
+        let mut world = World::new();
+        world.insert_resource(WorldChunks::new(&dimension_type, codec.biome.palette_kind()));
+        world.insert_resource(ChunkLoader::new(regions, Handle::current(), codec.biome.ids_by_name(), -64, 384, false));
+        world.insert_resource(WorldInfo::new(
+            Key::minecraft("overworld"),
+            Key::minecraft("overworld"),
+            dimension_type,
+            0,
+            PlayerPosition::new(0.5, 64.0, 0.5),
+        ));
+        world
+    }
+
+    fn receive(world: &mut World) {
+        let mut schedule = Schedule::new();
+        schedule.add_system(receive_chunks);
+        schedule.run(world);
+    }
+
+    async fn load(world: &mut World, pos: ChunkPos) {
+        world.resource_mut::<ChunkLoader>().request(pos);
+        while world.resource::<ChunkLoader>().is_pending(&pos) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            receive(world);
+        }
+    }
+
+    /// Saves the world and waits for the next tick to hear about it
+    async fn save(world: &mut World) -> usize {
+        let saved = save_world(world).unwrap().await.unwrap();
+        receive(world);
+        saved
+    }
+
+    fn is_unsaved(world: &World, pos: ChunkPos) -> bool {
+        let column = world.resource::<WorldChunks>().get(&pos).unwrap();
+        world.resource::<ChunkLoader>().is_unsaved(column)
+    }
+
+    fn read_chunk(directory: &Path, pos: ChunkPos) -> LevelChunk {
+        let region = Region::open(&directory.join("r.0.0.mca"), 0, 0).unwrap();
+        LevelChunk::from_blob(&region.get_chunk_data(pos.x(), pos.z()).unwrap().unwrap()).unwrap()
+    }
+
+    fn read_column(directory: &Path, pos: ChunkPos) -> ChunkColumn {
+        let biomes = RegistryCodec::vanilla().biome.ids_by_name();
+        ChunkColumn::from_level_chunk(&read_chunk(directory, pos), -64, 384, |name| biomes.get(name).copied()).unwrap()
+    }
+
+    fn fixture_pos() -> ChunkPos {
+        ChunkPos::new(FIXTURE_CHUNK.0, FIXTURE_CHUNK.1)
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        fixture(&format!("loader-{}", name))
+    }
+
+    #[tokio::test]
+    async fn test_save_round_trip() {
+        let directory = directory("round-trip");
+        let mut world = world(&directory);
+        let pos = fixture_pos();
+        load(&mut world, pos).await;
+        let dirt = Block::Dirt.default_state();
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(5, 80, 6, dirt);
+        assert_eq!(save(&mut world).await, 1);
+        // Nothing changed since
+        assert_eq!(save(&mut world).await, 0);
+
+        let column = read_column(&directory, pos);
+        assert_eq!(column.get_block(5, 80, 6), dirt);
+        assert_eq!(column.get_block(4, 63, 4).block(), Block::GrassBlock);
+        assert_eq!(column.get_block(3, 64, 5).block(), Block::Chest);
+        // The data the server does not load is kept
+        let chunk = read_chunk(&directory, pos);
+        assert_eq!(chunk.block_entities.len(), 2);
+        assert!(chunk.block_entities[1].data.0.contains_key("Bees"));
+        assert!(chunk.structures.starts.0.contains_key("minecraft:buried_treasure"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_states_are_not_saved() {
+        let directory = directory("unknown-states");
+        // A chunk from a newer version, next to the fixture chunk
+        let pos = ChunkPos::new(FIXTURE_CHUNK.0 + 1, FIXTURE_CHUNK.1);
+        let mut chunk = read_chunk(&directory, fixture_pos());
+        chunk.x_pos = pos.x();
+        let section = chunk.sections.iter_mut().find(|section| section.y == 4).unwrap();
+        section.block_states.as_mut().unwrap().palette[1].name = String::from("minecraft:future_block");
+        let mut region = Region::open(&directory.join("r.0.0.mca"), 0, 0).unwrap();
+        region.write_chunk_data(pos.x(), pos.z(), &chunk.to_blob().unwrap(), ChunkCompression::Zlib).unwrap();
+        drop(region);
+
+        let mut world = world(&directory);
+        load(&mut world, pos).await;
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(5, 80, 6, Block::Dirt.default_state());
+        assert_eq!(save(&mut world).await, 0);
+        let saved = read_chunk(&directory, pos);
+        let section = saved.sections.iter().find(|section| section.y == 4).unwrap();
+        assert_eq!(section.block_states.as_ref().unwrap().palette[1].name, "minecraft:future_block");
+    }
+
+    #[tokio::test]
+    async fn test_saved_after_writing() {
+        let directory = directory("saved");
+        let mut world = world(&directory);
+        let pos = fixture_pos();
+        load(&mut world, pos).await;
+        assert!(!is_unsaved(&world, pos));
+        let stone = Block::Stone.default_state();
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(0, 100, 0, stone);
+
+        // A change made while the save runs is saved by the next one
+        let first = save_world(&mut world).unwrap();
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(1, 100, 0, stone);
+        assert_eq!(first.await.unwrap(), 1);
+        receive(&mut world);
+        assert!(is_unsaved(&world, pos));
+        assert_eq!(read_column(&directory, pos).get_block(0, 100, 0), stone);
+        assert!(read_column(&directory, pos).get_block(1, 100, 0).is_air());
+
+        // Saves started together run one after the other
+        let first = save_world(&mut world).unwrap();
+        let second = save_world(&mut world).unwrap();
+        assert_eq!((first.await.unwrap(), second.await.unwrap()), (1, 1));
+        receive(&mut world);
+        assert!(!is_unsaved(&world, pos));
+        assert_eq!(read_column(&directory, pos).get_block(1, 100, 0), stone);
+    }
+
+    #[tokio::test]
+    async fn test_failed_saves_are_retried() {
+        let directory = directory("retried");
+        let mut world = world(&directory);
+        // In a region without a file, which cannot be created without the directory
+        let pos = ChunkPos::new(40, 8);
+        world.resource_mut::<WorldChunks>().get_or_create(pos);
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(0, 0, 0, Block::Stone.default_state());
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(save(&mut world).await, 0);
+        assert!(is_unsaved(&world, pos));
+
+        fs::create_dir_all(&directory).unwrap();
+        assert_eq!(save(&mut world).await, 1);
+        assert!(!is_unsaved(&world, pos));
+        assert_eq!(save(&mut world).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_save_keeps_columns_shared() {
+        let directory = directory("shared");
+        let mut world = world(&directory);
+        let pos = fixture_pos();
+        load(&mut world, pos).await;
+        world.resource_mut::<WorldChunks>().get_mut(&pos).unwrap().set_block(0, 100, 0, Block::Stone.default_state());
+        // As an instance of the world would
+        let instance = world.resource::<WorldChunks>().share();
+        assert_eq!(save(&mut world).await, 1);
+        let column = world.resource::<WorldChunks>().get_shared(&pos).unwrap();
+        assert!(Arc::ptr_eq(&column, &instance.get_shared(&pos).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_unload_saves_first() {
+        let directory = directory("unload");
+        let mut world = world(&directory);
+        let (changed, untouched) = (fixture_pos(), ChunkPos::new(FIXTURE_CHUNK.0 + 1, FIXTURE_CHUNK.1));
+        load(&mut world, changed).await;
+        load(&mut world, untouched).await;
+        let stone = Block::Stone.default_state();
+        world.resource_mut::<WorldChunks>().get_mut(&changed).unwrap().set_block(0, 100, 0, stone);
+        // Read-only worlds keep their changed columns
+        let mut instance = world.resource::<ChunkLoader>().instance();
+        let column = world.resource::<WorldChunks>().get_shared(&changed).unwrap();
+        assert!(instance.unload(vec![column]).is_empty());
+
+        let unload = |world: &mut World| {
+            let chunks = world.resource::<WorldChunks>();
+            let columns = [changed, untouched].iter().filter_map(|pos| chunks.get_shared(pos)).collect();
+            let unloaded = world.resource_mut::<ChunkLoader>().unload(columns);
+            for pos in &unloaded {
+                world.resource_mut::<WorldChunks>().unload(pos);
+            }
+            unloaded
+        };
+        assert_eq!(unload(&mut world), vec![untouched]);
+        // Kept until its save is received
+        while unload(&mut world).is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            receive(&mut world);
+        }
+        assert!(world.resource::<WorldChunks>().get(&changed).is_none());
+        assert_eq!(read_column(&directory, changed).get_block(0, 100, 0), stone);
+        // Loaded again as saved
+        load(&mut world, changed).await;
+        assert!(!is_unsaved(&world, changed));
+    }
+}
@@ -1,2 +1,8 @@
-mod world;
-pub mod worlds;
\ No newline at end of file
+pub mod world;
+pub mod worlds;
+pub mod chunks;
+pub mod player;
+pub mod streaming;
+pub mod anvil;
+pub mod loader;
+pub mod light;
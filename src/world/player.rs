@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use bevy::prelude::Component;
+use protocol::Clientbound;
+use protocol::codec::encode_packet;
+use protocol::fields::key::Key;
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use crate::client::client::ClientMessage;
+use crate::region::section::ChunkPos;
+
+/// One connection of a player. Players are known by their UUID, but the
+/// duplicate login policy may let them be online from several connections,
+/// which each have an entity.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Session {
+    pub id: Uuid,
+    pub address: SocketAddr,
+}
+
+impl Session {
+    pub fn new(id: Uuid, address: SocketAddr) -> Self {
+        Self { id, address }
+    }
+}
+
+/// The connection of a player entity
+#[derive(Component)]
+pub struct PlayerConnection {
+    id: Uuid,
+    address: SocketAddr,
+    messages: UnboundedSender<ClientMessage>,
+}
+
+impl PlayerConnection {
+    pub fn new(id: Uuid, address: SocketAddr, messages: UnboundedSender<ClientMessage>) -> Self {
+        Self { id, address, messages }
+    }
+
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn address(&self) -> &SocketAddr {
+        &self.address
+    }
+
+    pub fn session(&self) -> Session {
+        Session::new(self.id, self.address)
+    }
+
+    /// Queues the packet on the player's connection. Packets sent after the
+    /// player left are dropped.
+    pub fn send_packet<T: Clientbound>(&self, packet: &T) {
+        match encode_packet(packet) {
+            Ok(data) => {
+                let _ = self.messages.send(ClientMessage::Packet(data));
+            }
+            Err(why) => eprintln!("failed to encode a packet for {}: {}", self.id, why)
+        }
+    }
+}
+/// A Synchronize Player Position the client has not confirmed. Its
+/// movements are ignored until it does, as they were made from where it
+/// was before.
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct PendingTeleport(pub i32);
+
+/// The dimension types of the codec the client joined with. Players are
+/// only moved to worlds of a type their client knows about.
+#[derive(Component, Debug, Default, Clone)]
+pub struct KnownDimensionTypes(pub HashSet<Key>);
+
+#[derive(Component, Debug, Copy, Clone, PartialEq)]
+pub struct PlayerPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl PlayerPosition {
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+
+    /// The chunk the player is in
+    pub fn chunk(&self) -> ChunkPos {
+        ChunkPos::new((self.x.floor() as i32) >> 4, (self.z.floor() as i32) >> 4)
+    }
+}
+
+/// The chunks a player has been sent
+#[derive(Component, Debug, Default)]
+pub struct ChunkView {
+    // The view distance requested in Client Information. 0 until received.
+    client_view_distance: u8,
+    // The chunk last sent in Set Center Chunk
+    center: Option<ChunkPos>,
+    loaded: HashSet<ChunkPos>,
+}
+
+impl ChunkView {
+    pub fn client_view_distance(&self) -> u8 {
+        self.client_view_distance
+    }
+
+    pub fn set_client_view_distance(&mut self, view_distance: u8) {
+        self.client_view_distance = view_distance;
+    }
+
+    /// The view distance used for the player: the server's, unless the
+    /// client asked for less.
+    pub fn view_distance(&self, server_view_distance: u8) -> u8 {
+        match self.client_view_distance {
+            0 => server_view_distance,
+            client => client.min(server_view_distance),
+        }
+    }
+
+    pub fn center(&self) -> Option<ChunkPos> {
+        self.center
+    }
+
+    pub fn set_center(&mut self, center: ChunkPos) {
+        self.center = Some(center);
+    }
+
+    pub fn loaded(&self) -> &HashSet<ChunkPos> {
+        &self.loaded
+    }
+
+    pub fn is_loaded(&self, pos: &ChunkPos) -> bool {
+        self.loaded.contains(pos)
+    }
+
+    pub fn mark_loaded(&mut self, pos: ChunkPos) {
+        self.loaded.insert(pos);
+    }
+
+    /// Forgets the chunks for which `keep` returns false, returning them
+    pub fn unload_where(&mut self, mut keep: impl FnMut(&ChunkPos) -> bool) -> Vec<ChunkPos> {
+        let unloaded: Vec<ChunkPos> = self.loaded.iter().filter(|pos| !keep(pos)).copied().collect();
+        for pos in &unloaded {
+            self.loaded.remove(pos);
+        }
+        unloaded
+    }
+
+    /// Forgets every chunk, e.g. when the player changes world
+    pub fn clear(&mut self) {
+        self.center = None;
+        self.loaded.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::region::section::ChunkPos;
+    use crate::world::player::{ChunkView, PlayerPosition};
+
+    #[test]
+    fn test_view_distance() {
+        let mut view = ChunkView::default();
+        assert_eq!(view.view_distance(10), 10);
+        view.set_client_view_distance(4);
+        assert_eq!(view.view_distance(10), 4);
+        view.set_client_view_distance(32);
+        assert_eq!(view.view_distance(10), 10);
+    }
+
+    #[test]
+    fn test_loaded_chunks() {
+        let mut view = ChunkView::default();
+        view.set_center(ChunkPos::new(0, 0));
+        for x in -2..=2 {
+            view.mark_loaded(ChunkPos::new(x, 0));
+        }
+        assert!(view.is_loaded(&ChunkPos::new(-2, 0)));
+        assert!(!view.is_loaded(&ChunkPos::new(0, 1)));
+
+        let mut unloaded = view.unload_where(|pos| pos.x() >= 0);
+        unloaded.sort_by_key(ChunkPos::x);
+        assert_eq!(unloaded, vec![ChunkPos::new(-2, 0), ChunkPos::new(-1, 0)]);
+        assert_eq!(view.loaded().len(), 3);
+
+        view.clear();
+        assert!(view.loaded().is_empty());
+        assert_eq!(view.center(), None);
+    }
+
+    #[test]
+    fn test_position_chunk() {
+        assert_eq!(PlayerPosition::new(0.5, 64.0, 15.9).chunk(), ChunkPos::new(0, 0));
+        assert_eq!(PlayerPosition::new(-0.5, 64.0, 16.0).chunk(), ChunkPos::new(-1, 1));
+        assert_eq!(PlayerPosition::new(-16.0, 64.0, -16.1).chunk(), ChunkPos::new(-1, -2));
+    }
+}
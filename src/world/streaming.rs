@@ -0,0 +1,121 @@
+use bevy::prelude::{Query, Res, ResMut, Resource};
+use protocol::fields::numeric::VarInt;
+
+use crate::generation::WorldGenerator;
+use crate::packets::play::{SetCenterChunk, UnloadChunk};
+use crate::packets::play::chunk::{ChunkPacket, UpdateLight};
+use crate::region::section::ChunkPos;
+use crate::world::chunks::{is_within, spiral, WorldChunks};
+use crate::world::loader::ChunkLoader;
+use crate::world::player::{ChunkView, PlayerConnection, PlayerPosition};
+
+/// How chunks are streamed to the players of a world
+#[derive(Resource, Debug, Copy, Clone)]
+pub struct ChunkStreaming {
+    /// The maximum view distance, in chunks
+    pub view_distance: u8,
+    /// The maximum number of chunks sent to a player each tick
+    pub chunks_per_tick: usize,
+}
+
+/// Sends the chunks around each player that they do not have yet, nearest
+/// first, and unloads the ones that left their view distance. In worlds
+/// with a [ChunkLoader] or a [WorldGenerator], columns that are not loaded
+/// yet are requested and sent once they arrive.
+pub fn stream_chunks(
+    settings: Res<ChunkStreaming>,
+    mut chunks: ResMut<WorldChunks>,
+    mut loader: Option<ResMut<ChunkLoader>>,
+    mut generator: Option<ResMut<WorldGenerator>>,
+    mut players: Query<(&PlayerConnection, &PlayerPosition, &mut ChunkView)>,
+) {
+    for (connection, position, mut view) in players.iter_mut() {
+        let center = position.chunk();
+        let radius = view.view_distance(settings.view_distance) as i32;
+
+        if view.center() != Some(center) {
+            connection.send_packet(&SetCenterChunk {
+                chunk_x: VarInt(center.x()),
+                chunk_z: VarInt(center.z()),
+            });
+            view.set_center(center);
+        }
+        // The view distance may also have shrunk
+        for pos in view.unload_where(|pos| is_within(center, *pos, radius)) {
+            connection.send_packet(&UnloadChunk { chunk_x: pos.x(), chunk_z: pos.z() });
+        }
+
+        let missing: Vec<_> = spiral(center, radius).into_iter()
+            .filter(|pos| !view.is_loaded(pos))
+            .filter(|pos| loader.as_ref().map_or(true, |loader| !loader.is_pending(pos)))
+            .filter(|pos| generator.as_ref().map_or(true, |generator| !generator.is_pending(pos)))
+            .take(settings.chunks_per_tick)
+            .collect();
+        for pos in missing {
+            if chunks.get(&pos).is_none() {
+                if let Some(loader) = loader.as_mut() {
+                    if loader.is_failed(&pos) {
+                        // Sent empty, but never stored so it is not saved over
+                        connection.send_packet(&ChunkPacket::from_column(&chunks.empty_column(pos)));
+                        view.mark_loaded(pos);
+                    } else {
+                        loader.request(pos);
+                    }
+                    continue;
+                }
+                if let Some(generator) = generator.as_mut() {
+                    generator.request(chunks.empty_column(pos));
+                    continue;
+                }
+            }
+            connection.send_packet(&ChunkPacket::from_column(chunks.get_or_create(pos)));
+            view.mark_loaded(pos);
+        }
+    }
+}
+
+/// Drops the columns outside of the view distance of every player of the
+/// world, so the columns loaded follow the players. Columns that changed
+/// since they were saved are first saved by the [ChunkLoader], and dropped
+/// on a later tick once written. The changed columns of worlds that never
+/// save, read-only ones and those without a folder, are kept.
+pub fn unload_chunks(
+    settings: Res<ChunkStreaming>,
+    mut chunks: ResMut<WorldChunks>,
+    loader: Option<ResMut<ChunkLoader>>,
+    players: Query<(&PlayerPosition, &ChunkView)>,
+) {
+    let views: Vec<_> = players.iter()
+        .map(|(position, view)| (position.chunk(), view.view_distance(settings.view_distance) as i32))
+        .collect();
+    let unused: Vec<ChunkPos> = chunks.columns()
+        .map(|column| ChunkPos::new(column.x(), column.z()))
+        .filter(|pos| !views.iter().any(|(center, radius)| is_within(*center, *pos, *radius)))
+        .collect();
+    if unused.is_empty() {
+        return;
+    }
+    let unloaded = match loader {
+        Some(mut loader) => loader.unload(unused.iter().filter_map(|pos| chunks.get_shared(pos)).collect()),
+        None => unused.into_iter()
+            .filter(|pos| chunks.get(pos).map_or(false, |column| column.changes() == 0))
+            .collect(),
+    };
+    for pos in unloaded {
+        chunks.unload(&pos);
+    }
+}
+
+/// Sends the light of the columns it changed in to the players who have
+/// them loaded
+pub fn send_light_updates(mut chunks: ResMut<WorldChunks>, players: Query<(&PlayerConnection, &ChunkView)>) {
+    for pos in chunks.take_light_changed() {
+        let Some(column) = chunks.get(&pos) else {
+            continue;
+        };
+        let mut packet = None;
+        for (connection, _) in players.iter().filter(|(_, view)| view.is_loaded(&pos)) {
+            connection.send_packet(packet.get_or_insert_with(|| UpdateLight::from_column(column)));
+        }
+    }
+}
@@ -1,12 +1,90 @@
-use std::path::Path;
+use bevy::prelude::Resource;
+use sha2::{Digest, Sha256};
+
+use protocol::fields::key::Key;
 use protocol::fields::position::Position;
-// use crate::dimension::dimension::DimensionType;
-
-// pub struct World {
-//     pub name: String,
-//     pub path: Box<Path>,
-//     pub dimension_type: DimensionType,
-//     pub spawn_location: Position,
-//     pub entities: Vec<i32>,
-//     pub players: Vec<i32>
-// }
\ No newline at end of file
+
+use crate::dimension::dimension::DimensionType;
+use crate::world::player::PlayerPosition;
+
+/// Parses a seed like vanilla's `level-seed`: numbers are used as they are,
+/// other text is hashed, and an empty seed is random
+pub fn parse_seed(seed: &str) -> i64 {
+    let seed = seed.trim();
+    if seed.is_empty() {
+        return rand::random();
+    }
+    seed.parse().unwrap_or_else(|_| java_string_hash(seed) as i64)
+}
+
+/// Java's `String.hashCode`, over UTF-16 code units
+fn java_string_hash(string: &str) -> i32 {
+    string.encode_utf16().fold(0i32, |hash, unit| hash.wrapping_mul(31).wrapping_add(unit as i32))
+}
+
+/// The name, dimension type, seed and spawn of a world
+#[derive(Resource, Debug, Clone)]
+pub struct WorldInfo {
+    name: Key,
+    dimension_type_name: Key,
+    dimension_type: DimensionType,
+    seed: i64,
+    spawn: PlayerPosition,
+}
+
+impl WorldInfo {
+    pub fn new(
+        name: Key,
+        dimension_type_name: Key,
+        dimension_type: DimensionType,
+        seed: i64,
+        spawn: PlayerPosition,
+    ) -> Self {
+        Self { name, dimension_type_name, dimension_type, seed, spawn }
+    }
+
+    /// The dimension name clients know the world by
+    pub fn name(&self) -> &Key {
+        &self.name
+    }
+
+    /// The key of the dimension type in the registry codec
+    pub fn dimension_type_name(&self) -> &Key {
+        &self.dimension_type_name
+    }
+
+    pub fn dimension_type(&self) -> &DimensionType {
+        &self.dimension_type
+    }
+
+    /// The seed the world is generated from
+    pub fn seed(&self) -> i64 {
+        self.seed
+    }
+
+    /// The seed as sent to clients, which they use for biome noise. Like
+    /// vanilla, it is the first 8 bytes of the SHA-256 of the seed, so the
+    /// seed cannot be recovered from it.
+    pub fn hashed_seed(&self) -> i64 {
+        let hash = Sha256::digest(self.seed.to_le_bytes());
+        i64::from_le_bytes(hash[..8].try_into().expect("SHA-256 hashes are 32 bytes"))
+    }
+
+    /// Where players appear when they join or enter the world
+    pub fn spawn(&self) -> PlayerPosition {
+        self.spawn
+    }
+
+    pub fn set_spawn(&mut self, spawn: PlayerPosition) {
+        self.spawn = spawn;
+    }
+
+    /// The block of the spawn, as sent in Set Default Spawn Position
+    pub fn spawn_block(&self) -> Position {
+        Position {
+            x: self.spawn.x.floor() as i32,
+            y: self.spawn.y.floor() as i16,
+            z: self.spawn.z.floor() as i32,
+        }
+    }
+}
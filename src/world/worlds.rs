@@ -1,37 +1,719 @@
-use bevy::prelude::{Schedule, World};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{Context, ensure};
+use bevy::prelude::{Entity, IntoSystemConfigs, Schedule, World};
+use bevy::ecs::world::EntityMut;
 use slab::Slab;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use protocol::fields::key::Key;
+use protocol::fields::numeric::VarInt;
+
+use crate::generation::{ChunkGenerator, receive_generated, WorldGenerator};
+use crate::packets::play::{Respawn, SetDefaultSpawnPosition, SynchronizePlayerPosition};
+use crate::region::palette::PaletteKind;
+use crate::world::chunks::WorldChunks;
+use crate::world::player::{ChunkView, KnownDimensionTypes, PendingTeleport, PlayerConnection, PlayerPosition, Session};
+use crate::world::streaming::{ChunkStreaming, send_light_updates, stream_chunks, unload_chunks};
+use crate::world::anvil::AnvilWorld;
+use crate::world::loader::{ChunkLoader, receive_chunks, save_world};
+use crate::world::world::WorldInfo;
+
+/// The world players join. It is created first, so it always has this key.
+pub const DEFAULT_WORLD: usize = 0;
+
+/// A change to the players of the worlds, sent by their connections. The
+/// worlds are only locked by the tick, which applies the events sent since
+/// the previous one, so connections never wait for a tick.
+pub enum PlayerEvent {
+    /// The player finished logging in and joins the default world
+    Join(PlayerConnection),
+    /// The connection closed
+    Leave(Session),
+    /// The client sent the view distance it wants
+    ViewDistance(Session, u8),
+    /// The client moved its player
+    Move(Session, PlayerPosition),
+    /// The client reached the position it was teleported to
+    ConfirmTeleport(Session, i32),
+}
 
 pub struct Worlds {
     worlds: Slab<World>,
+    // The key of each world by name
+    names: HashMap<Key, usize>,
     scheduler: Schedule,
+    streaming: ChunkStreaming,
+    // The kind of biome containers, which follows the biome registry
+    biomes: PaletteKind,
+    // The world and entity of each player's session
+    players: HashMap<Session, (usize, Entity)>,
+    // The id of the next Synchronize Player Position sent
+    next_teleport_id: i32,
 }
 
 impl Worlds {
-    pub fn new() -> Self {
+    /// Creates an empty set of worlds. The first world created is the
+    /// default world.
+    pub fn new(streaming: ChunkStreaming, biomes: PaletteKind) -> Self {
         let mut scheduler = Schedule::new();
-        scheduler.add_system(test_system);
+        scheduler.add_systems((receive_chunks, receive_generated, stream_chunks, unload_chunks, send_light_updates).chain());
 
-        let mut worlds = Self {
+        Self {
             worlds: Slab::new(),
-            scheduler
+            names: HashMap::new(),
+            scheduler,
+            streaming,
+            biomes,
+            players: HashMap::new(),
+            next_teleport_id: 0,
+        }
+    }
+
+    /// Creates an empty world, returning its key. The dimension type must
+    /// be registered in the codec sent to players and within the bounds the
+    /// client enforces, and the name must not be taken.
+    pub fn new_world(&mut self, info: WorldInfo) -> anyhow::Result<usize> {
+        ensure!(!self.names.contains_key(info.name()), "a world is already named {}", info.name());
+        info.dimension_type().validate()
+            .with_context(|| format!("invalid dimension type for the world {}", info.name()))?;
+        let mut world = World::new();
+        world.insert_resource(WorldChunks::new(info.dimension_type(), self.biomes));
+        world.insert_resource(self.streaming);
+        let name = info.name().clone();
+        world.insert_resource(info);
+        let key = self.worlds.insert(world);
+        self.names.insert(name, key);
+        Ok(key)
+    }
+
+    /// Creates a world whose columns are loaded from a dimension of the
+    /// Anvil world. Chunks that were never saved are created empty. The
+    /// level data is kept in the overworld. Changes to read-only worlds are
+    /// never saved.
+    pub fn load_world(
+        &mut self,
+        info: WorldInfo,
+        anvil: &AnvilWorld,
+        dimension: &Key,
+        runtime: Handle,
+        biome_ids: HashMap<String, i32>,
+        read_only: bool,
+    ) -> anyhow::Result<usize> {
+        let regions = anvil.regions(dimension, read_only)?;
+        let loader = ChunkLoader::new(
+            regions,
+            runtime,
+            biome_ids,
+            info.dimension_type().min_y,
+            info.dimension_type().height,
+            read_only,
+        );
+        let key = self.new_world(info)?;
+        let world = &mut self.worlds[key];
+        world.insert_resource(loader);
+        if *dimension == Key::minecraft("overworld") {
+            world.insert_resource(anvil.level().clone());
+        }
+        Ok(key)
+    }
+
+    /// Creates an instance of a world: a copy that starts with the columns
+    /// of the template and never saves its changes. Columns are shared until
+    /// either world changes them, so creating an instance copies no chunk
+    /// data. Instances of worlds loaded from a folder read the chunks their
+    /// template has not loaded from the same region files.
+    ///
+    /// Instances are closed like other worlds, with [Worlds::remove_world].
+    pub fn new_instance(&mut self, template: usize, name: Key) -> anyhow::Result<usize> {
+        let world = self.worlds.get(template)
+            .with_context(|| format!("no world has the key {}", template))?;
+        let template_info = world.resource::<WorldInfo>();
+        let info = WorldInfo::new(
+            name,
+            template_info.dimension_type_name().clone(),
+            template_info.dimension_type().clone(),
+            template_info.seed(),
+            template_info.spawn(),
+        );
+        let chunks = world.resource::<WorldChunks>().share();
+        let loader = world.get_resource::<ChunkLoader>().map(ChunkLoader::instance);
+        let generator = world.get_resource::<WorldGenerator>().map(WorldGenerator::share);
+        let key = self.new_world(info)?;
+        let world = &mut self.worlds[key];
+        world.insert_resource(chunks);
+        if let Some(loader) = loader {
+            world.insert_resource(loader);
+        }
+        if let Some(generator) = generator {
+            world.insert_resource(generator);
+        }
+        Ok(key)
+    }
+
+    /// Sets the generator filling the columns of the world that were never
+    /// saved. Returns `false` if the world does not exist.
+    pub fn set_generator(&mut self, key: usize, generator: Arc<dyn ChunkGenerator>, runtime: Handle) -> bool {
+        let Some(world) = self.worlds.get_mut(key) else {
+            return false;
         };
+        world.insert_resource(WorldGenerator::new(generator, runtime));
+        true
+    }
 
-        worlds.init();
-        worlds
+    /// Removes a world without players, after starting a save of its
+    /// changes. Returns the world and, for worlds loaded from a folder that
+    /// are not read-only, the handle of the save, which keeps running after
+    /// the world is dropped. Returns `None` if the world does not exist,
+    /// players are still in it, or it is the default world, which players
+    /// join.
+    pub fn remove_world(&mut self, key: usize) -> Option<(World, Option<JoinHandle<usize>>)> {
+        if key == DEFAULT_WORLD || !self.worlds.contains(key) || self.players.values().any(|(world, _)| *world == key) {
+            return None;
+        }
+        let mut world = self.worlds.remove(key);
+        self.names.retain(|_, world| *world != key);
+        let save = save_world(&mut world);
+        Some((world, save))
     }
 
-    pub fn init(&mut self) {
-        self.new_world();
+    /// The key of the world with the given name
+    pub fn by_name(&self, name: &Key) -> Option<usize> {
+        self.names.get(name).copied()
     }
 
-    pub fn new_world(&mut self) -> usize {
-        let world = World::new();
-        let key = self.worlds.insert(world);
-        self.scheduler.run(self.worlds.get_mut(key).unwrap());
-        key
+    /// The names of every world, as listed in the Login (play) packet
+    pub fn names(&self) -> impl Iterator<Item=&Key> {
+        self.names.keys()
+    }
+
+    pub fn info(&self, key: usize) -> Option<&WorldInfo> {
+        self.worlds.get(key)?.get_resource::<WorldInfo>()
+    }
+
+    pub fn get(&self, key: usize) -> Option<&World> {
+        self.worlds.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut World> {
+        self.worlds.get_mut(key)
+    }
+
+    /// Resizes the biome containers of every world after the biome registry.
+    /// Fails if players are online and the containers would change size, as
+    /// their clients would read the chunks sent afterwards with the number
+    /// of bits of the codec they joined with.
+    pub fn set_biome_kind(&mut self, biomes: PaletteKind) -> anyhow::Result<()> {
+        if biomes == self.biomes {
+            return Ok(());
+        }
+        ensure!(self.players.is_empty(),
+            "the biome containers cannot be resized while {} players are online", self.players.len());
+        self.biomes = biomes;
+        for (_, world) in self.worlds.iter_mut() {
+            world.resource_mut::<WorldChunks>().set_biome_kind(biomes);
+        }
+        Ok(())
+    }
+
+    /// Starts saving the changes of every world loaded from a folder. The
+    /// handles resolve to the number of columns each world saved.
+    pub fn save_all(&mut self) -> Vec<JoinHandle<usize>> {
+        self.worlds.iter_mut()
+            .filter_map(|(_, world)| save_world(world))
+            .collect()
+    }
+
+    /// Runs the systems of every world once
+    pub fn tick(&mut self) {
+        for (_, world) in self.worlds.iter_mut() {
+            self.scheduler.run(world);
+        }
+    }
+
+    /// Spawns the player's entity at the spawn of the world, and sends
+    /// them their position. The client must have just been sent the Login
+    /// (play) packet, with a codec holding `dimension_types`. Returns `None`
+    /// if the world does not exist.
+    pub fn add_player(
+        &mut self,
+        key: usize,
+        connection: PlayerConnection,
+        dimension_types: KnownDimensionTypes,
+    ) -> Option<Entity> {
+        let session = connection.session();
+        let teleport_id = self.next_teleport_id();
+        let world = self.worlds.get_mut(key)?;
+        let info = world.resource::<WorldInfo>();
+        send_spawn(&connection, info, teleport_id);
+        let spawn = info.spawn();
+        let entity = world.spawn((connection, spawn, ChunkView::default(), dimension_types, PendingTeleport(teleport_id))).id();
+        self.players.insert(session, (key, entity));
+        Some(entity)
+    }
+
+    /// Despawns the entity of the player's session
+    pub fn remove_player(&mut self, session: &Session) {
+        let Some((key, entity)) = self.players.remove(session) else {
+            return;
+        };
+        if let Some(world) = self.worlds.get_mut(key) {
+            world.despawn(entity);
+        }
+    }
+
+    /// Moves the session of an online player to the spawn of another world,
+    /// sending the Respawn packet so the client drops the chunks of the
+    /// previous one. Returns the player's new entity. Fails if the session is
+    /// not online, or the world does not exist or has a dimension type that
+    /// was not in the codec the client joined with.
+    pub fn transfer_player(&mut self, session: &Session, key: usize, game_mode: u8) -> anyhow::Result<Entity> {
+        let (old_key, old_entity) = *self.players.get(session)
+            .with_context(|| format!("the player {} is not online from {}", session.id, session.address))?;
+        if old_key == key {
+            return Ok(old_entity);
+        }
+        let info = self.worlds.get(key)
+            .with_context(|| format!("no world has the key {}", key))?
+            .resource::<WorldInfo>().clone();
+        let teleport_id = self.next_teleport_id();
+
+        let old_world = &mut self.worlds[old_key];
+        let mut old_player = old_world.entity_mut(old_entity);
+        let knows_type = old_player.get::<KnownDimensionTypes>()
+            .map_or(false, |known| known.0.contains(info.dimension_type_name()));
+        ensure!(knows_type, "the client of {} does not know the dimension type {} of the world {}",
+            session.id, info.dimension_type_name(), info.name());
+        let connection = old_player.take::<PlayerConnection>()
+            .context("the player entity has no connection")?;
+        let mut view = old_player.take::<ChunkView>().unwrap_or_default();
+        let known = old_player.take::<KnownDimensionTypes>().unwrap_or_default();
+        old_world.despawn(old_entity);
+        view.clear();
+
+        connection.send_packet(&Respawn {
+            dimension_type: info.dimension_type_name().clone(),
+            dimension_name: info.name().clone(),
+            hashed_seed: info.hashed_seed(),
+            game_mode,
+            previous_game_mode: -1,
+            is_debug: false,
+            is_flat: false,
+            data_kept: 0,
+            has_death_location: false,
+            death_dimension_name: None,
+            death_location: None,
+        });
+        send_spawn(&connection, &info, teleport_id);
+
+        let entity = self.worlds[key].spawn((connection, info.spawn(), view, known, PendingTeleport(teleport_id))).id();
+        self.players.insert(*session, (key, entity));
+        Ok(entity)
+    }
+
+    /// Sets the view distance the player asked for
+    pub fn set_view_distance(&mut self, session: &Session, view_distance: u8) {
+        let Some(mut player) = self.player(session) else {
+            return;
+        };
+        if let Some(mut view) = player.get_mut::<ChunkView>() {
+            view.set_client_view_distance(view_distance);
+        }
+    }
+
+    /// Moves the player's entity, so chunks are streamed around its new
+    /// position. Ignored until the client confirms its last teleport.
+    pub fn move_player(&mut self, session: &Session, position: PlayerPosition) {
+        if let Some(mut player) = self.player(session) {
+            if !player.contains::<PendingTeleport>() {
+                player.insert(position);
+            }
+        }
+    }
+
+    /// Accepts the movements of the player again if `teleport_id` is the
+    /// last teleport they were sent
+    pub fn confirm_teleport(&mut self, session: &Session, teleport_id: i32) {
+        let Some(mut player) = self.player(session) else {
+            return;
+        };
+        if player.get::<PendingTeleport>() == Some(&PendingTeleport(teleport_id)) {
+            player.remove::<PendingTeleport>();
+        }
+    }
+
+    fn next_teleport_id(&mut self) -> i32 {
+        let id = self.next_teleport_id;
+        self.next_teleport_id = id.checked_add(1).unwrap_or(0);
+        id
+    }
+
+    /// The sessions of an online player. There are several if the
+    /// duplicate login policy allows it.
+    pub fn sessions<'a>(&'a self, id: &'a Uuid) -> impl Iterator<Item=&'a Session> {
+        self.players.keys().filter(move |session| session.id == *id)
+    }
+
+    /// The world the session of a player is in
+    pub fn player_world(&self, session: &Session) -> Option<usize> {
+        self.players.get(session).map(|(key, _)| *key)
+    }
+
+    /// The entity of the session of a player
+    pub fn player(&mut self, session: &Session) -> Option<EntityMut> {
+        let (key, entity) = *self.players.get(session)?;
+        self.worlds.get_mut(key)?.get_entity_mut(entity)
     }
 }
 
-fn test_system() {
-    println!("Helli!")
-}
\ No newline at end of file
+/// Sends the spawn of the world and moves the player to it
+fn send_spawn(connection: &PlayerConnection, info: &WorldInfo, teleport_id: i32) {
+    let spawn = info.spawn();
+    connection.send_packet(&SetDefaultSpawnPosition {
+        position: info.spawn_block(),
+        angle: 0.0,
+    });
+    connection.send_packet(&SynchronizePlayerPosition {
+        x: spawn.x,
+        y: spawn.y,
+        z: spawn.z,
+        yaw: 0.0,
+        pitch: 0.0,
+        flags: 0,
+        teleport_id: VarInt(teleport_id),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use bevy::prelude::Entity;
+    use tokio::runtime::Handle;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+    use uuid::Uuid;
+
+    use protocol::Clientbound;
+    use protocol::codec::encode_packet;
+    use protocol::fields::key::Key;
+    use protocol::fields::numeric::VarInt;
+
+    use crate::block::Block;
+    use crate::client::client::ClientMessage;
+    use crate::dimension::dimension::DimensionType;
+    use crate::dimension::registry::RegistryCodec;
+    use crate::packets::play::{Respawn, SetDefaultSpawnPosition, SynchronizePlayerPosition};
+    use crate::region::fixture_world;
+    use crate::region::palette::PaletteKind;
+    use crate::region::region::Region;
+    use crate::region::section::ChunkPos;
+    use crate::world::anvil::AnvilWorld;
+    use crate::world::chunks::WorldChunks;
+    use crate::world::player::{ChunkView, KnownDimensionTypes, PendingTeleport, PlayerConnection, PlayerPosition, Session};
+    use crate::world::streaming::ChunkStreaming;
+    use crate::world::world::WorldInfo;
+    use crate::world::worlds::{DEFAULT_WORLD, Worlds};
+
+    fn worlds() -> Worlds {
+        Worlds::new(ChunkStreaming { view_distance: 2, chunks_per_tick: 4 }, PaletteKind::biomes(64))
+    }
+
+    fn info(name: &str) -> WorldInfo {
+        WorldInfo::new(
+            Key::new("test", name),
+            Key::minecraft("overworld"),
+            DimensionType::new(0, 64),
+            0,
+            PlayerPosition::new(0.5, 1.0, 0.5),
+        )
+    }
+
+    struct TestPlayer {
+        session: Session,
+        receiver: UnboundedReceiver<ClientMessage>,
+    }
+
+    fn connect(worlds: &mut Worlds, key: usize) -> TestPlayer {
+        connect_as(worlds, key, Session::new(Uuid::new_v4(), "127.0.0.1:25565".parse().unwrap()))
+    }
+
+    fn connect_as(worlds: &mut Worlds, key: usize, session: Session) -> TestPlayer {
+        let (messages, receiver) = unbounded_channel();
+        let connection = PlayerConnection::new(session.id, session.address, messages);
+        let dimension_types = KnownDimensionTypes(HashSet::from([Key::minecraft("overworld")]));
+        worlds.add_player(key, connection, dimension_types).unwrap();
+        TestPlayer { session, receiver }
+    }
+
+    // The packets sent to the player since the last call
+    fn received(player: &mut TestPlayer) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        while let Ok(message) = player.receiver.try_recv() {
+            match message {
+                ClientMessage::Packet(data) => packets.push(data),
+                ClientMessage::Disconnect(_) => panic!("the player was disconnected"),
+            }
+        }
+        packets
+    }
+
+    fn packet_ids(packets: &[Vec<u8>]) -> Vec<i32> {
+        packets.iter().map(|data| data[0] as i32).collect()
+    }
+
+    fn synchronize(teleport_id: i32) -> Vec<u8> {
+        encode_packet(&SynchronizePlayerPosition {
+            x: 0.5,
+            y: 1.0,
+            z: 0.5,
+            yaw: 0.0,
+            pitch: 0.0,
+            flags: 0,
+            teleport_id: VarInt(teleport_id),
+        }).unwrap()
+    }
+
+    fn players_in(worlds: &mut Worlds, key: usize) -> Vec<Entity> {
+        let world = worlds.get_mut(key).unwrap();
+        world.query_filtered::<Entity, bevy::prelude::With<PlayerConnection>>().iter(world).collect()
+    }
+
+    #[test]
+    fn test_invalid_dimension_type() {
+        let mut worlds = worlds();
+        let invalid = WorldInfo::new(
+            Key::new("test", "invalid"),
+            Key::minecraft("overworld"),
+            DimensionType::new(0, 0),
+            0,
+            PlayerPosition::new(0.5, 1.0, 0.5),
+        );
+        assert!(worlds.new_world(invalid).is_err());
+        assert_eq!(worlds.by_name(&Key::new("test", "invalid")), None);
+        assert!(worlds.new_world(info("invalid")).is_ok());
+    }
+
+    #[test]
+    fn test_teleport_confirmation() {
+        let mut worlds = worlds();
+        let key = worlds.new_world(info("world")).unwrap();
+        let mut player = connect(&mut worlds, key);
+        let packets = received(&mut player);
+        assert_eq!(packet_ids(&packets), vec![SetDefaultSpawnPosition::id(), SynchronizePlayerPosition::id()]);
+        assert_eq!(packets[1], synchronize(0));
+
+        // Movements before the confirmation are ignored
+        let moved = PlayerPosition::new(20.0, 1.0, 20.0);
+        worlds.move_player(&player.session, moved);
+        worlds.confirm_teleport(&player.session, 1);
+        worlds.move_player(&player.session, moved);
+        assert_eq!(worlds.player(&player.session).unwrap().get::<PlayerPosition>(), Some(&PlayerPosition::new(0.5, 1.0, 0.5)));
+
+        worlds.confirm_teleport(&player.session, 0);
+        worlds.move_player(&player.session, moved);
+        assert_eq!(worlds.player(&player.session).unwrap().get::<PlayerPosition>(), Some(&moved));
+    }
+
+    #[test]
+    fn test_transfer_player() {
+        let mut worlds = worlds();
+        let lobby = worlds.new_world(info("lobby")).unwrap();
+        let arena = worlds.new_world(info("arena")).unwrap();
+        let mut player = connect(&mut worlds, lobby);
+        let other = connect(&mut worlds, lobby);
+        received(&mut player);
+        worlds.player(&player.session).unwrap()
+            .get_mut::<ChunkView>().unwrap()
+            .mark_loaded(PlayerPosition::new(0.5, 1.0, 0.5).chunk());
+
+        let entity = worlds.transfer_player(&player.session, arena, 0).unwrap();
+        assert_eq!(worlds.player_world(&player.session), Some(arena));
+        assert_eq!(worlds.players[&player.session], (arena, entity));
+        assert_eq!(players_in(&mut worlds, lobby), vec![worlds.players[&other.session].1]);
+        assert_eq!(players_in(&mut worlds, arena), vec![entity]);
+        let player_entity = worlds.player(&player.session).unwrap();
+        assert!(player_entity.get::<ChunkView>().unwrap().loaded().is_empty(), "the chunks of the lobby are forgotten");
+        assert!(player_entity.get::<KnownDimensionTypes>().unwrap().0.contains(&Key::minecraft("overworld")));
+        assert_eq!(player_entity.get::<PendingTeleport>(), Some(&PendingTeleport(2)));
+
+        let packets = received(&mut player);
+        assert_eq!(packet_ids(&packets), vec![Respawn::id(), SetDefaultSpawnPosition::id(), SynchronizePlayerPosition::id()]);
+        assert_eq!(packets[2], synchronize(2));
+
+        // Moving to the current world keeps the entity
+        assert_eq!(worlds.transfer_player(&player.session, arena, 0).unwrap(), entity);
+        assert!(received(&mut player).is_empty());
+
+        // Worlds created after the client joined are fine, dimension types
+        // missing from its codec are not
+        let late = worlds.new_world(info("late")).unwrap();
+        let entity = worlds.transfer_player(&player.session, late, 0).unwrap();
+        assert_eq!(packet_ids(&received(&mut player)),
+            vec![Respawn::id(), SetDefaultSpawnPosition::id(), SynchronizePlayerPosition::id()]);
+        let custom = worlds.new_world(WorldInfo::new(
+            Key::new("test", "custom"),
+            Key::new("test", "custom"),
+            DimensionType::new(0, 64),
+            0,
+            PlayerPosition::new(0.5, 1.0, 0.5),
+        )).unwrap();
+        assert!(worlds.transfer_player(&player.session, custom, 0).is_err());
+        assert!(worlds.transfer_player(&player.session, 42, 0).is_err());
+        let offline = Session::new(Uuid::new_v4(), "127.0.0.1:25565".parse().unwrap());
+        assert!(worlds.transfer_player(&offline, lobby, 0).is_err());
+        assert_eq!(worlds.players[&player.session], (late, entity));
+        assert_eq!(players_in(&mut worlds, late), vec![entity]);
+        assert!(received(&mut player).is_empty());
+
+        // Worlds with players cannot be removed
+        worlds.transfer_player(&player.session, arena, 0).unwrap();
+        assert!(worlds.remove_world(arena).is_none());
+        worlds.transfer_player(&player.session, lobby, 0).unwrap();
+        assert_eq!(players_in(&mut worlds, lobby).len(), 2);
+        assert!(players_in(&mut worlds, arena).is_empty());
+        assert!(worlds.remove_world(arena).is_some());
+        assert_eq!(worlds.by_name(&Key::new("test", "arena")), None);
+        worlds.remove_player(&other.session);
+        assert_eq!(players_in(&mut worlds, lobby).len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_sessions() {
+        let mut worlds = worlds();
+        let lobby = worlds.new_world(info("lobby")).unwrap();
+        let arena = worlds.new_world(info("arena")).unwrap();
+        let id = Uuid::new_v4();
+        let first = connect_as(&mut worlds, lobby, Session::new(id, "127.0.0.1:25565".parse().unwrap()));
+        let second = connect_as(&mut worlds, lobby, Session::new(id, "127.0.0.1:25566".parse().unwrap()));
+        assert_eq!(players_in(&mut worlds, lobby).len(), 2);
+        assert_eq!(worlds.sessions(&id).count(), 2);
+
+        // Each session has its own entity
+        worlds.transfer_player(&second.session, arena, 0).unwrap();
+        assert_eq!(worlds.player_world(&first.session), Some(lobby));
+        assert_eq!(worlds.player_world(&second.session), Some(arena));
+
+        worlds.remove_player(&first.session);
+        assert!(players_in(&mut worlds, lobby).is_empty());
+        assert_eq!(worlds.sessions(&id).collect::<Vec<_>>(), vec![&second.session]);
+    }
+
+    #[test]
+    fn test_biome_kind_with_players() {
+        let mut worlds = worlds();
+        let key = worlds.new_world(info("world")).unwrap();
+        let mut player = connect(&mut worlds, key);
+        assert!(player.receiver.try_recv().is_ok(), "the spawn is sent");
+
+        // The same number of bits is fine, a resize is not
+        worlds.set_biome_kind(PaletteKind::biomes(63)).unwrap();
+        assert!(worlds.set_biome_kind(PaletteKind::biomes(65)).is_err());
+        assert_eq!(worlds.biomes, PaletteKind::biomes(64));
+
+        worlds.remove_player(&player.session);
+        worlds.set_biome_kind(PaletteKind::biomes(65)).unwrap();
+        assert_eq!(worlds.biomes, PaletteKind::biomes(65));
+    }
+
+    #[test]
+    fn test_instance_copy_on_write() {
+        let mut worlds = worlds();
+        let template = worlds.new_world(info("template")).unwrap();
+        assert_eq!(template, DEFAULT_WORLD);
+        let (changed, untouched) = (ChunkPos::new(0, 0), ChunkPos::new(1, 0));
+        let stone = Block::Stone.default_state();
+        {
+            let mut chunks = worlds.get_mut(template).unwrap().resource_mut::<WorldChunks>();
+            for pos in [changed, untouched] {
+                chunks.get_or_create(pos);
+                chunks.get_mut(&pos).unwrap().set_block(0, 10, 0, stone);
+            }
+        }
+        let instance = worlds.new_instance(template, Key::new("test", "instance")).unwrap();
+        let shared = |worlds: &Worlds, key: usize, pos: ChunkPos| {
+            worlds.get(key).unwrap().resource::<WorldChunks>().get_shared(&pos).unwrap()
+        };
+        assert!(Arc::ptr_eq(&shared(&worlds, template, changed), &shared(&worlds, instance, changed)));
+
+        let dirt = Block::Dirt.default_state();
+        worlds.get_mut(instance).unwrap().resource_mut::<WorldChunks>().get_mut(&changed).unwrap().set_block(0, 10, 0, dirt);
+        let block = |worlds: &Worlds, key: usize, pos: ChunkPos| {
+            worlds.get(key).unwrap().resource::<WorldChunks>().get(&pos).unwrap().get_block(0, 10, 0)
+        };
+        assert_eq!(block(&worlds, instance, changed), dirt);
+        assert_eq!(block(&worlds, template, changed), stone);
+        assert!(Arc::ptr_eq(&shared(&worlds, template, untouched), &shared(&worlds, instance, untouched)));
+
+        // Closing the instance drops its copies, and its share of the others
+        let copy = Arc::downgrade(&shared(&worlds, instance, changed));
+        let (instance, save) = worlds.remove_world(instance).unwrap();
+        assert!(save.is_none());
+        drop(instance);
+        assert!(copy.upgrade().is_none());
+        assert_eq!(Arc::strong_count(&shared(&worlds, template, untouched)), 2);
+        assert_eq!(block(&worlds, template, changed), stone);
+        // The default world is never removed, even without players
+        assert!(worlds.remove_world(template).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remove_saves_world() {
+        let mut worlds = worlds();
+        worlds.new_world(info("default")).unwrap();
+        let directory = fixture_world("remove-saves");
+        let anvil = AnvilWorld::load(&directory).unwrap();
+        let overworld = Key::minecraft("overworld");
+        let info = WorldInfo::new(
+            Key::new("test", "loaded"),
+            overworld.clone(),
+            DimensionType::new(-64, 384),
+            0,
+            PlayerPosition::new(0.5, 64.0, 0.5),
+        );
+        let biome_ids = RegistryCodec::vanilla().biome.ids_by_name();
+        let key = worlds.load_world(info, &anvil, &overworld, Handle::current(), biome_ids, false).unwrap();
+        // In a region the fixture has no file for
+        let pos = ChunkPos::new(40, 8);
+        let stone = Block::Stone.default_state();
+        {
+            let mut chunks = worlds.get_mut(key).unwrap().resource_mut::<WorldChunks>();
+            chunks.get_or_create(pos);
+            chunks.get_mut(&pos).unwrap().set_block(0, 0, 0, stone);
+        }
+        let (world, save) = worlds.remove_world(key).unwrap();
+        drop(world);
+        assert_eq!(save.unwrap().await.unwrap(), 1);
+        let region = Region::open(&directory.join("region").join("r.1.0.mca"), 1, 0).unwrap();
+        assert!(region.get_chunk_data(pos.x(), pos.z()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_unload_out_of_view() {
+        let mut worlds = worlds();
+        let key = worlds.new_world(info("unload")).unwrap();
+        let (far, changed) = (ChunkPos::new(20, 0), ChunkPos::new(0, 20));
+        {
+            let mut chunks = worlds.get_mut(key).unwrap().resource_mut::<WorldChunks>();
+            chunks.get_or_create(far);
+            chunks.get_or_create(changed);
+            chunks.set_block(0, 10, 320, Block::Stone.default_state());
+        }
+        let mut player = connect(&mut worlds, key);
+        worlds.tick();
+        let chunks = worlds.get(key).unwrap().resource::<WorldChunks>();
+        // Only the unchanged column outside of the view distance is dropped
+        assert!(chunks.get(&far).is_none());
+        assert!(chunks.get(&changed).is_some());
+        assert!(chunks.get(&ChunkPos::new(0, 0)).is_some());
+
+        worlds.remove_player(&player.session);
+        received(&mut player);
+        worlds.tick();
+        let chunks = worlds.get(key).unwrap().resource::<WorldChunks>();
+        assert_eq!(chunks.columns().count(), 1);
+    }
+}